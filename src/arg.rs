@@ -77,16 +77,22 @@ pub fn get_type(arg: &str) -> Type {
             }
         }
         // Custom emoji
-        else if arg.starts_with("<:") && arg.len() >= 6 {
-            // The minimum possible length of a custom emoji reference is 6:
-            // <:a:1>
-            // 012345
+        else if (arg.starts_with("<:") && arg.len() >= 6) ||
+                (arg.starts_with("<a:") && arg.len() >= 7) {
+            // The minimum possible length of a custom emoji reference is 6, or 7 if it is
+            // animated:
+            // <:a:1>  <a:a:1>
+            // 012345  0123456
 
             // The emoji ID, if there is a valid one, will begin after the
             // second colon and end at the closing angle bracket
 
             // The second colon, if present, will be somewhere at or after &arg[3]
-            let maybe_arg = &arg[3..];
+            // (or &arg[4] if the emoji is animated)
+            // The name's first character may be more than one byte long, in which case the
+            // search can't start after it; such an argument is Type::Text
+            let name_start = if arg.starts_with("<a:") { 3 } else { 2 };
+            let maybe_arg = arg.get((name_start + 1)..).unwrap_or("");
             match maybe_arg.find(":") {
                 Some(pos) => {
                     // Attempt to parse the string that
@@ -191,6 +197,8 @@ mod tests {
     fn custom_emoji() {
         test!("<:a:1>" => EmojiId(1));
         test!("<:abc:123>" => EmojiId(123));
+        test!("<a:a:1>" => EmojiId(1));
+        test!("<a:abc:123>" => EmojiId(123));
     }
 
     #[test]
@@ -201,6 +209,11 @@ mod tests {
         test!("::1" => Text);
         test!("::1." => Text);
         test!(":a:1." => Text);
+        test!("<a::1>" => Text);
+        test!("<a:abc:>" => Text);
+        test!("<b:abc:1>" => Text);
+        test!("<:éé>" => Text);
+        test!("<a:éé>" => Text);
     }
 
     #[test]
@@ -0,0 +1,352 @@
+extern crate discord;
+
+use std::collections::HashSet;
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+use postgres::params::ConnectParams;
+use bot_utility::{extract_preceding_arg, extract_prefixed_command, MessageRecipient};
+use arg;
+use settings::Setting;
+use emojistats::{count_emoji, BackfillProgress, Database, Emoji, ANONYMOUS_USER_ID};
+
+use self::discord::{Discord, GetMessages};
+use self::discord::model::{ChannelId, Message, MessageType, UserId};
+
+// Discord allows at most 100 messages to be retrieved at once
+const MESSAGES_PER_PAGE: u64 = 100;
+// Report progress after this many pages of a channel's history have been processed
+const PAGES_PER_PROGRESS_REPORT: u32 = 50;
+// Discord's own rate limiting is respected by discord-rs; this pause keeps the bot from using up
+// the whole rate limit so that it can still respond to commands while backfilling
+const PAUSE_BETWEEN_PAGES: u64 = 1000;
+const MAX_ATTEMPTS_PER_PAGE: u32 = 5;
+// Snowflake IDs contain the number of milliseconds since the first second of 2015
+const DISCORD_EPOCH: i64 = 1420070400000;
+
+pub struct Backfiller {
+    bot_token: String,
+    bot_user_id: UserId,
+    db_params: ConnectParams,
+    // The bot's set, so that emoji added while backfilling are counted too
+    emoji: Arc<RwLock<HashSet<Emoji>>>,
+    // The bot's command prefix, used on servers that haven't set their own
+    prefix: Option<String>,
+    running: Arc<AtomicBool>,
+}
+
+impl Backfiller {
+    // Starts a thread that works through every pending backfill in the database, unless one is
+    // already running; a running thread picks up newly added backfills before it finishes
+    pub fn start(bot_token: &str,
+                 bot_user_id: UserId,
+                 db_params: ConnectParams,
+                 emoji: Arc<RwLock<HashSet<Emoji>>>,
+                 prefix: Option<String>,
+                 running: &Arc<AtomicBool>) {
+        if running.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        let backfiller = Backfiller {
+            bot_token: bot_token.to_string(),
+            bot_user_id,
+            db_params,
+            emoji,
+            prefix,
+            running: running.clone(),
+        };
+
+        let spawn_result = thread::Builder::new()
+            .name("backfill".to_string())
+            .spawn(move || backfiller.run());
+
+        if let Err(reason) = spawn_result {
+            warn!("Unable to start backfill thread: {}", reason);
+            running.store(false, Ordering::SeqCst);
+        }
+    }
+
+    fn run(self) {
+        let discord = match Discord::from_bot_token(&self.bot_token) {
+            Ok(discord) => discord,
+            Err(reason) => {
+                warn!("Backfill unable to authenticate with Discord: {}", reason);
+                self.running.store(false, Ordering::SeqCst);
+                return;
+            }
+        };
+
+        let db = match Database::new(self.db_params.clone()) {
+            Ok(db) => db,
+            Err(reason) => {
+                warn!("Backfill unable to connect to database: {}", reason);
+                self.running.store(false, Ordering::SeqCst);
+                return;
+            }
+        };
+
+        loop {
+            let backfills = match db.get_pending_backfills() {
+                Ok(backfills) => backfills,
+                Err(reason) => {
+                    warn!("Unable to retrieve pending backfills: {}", reason);
+                    Vec::new()
+                }
+            };
+
+            if backfills.is_empty() {
+                self.running.store(false, Ordering::SeqCst);
+
+                // A backfill may have been added after checking but before the flag was cleared
+                match db.get_pending_backfills() {
+                    Ok(ref backfills) if !backfills.is_empty() &&
+                                         !self.running.swap(true, Ordering::SeqCst) => continue,
+                    _ => break,
+                }
+            }
+
+            for backfill in backfills {
+                if !self.backfill_channel(&discord, &db, backfill) {
+                    // Leave the remaining backfills to be resumed later
+                    self.running.store(false, Ordering::SeqCst);
+                    return;
+                }
+            }
+        }
+
+        info!("Finished backfilling");
+    }
+
+    // Pages backwards through a channel's history, recording every message that hasn't been
+    // recorded yet
+    // Returns false if the backfill had to be abandoned because of repeated errors
+    fn backfill_channel(&self,
+                        discord: &Discord,
+                        db: &Database,
+                        mut backfill: BackfillProgress)
+                        -> bool {
+        info!("Backfilling channel {} from message {:?} ({} messages processed so far)",
+              backfill.channel_id,
+              backfill.before_message_id,
+              backfill.messages_processed);
+
+        let mut num_pages = 0;
+
+        loop {
+            let what = match backfill.before_message_id {
+                Some(message_id) => GetMessages::Before(message_id),
+                None => GetMessages::MostRecent,
+            };
+
+            let messages = match self.get_messages(discord, &backfill, what) {
+                Some(messages) => messages,
+                None => {
+                    backfill
+                        .report_channel_id
+                        .send_message(discord,
+                                      &format!("Backfilling <#{}> stopped after {} messages \
+                                                because of an error; it will resume when I \
+                                                restart. :warning:",
+                                               backfill.channel_id,
+                                               backfill.messages_processed));
+                    return false;
+                }
+            };
+
+            // Messages are returned newest first
+            let mut completed = messages.len() < MESSAGES_PER_PAGE as usize;
+            // The server's prefix can change while a backfill runs, so it's looked up for each page
+            let prefix = self.command_prefix(db, &backfill.channel_id);
+
+            for message in &messages {
+                if let Some(since) = backfill.since {
+                    if message_timestamp(message) < since {
+                        completed = true;
+                        break;
+                    }
+                }
+
+                self.record_message(db, message, prefix.as_ref().map(String::as_str));
+                backfill.before_message_id = Some(message.id);
+                backfill.messages_processed += 1;
+            }
+
+            if let Err(reason) = db.update_backfill(&backfill, completed) {
+                warn!("Unable to record backfill progress for channel {}: {}",
+                      backfill.channel_id,
+                      reason);
+            }
+
+            if completed {
+                info!("Finished backfilling channel {} ({} messages)",
+                      backfill.channel_id,
+                      backfill.messages_processed);
+                backfill
+                    .report_channel_id
+                    .send_message(discord,
+                                  &format!("Finished backfilling <#{}> ({} message{}). \
+                                            :white_check_mark:",
+                                           backfill.channel_id,
+                                           backfill.messages_processed,
+                                           if backfill.messages_processed == 1 {
+                                               ""
+                                           } else {
+                                               "s"
+                                           }));
+                return true;
+            }
+
+            num_pages += 1;
+
+            if num_pages % PAGES_PER_PROGRESS_REPORT == 0 {
+                backfill
+                    .report_channel_id
+                    .send_message(discord,
+                                  &format!("Still backfilling <#{}>: {} messages so far. \
+                                            :hourglass_flowing_sand:",
+                                           backfill.channel_id,
+                                           backfill.messages_processed));
+            }
+
+            thread::sleep(Duration::from_millis(PAUSE_BETWEEN_PAGES));
+        }
+    }
+
+    // Retrieves a page of messages, retrying with an increasing delay if there is an error
+    fn get_messages(&self,
+                    discord: &Discord,
+                    backfill: &BackfillProgress,
+                    what: GetMessages)
+                    -> Option<Vec<Message>> {
+        let before_message_id = match what {
+            GetMessages::Before(message_id) => Some(message_id),
+            _ => None,
+        };
+
+        for attempt in 1..(MAX_ATTEMPTS_PER_PAGE + 1) {
+            let what = match before_message_id {
+                Some(message_id) => GetMessages::Before(message_id),
+                None => GetMessages::MostRecent,
+            };
+
+            match discord.get_messages(backfill.channel_id, what, Some(MESSAGES_PER_PAGE)) {
+                Ok(messages) => return Some(messages),
+                Err(reason) => {
+                    warn!("Unable to retrieve messages in channel {} (attempt {} of {}): {}",
+                          backfill.channel_id,
+                          attempt,
+                          MAX_ATTEMPTS_PER_PAGE,
+                          reason);
+                    thread::sleep(Duration::from_millis(PAUSE_BETWEEN_PAGES *
+                                                        (1 << attempt)));
+                }
+            }
+        }
+
+        None
+    }
+
+    // The server's prefix if it has set one, or the bot's prefix otherwise
+    fn command_prefix(&self, db: &Database, channel_id: &ChannelId) -> Option<String> {
+        match db.get_channel_server_setting(channel_id, Setting::Prefix.name()) {
+            Ok(Some(ref prefix)) if prefix.is_empty() => None,
+            Ok(Some(prefix)) => Some(prefix),
+            Ok(None) => self.prefix.clone(),
+            Err(reason) => {
+                warn!("Unable to retrieve the command prefix for channel {}: {}",
+                      channel_id,
+                      reason);
+                self.prefix.clone()
+            }
+        }
+    }
+
+    // Records a message the same way as a newly-posted message
+    fn record_message(&self, db: &Database, message: &Message, prefix: Option<&str>) {
+        // Ignore all messages except regular, text-based messages from users
+        if message.kind != MessageType::Regular || message.author.bot {
+            return;
+        }
+
+        // Ignore commands
+        if let (Some(arg::Type::UserId(user_id)), _) = extract_preceding_arg(&message.content) {
+            if user_id == self.bot_user_id {
+                return;
+            }
+        }
+
+        if prefix
+               .and_then(|prefix| extract_prefixed_command(&message.content, prefix))
+               .is_some() {
+            return;
+        }
+
+        match db.message_exists(&message.id) {
+            Ok(false) => {}
+            Ok(true) => return,
+            Err(reason) => {
+                warn!("Unable to determine whether message {} exists in database: {}",
+                      message.id,
+                      reason);
+                return;
+            }
+        }
+
+        // Exclusions can change while a backfill runs, so they're checked for each message
+        match db.is_excluded_from_tracking(&message.channel_id, &message.author.id) {
+            Ok(false) => {}
+            Ok(true) => return,
+            Err(reason) => {
+                warn!("Unable to determine whether message {} is excluded from tracking: {}",
+                      message.id,
+                      reason);
+                return;
+            }
+        }
+
+        // Opted-out users' emoji are recorded anonymously; users can opt out while a backfill
+        // runs, so this is checked for each message too
+        let opted_out = match db.is_user_opted_out(&message.author.id) {
+            Ok(opted_out) => opted_out,
+            Err(reason) => {
+                warn!("Unable to determine whether the author of message {} opted out: {}",
+                      message.id,
+                      reason);
+                return;
+            }
+        };
+
+        let user_id = if opted_out {
+            ANONYMOUS_USER_ID
+        } else {
+            if let Err(reason) = db.add_user(&message.author) {
+                warn!("Error adding user {}#{} ({}) to database: {}",
+                      message.author.name,
+                      message.author.discriminator,
+                      message.author.id,
+                      reason);
+            }
+
+            message.author.id
+        };
+
+        let emoji_counts = count_emoji(&self.emoji.read().unwrap(), &message.content);
+
+        if let Err(reason) = db.record_message(&message.id,
+                                               &message.channel_id,
+                                               &user_id,
+                                               &message.timestamp,
+                                               &emoji_counts) {
+            warn!("Error recording statistics for message {}: {}",
+                  message.id,
+                  reason);
+        }
+    }
+}
+
+// Returns the time a message was posted in seconds since the Unix epoch, based on its ID
+fn message_timestamp(message: &Message) -> i64 {
+    ((message.id.0 >> 22) as i64 + DISCORD_EPOCH) / 1000
+}
@@ -4,17 +4,28 @@ extern crate chrono_humanize;
 extern crate rand;
 
 use arg;
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fs::{File, OpenOptions};
 use std::io::Write;
-use bot_utility::{extract_preceding_arg, remove_non_command_characters, extract_first_word,
-                  BasicServerInfo, MessageRecipient};
-use emojistats::{CustomEmoji, Database, Emoji};
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use backfill::Backfiller;
+use bot_utility::{extract_preceding_arg, extract_prefixed_command, remove_non_command_characters,
+                  extract_first_word, create_sparkline, BasicServerInfo, MessageRecipient};
+use emojistats::{count_emoji, find_emoji, find_emoji_by_name, ActivityStats, Bucket, Comparison,
+                 CustomEmoji, Database, Emoji, Exclusion, NameMatch, Page, Period, Scope,
+                 UnicodeEmoji, UsageFilter, UsageSource, ANONYMOUS_USER_ID};
+use options::{parse_bucket, parse_period, parse_stats_options, StatsOptions};
+use permissions::{AdminSource, BotAdmins, PermissionLevel};
+use settings::{ServerSettings, Setting};
 
 use self::chrono_humanize::HumanTime;
+use self::discord::builders::EmbedBuilder;
 use self::discord::model::{Event, Channel, ChannelId, ChannelType, Game, GameType, LiveServer,
-                           Message, MessageType, OnlineStatus, PossibleServer, PrivateChannel,
-                           PublicChannel, Server, ServerId, ServerInfo, User, UserId};
+                           Message, MessageId, MessageType, OnlineStatus, Permissions,
+                           PossibleServer, PrivateChannel, PublicChannel, Reaction, ReactionEmoji,
+                           Role, RoleId, Server, ServerId, ServerInfo, User, UserId};
+use self::discord::model::permissions::{ADMINISTRATOR, MANAGE_SERVER};
 use self::rand::{Rng, thread_rng};
 use self::time::{Timespec, get_time};
 
@@ -22,11 +33,26 @@ const RESPONSE_STATS_ERR: &str = "\
         Sorry! An error occurred while retrieving the statistics. :(";
 const RESPONSE_USE_COMMAND_IN_PUBLIC_CHANNEL: &str = "\
         Please use this command in a public channel. :shrug:";
+const RESPONSE_COMMAND_DISABLED: &str = "\
+        That command has been disabled on this server. :no_entry_sign:";
+const RESPONSE_SAVE_ERR: &str = "\
+        Sorry! An error occurred while saving that. :(";
+const MAX_TREND_POINTS: usize = 60;
+const NUM_TREND_TOP_EMOJI: usize = 3;
+const DEFAULT_PAGE_SIZE: u32 = 5;
+// Embed fields are limited to 1024 characters
+const MAX_PAGE_SIZE: u32 = 15;
+// Leaderboards older than this many can no longer be paged through
+const MAX_LEADERBOARDS: usize = 100;
+const PREVIOUS_PAGE_EMOJI: &str = "\u{2B05}\u{FE0F}";
+const NEXT_PAGE_EMOJI: &str = "\u{27A1}\u{FE0F}";
 
 #[derive(Debug)]
 pub enum BotError {
     FailedToAuthenticate = 101,
     FailedToConnect = 102,
+    FailedToLoadOptOuts = 103,
+    FailedToLoadExclusions = 104,
 }
 
 #[derive(Debug)]
@@ -42,13 +68,35 @@ enum BotLoopDisposition {
     Restart,
 }
 
+// A leaderboard the bot has posted, kept so that it can be paged through with reactions
+#[derive(Debug, Clone)]
+struct Leaderboard {
+    kind: LeaderboardKind,
+    title: String,
+    description: Option<String>,
+    filter: UsageFilter,
+    page: Page,
+}
+
+#[derive(Debug, Clone)]
+enum LeaderboardKind {
+    TopEmoji(Scope),
+    TopGlobalCustomEmoji,
+    TopEmojiAndUsers(Scope),
+    LeastUsedEmoji(ServerId, bool), // Whether to show only unused emoji
+    PairedEmoji(Emoji, Option<ServerId>),
+    TopEmojiPairs(Option<ServerId>),
+    TopServers(Option<ServerId>), // The server to highlight, if any
+    ServerVsGlobal(ServerId),
+}
+
 pub struct Bot {
     discord: discord::Discord,
     discord_conn: discord::Connection,
+    bot_token: String,
     online_since: Timespec,
     bot_user_id: UserId,
-    bot_admin_password: String,
-    bot_admins: HashMap<UserId, User>,
+    bot_admins: BotAdmins,
     about_text: Option<String>,
     help_text: Option<String>,
     feedback_file: Option<File>,
@@ -56,12 +104,30 @@ pub struct Bot {
     public_text_channels: HashMap<ChannelId, PublicChannel>,
     private_channels: HashMap<ChannelId, PrivateChannel>,
     unknown_public_text_channels: HashSet<ChannelId>,
+    // Users whose emoji are counted but not attributed to them
+    opted_out_users: HashSet<UserId>,
+    server_settings: HashMap<ServerId, ServerSettings>,
+    // Channels and users whose emoji aren't recorded, or aren't shown in statistics
+    excluded_channels: HashMap<ChannelId, Exclusion>,
+    excluded_users: HashMap<(ServerId, UserId), Exclusion>,
+    // Used to check whether members can manage their servers
+    role_permissions: HashMap<RoleId, Permissions>,
     db: Database,
-    emoji: HashSet<Emoji>,
+    // Shared with the backfill thread, which counts emoji in past messages
+    emoji: Arc<RwLock<HashSet<Emoji>>>,
+    unicode_emoji_names: HashMap<Emoji, String>,
+    // Servers from the ready event that haven't been received yet
+    awaited_servers: HashSet<ServerId>,
+    backfill_running: Arc<AtomicBool>,
+    page_size: u32,
+    // Commands can begin with this instead of a mention of the bot, unless a server has its own
+    prefix: Option<String>,
+    leaderboards: HashMap<MessageId, Leaderboard>,
+    leaderboard_order: VecDeque<MessageId>, // Oldest first
 }
 
 impl Bot {
-    pub fn new(bot_token: &str, bot_admin_password: &str, db: Database) -> Result<Bot, BotError> {
+    pub fn new(bot_token: &str, db: Database) -> Result<Bot, BotError> {
         let discord = match discord::Discord::from_bot_token(bot_token) {
             Ok(discord) => discord,
             Err(reason) => {
@@ -80,28 +146,119 @@ impl Bot {
         };
 
         let bot_user_id = ready_event.user.id;
-        let bot_admin_password = bot_admin_password.to_string();
 
-        let mut bot_admins = HashMap::new();
+        // Only servers with pending backfills need to be waited for before resuming them
+        let backfill_servers = match db.get_pending_backfill_servers() {
+            Ok(server_ids) => server_ids.into_iter().collect::<HashSet<_>>(),
+            Err(reason) => {
+                warn!("Unable to retrieve servers with pending backfills: {}", reason);
+                HashSet::new()
+            }
+        };
+
+        let awaited_servers = ready_event
+            .servers
+            .iter()
+            .map(|server| match *server {
+                     PossibleServer::Online(ref server) => server.id,
+                     PossibleServer::Offline(server_id) => server_id,
+                 })
+            .filter(|server_id| backfill_servers.contains(server_id))
+            .collect();
+
+        // Without the opt-outs, opted-out users would be attributed their emoji again
+        let opted_out_users = match db.get_opted_out_users() {
+            Ok(user_ids) => user_ids.into_iter().collect(),
+            Err(reason) => {
+                error!("Failed to load opted-out users from database: {}", reason);
+                return Err(BotError::FailedToLoadOptOuts);
+            }
+        };
+
+        // Without the exclusions, emoji in excluded channels and by excluded users would be
+        // recorded
+        let excluded_channels = match db.get_excluded_channels() {
+            Ok(channels) => {
+                channels
+                    .into_iter()
+                    .map(|(channel_id, _, exclusion)| (channel_id, exclusion))
+                    .collect()
+            }
+            Err(reason) => {
+                error!("Failed to load excluded channels from database: {}", reason);
+                return Err(BotError::FailedToLoadExclusions);
+            }
+        };
+
+        let excluded_users = match db.get_excluded_users() {
+            Ok(users) => {
+                users
+                    .into_iter()
+                    .map(|(server_id, user_id, exclusion)| ((server_id, user_id), exclusion))
+                    .collect()
+            }
+            Err(reason) => {
+                error!("Failed to load excluded users from database: {}", reason);
+                return Err(BotError::FailedToLoadExclusions);
+            }
+        };
+
+        let mut server_settings = HashMap::new();
+
+        match db.get_server_settings() {
+            Ok(settings) => {
+                for (server_id, name, value) in settings {
+                    match Setting::from_name(&name) {
+                        Some(setting) => {
+                            server_settings
+                                .entry(server_id)
+                                .or_insert_with(ServerSettings::default)
+                                .load(setting, value);
+                        }
+                        None => {
+                            warn!("Ignoring unknown setting {} on server {}", name, server_id);
+                        }
+                    }
+                }
+            }
+            Err(reason) => {
+                warn!("Unable to load server settings from database; using defaults: {}",
+                      reason);
+            }
+        }
+
+        let mut bot_admins = BotAdmins::default();
         match discord.get_application_info() {
             Ok(application_info) => {
                 debug!("Application owner = {}#{} ({})",
                        application_info.owner.name,
                        application_info.owner.discriminator,
                        application_info.owner.id);
-                bot_admins.insert(application_info.owner.id, application_info.owner);
+                bot_admins.add(application_info.owner.id, AdminSource::Owner);
             }
             Err(_) => {
                 debug!("No application info available");
             }
         }
 
+        // Users who authenticated with the password remain administrators after a restart
+        match db.get_bot_admins() {
+            Ok(user_ids) => {
+                for user_id in user_ids {
+                    bot_admins.add(user_id, AdminSource::Password);
+                }
+            }
+            Err(reason) => {
+                warn!("Unable to load bot administrators from database: {}", reason);
+            }
+        }
+
         Ok(Bot {
                discord,
                discord_conn,
+               bot_token: bot_token.to_string(),
                online_since: get_time(),
                bot_user_id,
-               bot_admin_password,
                bot_admins,
                about_text: None,
                help_text: None,
@@ -110,11 +267,56 @@ impl Bot {
                public_text_channels: HashMap::new(),
                private_channels: HashMap::new(),
                unknown_public_text_channels: HashSet::new(),
+               opted_out_users,
+               server_settings,
+               excluded_channels,
+               excluded_users,
+               role_permissions: HashMap::new(),
                db,
-               emoji: HashSet::new(),
+               emoji: Arc::new(RwLock::new(HashSet::new())),
+               unicode_emoji_names: HashMap::new(),
+               awaited_servers,
+               backfill_running: Arc::new(AtomicBool::new(false)),
+               page_size: DEFAULT_PAGE_SIZE,
+               prefix: None,
+               leaderboards: HashMap::new(),
+               leaderboard_order: VecDeque::new(),
            })
     }
 
+    // The number of entries on each page of a leaderboard
+    pub fn set_page_size(&mut self, page_size: u32) {
+        self.page_size = if page_size == 0 {
+            DEFAULT_PAGE_SIZE
+        } else if page_size > MAX_PAGE_SIZE {
+            MAX_PAGE_SIZE
+        } else {
+            page_size
+        };
+    }
+
+    // An empty prefix leaves commands to be invoked by mentioning the bot
+    pub fn set_prefix<S>(&mut self, prefix: S)
+        where S: Into<String>
+    {
+        let prefix = prefix.into().trim().to_lowercase();
+
+        self.prefix = if prefix.is_empty() { None } else { Some(prefix) };
+    }
+
+    // Adds a bot administrator listed in the config file
+    pub fn add_bot_admin(&mut self, user_id: UserId) {
+        self.bot_admins.add(user_id, AdminSource::Config);
+    }
+
+    // Enables authenticating with the bot administration password
+    // Returns false if the hash isn't valid
+    pub fn set_bot_admin_password_hash<S>(&mut self, password_hash: S) -> bool
+        where S: Into<String>
+    {
+        self.bot_admins.set_password_hash(password_hash)
+    }
+
     pub fn set_about_text<S>(&mut self, text: S)
         where S: Into<String>
     {
@@ -145,18 +347,47 @@ impl Bot {
         }
     }
 
-    pub fn add_unicode_emoji(&mut self, emoji: String) {
-        let emoji = Emoji::Unicode(emoji);
+    // Returns false if the emoji was already added
+    // Extra emoji listed in the config have no details, so they don't replace those already
+    // recorded for them
+    pub fn add_unicode_emoji(&mut self, unicode_emoji: UnicodeEmoji) -> bool {
+        let emoji = Emoji::Unicode(unicode_emoji.emoji.clone());
 
-        match self.db.add_emoji(&emoji, None) {
-            Ok(_) => {}
+        if self.emoji.read().unwrap().contains(&emoji) {
+            return false;
+        }
+
+        match self.db.add_emoji(&emoji) {
+            Ok(_) => {
+                if unicode_emoji.name.is_some() {
+                    if let Err(reason) = self.db.set_unicode_emoji_details(&unicode_emoji) {
+                        warn!("Error recording details of Unicode emoji <{:?}> in database: {}",
+                              emoji,
+                              reason);
+                    }
+                }
+            }
             Err(reason) => {
                 warn!("Error adding Unicode emoji <{:?}> to database: {}",
                       emoji,
                       reason);
             }
         }
-        self.emoji.insert(emoji);
+
+        if let Some(name) = unicode_emoji.name {
+            self.unicode_emoji_names.insert(emoji.clone(), name);
+        }
+
+        self.emoji.write().unwrap().insert(emoji);
+        true
+    }
+
+    // Resumes interrupted backfills once no more servers from the ready event are expected,
+    // whether they arrived, turned out to be unavailable or were removed
+    fn stop_awaiting_server(&mut self, server_id: &ServerId) {
+        if self.awaited_servers.remove(server_id) && self.awaited_servers.is_empty() {
+            self.start_backfill();
+        }
     }
 
     pub fn run(mut self) -> BotDisposition {
@@ -164,6 +395,11 @@ impl Bot {
                               env!("CARGO_PKG_NAME"),
                               env!("CARGO_PKG_VERSION")));
 
+        // Backfills interrupted by a restart are resumed once every server's emoji are known
+        if self.awaited_servers.is_empty() {
+            self.start_backfill();
+        }
+
         let mut bot_loop_disposition = BotLoopDisposition::Continue;
 
         // Main loop
@@ -172,13 +408,28 @@ impl Bot {
                 Ok(Event::MessageCreate(message)) => {
                     bot_loop_disposition = self.process_message(message);
                 }
+                Ok(Event::MessageUpdate { id, content: Some(content), .. }) => {
+                    self.update_emoji_usage(&id, &content);
+                }
+                Ok(Event::MessageDelete { message_id, .. }) => {
+                    self.remove_emoji_usage(&message_id);
+                }
+                Ok(Event::MessageDeleteBulk { ids, .. }) => {
+                    for message_id in ids {
+                        self.remove_emoji_usage(&message_id);
+                    }
+                }
                 Ok(Event::ServerCreate(server)) => {
                     match server {
                         PossibleServer::Online(server) => {
                             self.add_emoji_list(server.id, server.emojis.clone());
+                            self.stop_awaiting_server(&server.id);
                             self.add_live_server(server);
                         }
-                        PossibleServer::Offline(_) => {}
+                        // An unavailable server's emoji won't be known until it comes back
+                        PossibleServer::Offline(server_id) => {
+                            self.stop_awaiting_server(&server_id);
+                        }
                     }
                 }
                 Ok(Event::ServerUpdate(server)) => {
@@ -187,9 +438,11 @@ impl Bot {
                 Ok(Event::ServerDelete(possible_server)) => {
                     match possible_server {
                         PossibleServer::Online(server) => {
+                            self.stop_awaiting_server(&server.id);
                             self.remove_server_id(&server.id);
                         }
                         PossibleServer::Offline(server_id) => {
+                            self.stop_awaiting_server(&server_id);
                             self.remove_server_id(&server_id);
                         }
                     }
@@ -206,12 +459,40 @@ impl Bot {
                 Ok(Event::ChannelRecipientAdd(_, user)) => {
                     self.add_user(&user);
                 }
-                Ok(Event::ServerMemberUpdate { user, .. }) => {
+                Ok(Event::ServerMemberAdd(server_id, member)) => {
+                    self.add_user(&member.user);
+                    self.set_member_roles(&server_id, &member.user.id, &member.roles);
+                }
+                Ok(Event::ServerMemberUpdate { server_id, roles, user, .. }) => {
                     self.add_user(&user);
+                    self.set_member_roles(&server_id, &user.id, &roles);
+                }
+                Ok(Event::ServerMemberRemove(server_id, user)) => {
+                    self.remove_member(&server_id, &user.id);
+                }
+                Ok(Event::ServerRoleCreate(server_id, role)) |
+                Ok(Event::ServerRoleUpdate(server_id, role)) => {
+                    self.add_role(&server_id, &role);
+                }
+                Ok(Event::ServerRoleDelete(_, role_id)) => {
+                    self.remove_role(&role_id);
                 }
                 Ok(Event::ServerEmojisUpdate(server_id, emoji_list)) => {
                     self.add_emoji_list(server_id, emoji_list);
                 }
+                Ok(Event::ReactionAdd(reaction)) => {
+                    if !self.turn_leaderboard_page(&reaction) {
+                        self.log_reaction(&reaction);
+                    }
+                }
+                Ok(Event::ReactionRemove(reaction)) => {
+                    if !self.turn_leaderboard_page(&reaction) {
+                        self.remove_reaction(&reaction);
+                    }
+                }
+                Ok(Event::ReactionRemoveAll { message_id, .. }) => {
+                    self.remove_all_reactions(&message_id);
+                }
                 _ => {}
             }
 
@@ -249,8 +530,12 @@ impl Bot {
             self.add_channel(Channel::Public(channel.clone()));
         }
 
+        self.set_server_roles(&server.id, &server.roles);
+        self.add_server_name(&server.id, &server.name);
+
         for member in &server.members {
             self.add_user(&member.user);
+            self.set_member_roles(&server.id, &member.user.id, &member.roles);
         }
 
         self.servers
@@ -268,8 +553,22 @@ impl Bot {
             }
         }
 
-        self.servers
-            .insert(server.id, BasicServerInfo::from(server));
+        self.add_server_name(&server.id, &server.name);
+
+        // ServerInfo doesn't include the owner, so keep the one from the LiveServer if known
+        let owner_id = self.servers
+            .get(&server.id)
+            .and_then(|server| server.owner_id);
+        let mut server_info = BasicServerInfo::from(server);
+
+        server_info.owner_id = owner_id;
+        self.servers.insert(server_info.id, server_info);
+    }
+
+    fn add_server_name(&self, server_id: &ServerId, name: &str) {
+        if let Err(reason) = self.db.add_server(server_id, name) {
+            warn!("Error adding server {} to database: {}", server_id, reason);
+        }
     }
 
     fn remove_server_id(&mut self, server_id: &ServerId) {
@@ -285,6 +584,8 @@ impl Bot {
 
     fn update_server(&mut self, new_server_info: Server) {
         self.add_emoji_list(new_server_info.id, new_server_info.emojis);
+        self.set_server_roles(&new_server_info.id, &new_server_info.roles);
+        self.add_server_name(&new_server_info.id, &new_server_info.name);
 
         if let Some(server) = self.servers.get_mut(&new_server_info.id) {
             debug!("Updating server info: {} -> {} ({})",
@@ -294,6 +595,7 @@ impl Bot {
 
             server.name = new_server_info.name;
             server.icon = new_server_info.icon;
+            server.owner_id = Some(new_server_info.owner_id);
             return;
         }
     }
@@ -375,6 +677,10 @@ impl Bot {
     }
 
     fn add_user(&mut self, user: &User) {
+        if self.opted_out_users.contains(&user.id) {
+            return;
+        }
+
         if let Err(reason) = self.db.add_user(user) {
             warn!("Error adding user {}#{} ({}) to database: {}",
                   user.name,
@@ -384,6 +690,60 @@ impl Bot {
         }
     }
 
+    fn set_server_roles(&mut self, server_id: &ServerId, roles: &[Role]) {
+        for role in roles {
+            self.role_permissions.insert(role.id, role.permissions);
+        }
+
+        if let Err(reason) = self.db.set_server_roles(server_id, roles) {
+            warn!("Error setting roles of server {} in database: {}",
+                  server_id,
+                  reason);
+        }
+    }
+
+    fn add_role(&mut self, server_id: &ServerId, role: &Role) {
+        self.role_permissions.insert(role.id, role.permissions);
+
+        if let Err(reason) = self.db.add_role(server_id, role) {
+            warn!("Error adding role @{} ({}) on server {} to database: {}",
+                  role.name,
+                  role.id,
+                  server_id,
+                  reason);
+        }
+    }
+
+    fn remove_role(&mut self, role_id: &RoleId) {
+        self.role_permissions.remove(role_id);
+
+        if let Err(reason) = self.db.remove_role(role_id) {
+            warn!("Error removing role {} from database: {}", role_id, reason);
+        }
+    }
+
+    fn set_member_roles(&mut self, server_id: &ServerId, user_id: &UserId, role_ids: &[RoleId]) {
+        if self.opted_out_users.contains(user_id) {
+            return;
+        }
+
+        if let Err(reason) = self.db.set_member_roles(server_id, user_id, role_ids) {
+            warn!("Error setting roles of user {} on server {} in database: {}",
+                  user_id,
+                  server_id,
+                  reason);
+        }
+    }
+
+    fn remove_member(&mut self, server_id: &ServerId, user_id: &UserId) {
+        if let Err(reason) = self.db.remove_member(server_id, user_id) {
+            warn!("Error removing roles of user {} on server {} from database: {}",
+                  user_id,
+                  server_id,
+                  reason);
+        }
+    }
+
     fn resolve_unknown_channel(&mut self, channel_id: &ChannelId) {
         // Get an updated list of servers
         if let Ok(servers) = self.discord.get_servers() {
@@ -409,11 +769,41 @@ impl Bot {
         }
     }
 
+    // Adds the server's custom emoji; any of its emoji that aren't in the list are no longer
+    // available
     fn add_emoji_list(&mut self, server_id: ServerId, emoji_list: Vec<discord::model::Emoji>) {
+        let emoji_ids = emoji_list.iter().map(|emoji| emoji.id).collect::<Vec<_>>();
+
+        if let Err(reason) = self.db.set_available_server_emoji(&server_id, &emoji_ids) {
+            warn!("Error updating available custom emoji on server ({}): {}",
+                  server_id,
+                  reason);
+        }
+
+        self.emoji
+            .write()
+            .unwrap()
+            .retain(|emoji| match *emoji {
+                        Emoji::Custom(ref emoji) => {
+                            emoji.server_id != Some(server_id) || emoji_ids.contains(&emoji.id)
+                        }
+                        Emoji::Unicode(_) => true,
+                    });
+
         for emoji in emoji_list {
-            let custom_emoji = Emoji::Custom(CustomEmoji::new(server_id, emoji.id, emoji.name));
+            let animated = match self.db.is_custom_emoji_animated(&emoji.id) {
+                Ok(animated) => animated,
+                Err(reason) => {
+                    warn!("Unable to determine whether custom emoji {} is animated: {}",
+                          emoji.id,
+                          reason);
+                    false
+                }
+            };
+            let custom_emoji =
+                Emoji::Custom(CustomEmoji::new(Some(server_id), emoji.id, emoji.name, animated));
 
-            match self.db.add_emoji(&custom_emoji, Some(&server_id)) {
+            match self.db.add_emoji(&custom_emoji) {
                 Ok(_) => {
                     debug!("Added custom emoji on server ({}): <{:?}>",
                            server_id,
@@ -426,7 +816,7 @@ impl Bot {
                 }
             }
 
-            self.emoji.insert(custom_emoji);
+            self.emoji.write().unwrap().insert(custom_emoji);
         }
     }
 
@@ -481,6 +871,12 @@ impl Bot {
             }
         }
 
+        // If the message begins with the command prefix, the rest of the message is a command
+        if let Some(command) = self.command_prefix(&message.channel_id)
+               .and_then(|prefix| extract_prefixed_command(&message.content, prefix)) {
+            return self.process_command(&message, command);
+        }
+
         // If the message was sent in a private channel to the bot, the entire message is a command
         if self.private_channels.contains_key(&message.channel_id) {
             return self.process_command(&message, &message.content);
@@ -491,7 +887,11 @@ impl Bot {
         BotLoopDisposition::Continue
     }
 
-    fn log_emoji_usage(&self, message: &Message) {
+    fn log_emoji_usage(&mut self, message: &Message) {
+        if self.is_excluded_from_tracking(&message.channel_id, &message.author.id) {
+            return;
+        }
+
         match self.db.message_exists(&message.id) {
             Ok(message_exists) => {
                 if message_exists {
@@ -506,80 +906,202 @@ impl Bot {
             }
         }
 
-        let mut total_emoji_used = 0;
+        let emoji_counts = count_emoji(&self.emoji.read().unwrap(), &message.content);
+        self.update_animated_emoji(&emoji_counts);
 
-        for emoji in &self.emoji {
-            let pattern = match *emoji {
-                Emoji::Custom(ref custom_emoji) => &custom_emoji.pattern,
-                Emoji::Unicode(ref emoji) => emoji,
-            };
+        match self.db
+                  .record_message(&message.id,
+                                  &message.channel_id,
+                                  &self.attributed_user_id(&message.author.id),
+                                  &message.timestamp,
+                                  &emoji_counts) {
+            Ok(_) => {}
+            Err(reason) => {
+                warn!("Error recording statistics for message {}: {}",
+                      message.id,
+                      reason);
+            }
+        }
+    }
 
-            let count = message.content.matches(pattern).count() as i32;
+    // Emoji used by opted-out users are recorded anonymously
+    fn attributed_user_id(&self, user_id: &UserId) -> UserId {
+        if self.opted_out_users.contains(user_id) {
+            ANONYMOUS_USER_ID
+        } else {
+            *user_id
+        }
+    }
 
-            if count > 0 {
-                total_emoji_used += count;
+    fn update_emoji_usage(&mut self, message_id: &MessageId, content: &str) {
+        let emoji_counts = count_emoji(&self.emoji.read().unwrap(), content);
+        self.update_animated_emoji(&emoji_counts);
 
-                match self.db
-                          .record_emoji_usage(&message.channel_id,
-                                              &message.author.id,
-                                              emoji,
-                                              count) {
-                    Ok(_) => {}
-                    Err(reason) => warn!("Error recording emoji usage: {}", reason),
+        match self.db.update_message(message_id, &emoji_counts) {
+            Ok(true) => {
+                debug!("Updated statistics for edited message {}", message_id);
+            }
+            Ok(false) => {}
+            Err(reason) => {
+                warn!("Error updating statistics for message {}: {}",
+                      message_id,
+                      reason);
+            }
+        }
+    }
+
+    // Discord doesn't say which server emoji are animated, so the tracked emoji are updated when
+    // a custom emoji is first used in its animated form
+    fn update_animated_emoji(&mut self, emoji_counts: &[(Emoji, i32)]) {
+        for &(ref emoji, _) in emoji_counts {
+            if let Emoji::Custom(ref custom_emoji) = *emoji {
+                if custom_emoji.animated {
+                    self.emoji.write().unwrap().replace(emoji.clone());
                 }
             }
         }
+    }
 
-        match self.db
-                  .record_message_stats(&message.id,
-                                        &message.channel_id,
-                                        &message.author.id,
-                                        total_emoji_used) {
-            Ok(_) => {}
-            Err(reason) => {
-                warn!("Error recording statistics for message {}: {}",
-                      message.id,
+    fn remove_emoji_usage(&self, message_id: &MessageId) {
+        if let Err(reason) = self.db.remove_message(message_id) {
+            warn!("Error removing statistics for message {}: {}",
+                  message_id,
+                  reason);
+        }
+    }
+
+    // Returns the tracked emoji corresponding to a reaction, if there is one
+    fn get_reaction_emoji(&self, reaction_emoji: &ReactionEmoji) -> Option<Emoji> {
+        let emoji = match *reaction_emoji {
+            ReactionEmoji::Unicode(ref emoji) => Emoji::Unicode(emoji.clone()),
+            ReactionEmoji::Custom { ref name, id } => {
+                Emoji::Custom(CustomEmoji::new(None, id, name.clone(), false))
+            }
+        };
+
+        find_emoji(&self.emoji.read().unwrap(), emoji.pattern())
+    }
+
+    fn log_reaction(&self, reaction: &Reaction) {
+        // Only reactions in public text channels are tracked, and the bot's own are ignored
+        if !self.public_text_channels.contains_key(&reaction.channel_id) ||
+           reaction.user_id == self.bot_user_id ||
+           self.is_excluded_from_tracking(&reaction.channel_id, &reaction.user_id) {
+            return;
+        }
+
+        if let Some(emoji) = self.get_reaction_emoji(&reaction.emoji) {
+            if let Err(reason) = self.db
+                   .record_reaction(&reaction.message_id,
+                                    &reaction.channel_id,
+                                    &self.attributed_user_id(&reaction.user_id),
+                                    &emoji) {
+                warn!("Error recording reaction on message {}: {}",
+                      reaction.message_id,
+                      reason);
+            }
+        }
+    }
+
+    // The reaction is removed for the user themselves rather than whoever it is attributed to
+    // now, since they may have opted out or in since reacting
+    fn remove_reaction(&self, reaction: &Reaction) {
+        // Untracked reactions must not be taken from the anonymous ones
+        if !self.public_text_channels.contains_key(&reaction.channel_id) ||
+           reaction.user_id == self.bot_user_id {
+            return;
+        }
+
+        if let Some(emoji) = self.get_reaction_emoji(&reaction.emoji) {
+            if let Err(reason) = self.db
+                   .remove_reaction(&reaction.message_id, &reaction.user_id, &emoji) {
+                warn!("Error removing reaction on message {}: {}",
+                      reaction.message_id,
                       reason);
             }
         }
     }
 
+    fn remove_all_reactions(&self, message_id: &MessageId) {
+        if let Err(reason) = self.db.remove_all_reactions(message_id) {
+            warn!("Error removing all reactions on message {}: {}",
+                  message_id,
+                  reason);
+        }
+    }
+
     fn process_command(&mut self, message: &Message, command: &str) -> BotLoopDisposition {
         let command = remove_non_command_characters(command);
 
         match extract_first_word(command) {
             (command, args) if !command.is_empty() => {
+                if self.is_command_disabled(message, command) {
+                    self.send_response(message, RESPONSE_COMMAND_DISABLED);
+                    return BotLoopDisposition::Continue;
+                }
+
                 // Commands are case-insensitive
                 match command.to_lowercase().as_ref() {
                     "auth" => self.attempt_auth(message, args),
+                    "deauth" => self.deauth(message),
                     "botinfo" => self.bot_info(message),
                     "quit" => self.quit(message),
+                    "backfill" => self.backfill(message, args),
                     "restart" => self.restart(message),
                     "feedback" => self.feedback(message, args),
+                    "optout" => self.set_opted_out(message, true),
+                    "optin" => self.set_opted_out(message, false),
+                    "forget" => self.forget(message, args),
+                    "settings" => self.settings(message, args),
+                    "exclude" => self.exclude(message, args, true),
+                    "include" => self.exclude(message, args, false),
+                    "exclusions" => self.exclusions(message),
                     "about" | "info" => self.about(message),
                     "help" | "commands" => self.help(message),
-                    "g" | "global" => self.stats_global(message),
-                    "s" | "server" => self.stats_server(message),
-                    "c" | "channel" => self.stats_channel(message, None),
-                    "m" | "me" => self.stats_user(message, None),
+                    "g" | "global" => self.stats_global(message, args),
+                    "s" | "server" => self.stats_server(message, args),
+                    "c" | "channel" => self.stats_channel(message, None, args),
+                    "m" | "me" => self.stats_user(message, None, args),
+                    "t" | "trend" => self.trend(message, args),
+                    "r" | "ratio" => self.ratio(message, args),
+                    "vs" | "compare" => self.compare(message, args),
+                    "pairs" => self.stats_pairs(message, args),
+                    "combos" => self.stats_combos(message, args),
+                    "leastused" => self.stats_least_used(message, args, false),
+                    "unused" => self.stats_least_used(message, args, true),
                     _ => {
                         // Something else
+                        // Mentions and emoji can be disabled like the commands they stand for
+                        let disabled_as = match arg::get_type(command) {
+                            arg::Type::UserId(_) => "user",
+                            arg::Type::ChannelId(_) => "channel",
+                            arg::Type::RoleId(_) => "role",
+                            _ => "emoji",
+                        };
+
+                        if self.is_command_disabled(message, disabled_as) {
+                            self.send_response(message, RESPONSE_COMMAND_DISABLED);
+                            return BotLoopDisposition::Continue;
+                        }
+
                         // Did the user begin the message with a #channel or mention a user?
                         match arg::get_type(command) {
                             arg::Type::UserId(user_id) => {
-                                self.stats_user(message, Some(&user_id));
+                                self.stats_user(message, Some(&user_id), args);
                             }
                             arg::Type::ChannelId(channel_id) => {
-                                self.stats_channel(message, Some(&channel_id));
+                                self.stats_channel(message, Some(&channel_id), args);
+                            }
+                            arg::Type::RoleId(role_id) => {
+                                self.stats_role(message, &role_id, args);
                             }
                             _ => {
-                                let mut matches =
-                                    self.emoji.iter().filter(|e| e.pattern() == command);
+                                let emoji = find_emoji(&self.emoji.read().unwrap(), command);
 
-                                if let Some(emoji) = matches.next() {
-                                    self.stats_emoji(message, &emoji);
+                                if let Some(emoji) = emoji {
+                                    self.stats_emoji(message, &emoji, args);
                                 } else {
-                                    self.help(message);
+                                    self.stats_emoji_name(message, command, args);
                                 }
                             }
                         }
@@ -604,21 +1126,42 @@ impl Bot {
         self.send_message(message, &format!("<@{}>: {}", message.author.id, text));
     }
 
+    // Password authentication is a fallback for administrators who aren't the application owner
+    // or listed in the config file; it's only available if a password hash is configured
     fn attempt_auth(&mut self, message: &Message, password_attempt: &str) -> BotLoopDisposition {
-        if self.bot_admins.contains_key(&message.author.id) {
+        if self.is_bot_admin(&message.author.id) {
             self.send_response(message,
                                "You are already authenticated as a bot administrator. :unlock:");
         } else if !self.private_channels.contains_key(&message.channel_id) {
             self.send_response(message,
                                "Please use this command in a private message. :lock:");
+        } else if !self.bot_admins.is_password_set() {
+            self.send_response(message,
+                               "Password authentication isn't enabled for this bot. :lock:");
         } else {
             if password_attempt.is_empty() {
                 self.send_response(message,
                                    "Please enter the bot administration password. :lock:");
-            } else if password_attempt == self.bot_admin_password {
-                self.send_response(message, "Authenticated successfully. :white_check_mark:");
+            } else if self.bot_admins.check_password(password_attempt) {
                 self.bot_admins
-                    .insert(message.author.id, message.author.clone());
+                    .add(message.author.id, AdminSource::Password);
+
+                if let Err(reason) = self.db.set_bot_admin(&message.author.id, true) {
+                    warn!("Error saving bot administrator {}: {}",
+                          message.author.id,
+                          reason);
+                    self.send_response(message,
+                                       "Authenticated successfully, but you'll need to \
+                                        authenticate again after the bot restarts. \
+                                        :white_check_mark:");
+                } else {
+                    self.send_response(message, "Authenticated successfully. :white_check_mark:");
+                }
+
+                info!("{}#{} ({}) authenticated as a bot administrator.",
+                      message.author.name,
+                      message.author.discriminator,
+                      message.author.id);
             } else {
                 self.send_response(message, "Unable to authenticate. :x:");
             }
@@ -627,8 +1170,40 @@ impl Bot {
         BotLoopDisposition::Continue
     }
 
+    // Stops the user being a bot administrator, if they authenticated with the password
+    fn deauth(&mut self, message: &Message) -> BotLoopDisposition {
+        match self.bot_admins.source(&message.author.id) {
+            Some(AdminSource::Password) => {}
+            Some(source) => {
+                self.send_response(message,
+                                   &format!("You're a bot administrator because you're {}, \
+                                             so you can't stop being one here. :lock:",
+                                            source.description()));
+                return BotLoopDisposition::Continue;
+            }
+            None => {
+                self.send_response(message,
+                                   "You aren't authenticated as a bot administrator. :shrug:");
+                return BotLoopDisposition::Continue;
+            }
+        }
+
+        if let Err(reason) = self.db.set_bot_admin(&message.author.id, false) {
+            warn!("Error removing bot administrator {}: {}",
+                  message.author.id,
+                  reason);
+            self.send_response(message, RESPONSE_SAVE_ERR);
+            return BotLoopDisposition::Continue;
+        }
+
+        self.bot_admins.remove(&message.author.id);
+        self.send_response(message, "You're no longer a bot administrator. :lock:");
+
+        BotLoopDisposition::Continue
+    }
+
     fn bot_info(&mut self, message: &Message) -> BotLoopDisposition {
-        if self.bot_admins.contains_key(&message.author.id) {
+        if self.is_bot_admin(&message.author.id) {
             self.check_for_new_servers();
 
             let online_time = HumanTime::from(self.online_since - get_time());
@@ -656,7 +1231,7 @@ impl Bot {
     }
 
     fn quit(&self, message: &Message) -> BotLoopDisposition {
-        if self.bot_admins.contains_key(&message.author.id) {
+        if self.is_bot_admin(&message.author.id) {
             self.send_response(message, "Quitting. :octagonal_sign:");
             info!("Quit command issued by {}.", message.author.name);
             BotLoopDisposition::Quit
@@ -667,7 +1242,7 @@ impl Bot {
     }
 
     fn restart(&self, message: &Message) -> BotLoopDisposition {
-        if self.bot_admins.contains_key(&message.author.id) {
+        if self.is_bot_admin(&message.author.id) {
             self.send_response(message, "Restarting. :repeat:");
             info!("Restart command issued by {}.", message.author.name);
             BotLoopDisposition::Restart
@@ -677,6 +1252,98 @@ impl Bot {
         }
     }
 
+    fn backfill(&self, message: &Message, args: &str) -> BotLoopDisposition {
+        if !self.is_bot_admin(&message.author.id) {
+            self.respond_auth_required(message);
+            return BotLoopDisposition::Continue;
+        }
+
+        let mut channel_ids = Vec::new();
+        let mut since = None;
+
+        for arg in args.split_whitespace() {
+            if arg.to_lowercase() == "server" {
+                let server_id = match self.public_text_channels.get(&message.channel_id) {
+                    Some(channel) => channel.server_id,
+                    None => {
+                        self.send_response(message, RESPONSE_USE_COMMAND_IN_PUBLIC_CHANNEL);
+                        return BotLoopDisposition::Continue;
+                    }
+                };
+
+                channel_ids.extend(self.public_text_channels
+                                       .values()
+                                       .filter(|channel| channel.server_id == server_id)
+                                       .map(|channel| channel.id));
+            } else if let arg::Type::ChannelId(channel_id) = arg::get_type(arg) {
+                if !self.public_text_channels.contains_key(&channel_id) {
+                    self.send_response(message,
+                                       &format!("I'm not able to read <#{}>. :confused:",
+                                                channel_id));
+                    return BotLoopDisposition::Continue;
+                }
+
+                channel_ids.push(channel_id);
+            } else if let Some(period) = parse_period(&arg.to_lowercase()) {
+                since = period.bounds().0;
+            } else {
+                self.send_response(message,
+                                   &format!("I don't understand \"{}\". Usage: `backfill \
+                                             [#channel | server] [since]` :confused:",
+                                            arg));
+                return BotLoopDisposition::Continue;
+            }
+        }
+
+        if channel_ids.is_empty() {
+            if self.public_text_channels.contains_key(&message.channel_id) {
+                channel_ids.push(message.channel_id);
+            } else {
+                self.send_response(message, RESPONSE_USE_COMMAND_IN_PUBLIC_CHANNEL);
+                return BotLoopDisposition::Continue;
+            }
+        }
+
+        for channel_id in &channel_ids {
+            if let Err(reason) = self.db
+                   .add_backfill(channel_id, &message.channel_id, since) {
+                warn!("Error adding backfill for channel {} to database: {}",
+                      channel_id,
+                      reason);
+                self.send_response(message,
+                                   "Sorry! An error occurred while starting the backfill. :(");
+                return BotLoopDisposition::Continue;
+            }
+        }
+
+        info!("Backfill of {} channel{} requested by {}.",
+              channel_ids.len(),
+              if channel_ids.len() == 1 { "" } else { "s" },
+              message.author.name);
+        self.send_response(message,
+                           &format!("Backfilling {} channel{}; I'll report progress here. \
+                                     :hourglass_flowing_sand:",
+                                    channel_ids.len(),
+                                    if channel_ids.len() == 1 { "" } else { "s" }));
+        self.start_backfill();
+
+        BotLoopDisposition::Continue
+    }
+
+    // Works through pending backfills in another thread so that the bot remains responsive
+    fn start_backfill(&self) {
+        if self.backfill_running.load(Ordering::SeqCst) {
+            return;
+        }
+
+        Backfiller::start(&self.bot_token,
+                          self.bot_user_id,
+                          self.db.connect_params(),
+                          self.emoji.clone(),
+                          self.prefix.clone(),
+                          &self.backfill_running);
+    }
+
     fn feedback(&self, message: &Message, feedback: &str) -> BotLoopDisposition {
         self.send_response(message,
                            "Thanks. Your feedback has been logged for review. :smiley:");
@@ -713,26 +1380,25 @@ impl Bot {
                                message.author.discriminator,
                                feedback);
 
-        for (user_id, user) in &self.bot_admins {
+        for user_id in self.bot_admins.user_ids() {
             let mut num_channels_sent_to = 0;
 
             // Look for an existing private channel for each administrator
             for (channel_id, _) in self.private_channels
                     .iter()
-                    .filter(|&(_, c)| c.recipient.id == *user_id) {
+                    .filter(|&(_, c)| c.recipient.id == user_id) {
                 num_channels_sent_to += 1;
                 self.send_message(channel_id, &feedback);
             }
 
             // If there wasn't an existing private channel, create one
             if num_channels_sent_to == 0 {
-                if let Ok(private_channel) = self.discord.create_private_channel(*user_id) {
+                if let Ok(private_channel) = self.discord.create_private_channel(user_id) {
                     self.send_message(&private_channel.id, &feedback);
                 } else {
                     warn!("Unable to create private channel to send feedback to bot administrator \
-                          {}#{}.",
-                          user.name,
-                          user.discriminator);
+                          {}.",
+                          user_id);
                 }
             }
         }
@@ -748,265 +1414,2162 @@ impl Bot {
         BotLoopDisposition::Continue
     }
 
-    fn help(&self, message: &Message) -> BotLoopDisposition {
-        if self.help_text.is_some() {
-            self.send_response(message, self.help_text.as_ref().unwrap());
+    // Stops or resumes attributing the user's emoji to them; opted-out users' emoji still count
+    // towards emoji totals, but not towards any user
+    fn set_opted_out(&mut self, message: &Message, opted_out: bool) -> BotLoopDisposition {
+        let user_id = message.author.id;
+
+        if self.opted_out_users.contains(&user_id) == opted_out {
+            self.send_response(message,
+                               if opted_out {
+                                   "You've already opted out of statistics. :see_no_evil:"
+                               } else {
+                                   "You haven't opted out of statistics. :shrug:"
+                               });
+            return BotLoopDisposition::Continue;
         }
 
+        if let Err(reason) = self.db.set_user_opted_out(&user_id, opted_out) {
+            warn!("Error setting whether user {} is opted out: {}", user_id, reason);
+            self.send_response(message, RESPONSE_SAVE_ERR);
+            return BotLoopDisposition::Continue;
+        }
+
+        if opted_out {
+            self.opted_out_users.insert(user_id);
+            self.send_response(message,
+                               "You've opted out of statistics. From now on, your emoji will \
+                                count towards totals but won't be attributed to you. Your past \
+                                statistics are kept; use `forget me` to remove them. \
+                                :see_no_evil:");
+        } else {
+            self.opted_out_users.remove(&user_id);
+            self.add_user(&message.author);
+            self.send_response(message,
+                               "You've opted back in to statistics. :white_check_mark:");
+        }
+
+        BotLoopDisposition::Continue
+    }
+
+    // Opts the user out and anonymises all of their past statistics
+    fn forget(&mut self, message: &Message, args: &str) -> BotLoopDisposition {
+        if args.trim().to_lowercase() != "me" {
+            self.send_response(message,
+                               "Use `forget me` to opt out of statistics and remove your name \
+                                from all of your past statistics. :see_no_evil:");
+            return BotLoopDisposition::Continue;
+        }
+
+        let user_id = message.author.id;
+
+        let result = self.db
+            .set_user_opted_out(&user_id, true)
+            .and_then(|_| self.db.forget_user(&user_id));
+
+        if let Err(reason) = result {
+            warn!("Error forgetting user {}: {}", user_id, reason);
+            self.send_response(message, "Sorry! An error occurred while forgetting you. :(");
+            return BotLoopDisposition::Continue;
+        }
+
+        self.opted_out_users.insert(user_id);
+        self.excluded_users
+            .retain(|&(_, excluded_user_id), _| excluded_user_id != user_id);
+        self.bot_admins.remove(&user_id);
+        self.send_response(message,
+                           "I've forgotten you. Your past emoji still count towards totals, but \
+                            are no longer attributed to you, and your future emoji won't be \
+                            either. :see_no_evil:");
+
+        BotLoopDisposition::Continue
+    }
+
+    // Shows or changes this server's settings, e.g. "settings", "settings get pagesize",
+    // "settings set pagesize 10" or "settings reset pagesize"
+    // Only members who can manage the server can use this command
+    fn settings(&mut self, message: &Message, args: &str) -> BotLoopDisposition {
+        let server_id = match self.public_text_channels.get(&message.channel_id) {
+            Some(channel) => channel.server_id,
+            None => {
+                self.send_response(message, RESPONSE_USE_COMMAND_IN_PUBLIC_CHANNEL);
+                return BotLoopDisposition::Continue;
+            }
+        };
+
+        if !self.check_server_manager(message, &server_id) {
+            return BotLoopDisposition::Continue;
+        }
+
+        let (action, args) = extract_first_word(args);
+        let (name, value) = extract_first_word(args);
+
+        let setting = match action.to_lowercase().as_ref() {
+            "" | "get" | "list" if name.is_empty() => {
+                self.show_settings(message, &server_id);
+                return BotLoopDisposition::Continue;
+            }
+            "get" | "set" | "reset" => {
+                match Setting::from_name(name) {
+                    Some(setting) => setting,
+                    None => {
+                        let names = Setting::all()
+                            .iter()
+                            .map(|setting| format!("`{}`", setting.name()))
+                            .collect::<Vec<_>>();
+
+                        self.send_response(message,
+                                           &format!("Please choose a setting: {}. :gear:",
+                                                    names.join(", ")));
+                        return BotLoopDisposition::Continue;
+                    }
+                }
+            }
+            _ => {
+                self.send_response(message,
+                                   "Use `settings`, `settings get <setting>`, `settings set \
+                                    <setting> <value>` or `settings reset <setting>`. :gear:");
+                return BotLoopDisposition::Continue;
+            }
+        };
+
+        let mut settings = self.server_settings
+            .get(&server_id)
+            .cloned()
+            .unwrap_or_default();
+
+        let result = match action.to_lowercase().as_ref() {
+            "set" => {
+                match settings.set(setting, value, MAX_PAGE_SIZE) {
+                    Ok(value) => self.db.set_server_setting(&server_id, setting.name(), &value),
+                    Err(problem) => {
+                        self.send_response(message, &format!("{} :gear:", problem));
+                        return BotLoopDisposition::Continue;
+                    }
+                }
+            }
+            "reset" => {
+                settings.reset(setting);
+                self.db.remove_server_setting(&server_id, setting.name())
+            }
+            _ => {
+                self.send_response(message,
+                                   &format!("`{}`: {}",
+                                            setting.name(),
+                                            describe_setting(&settings,
+                                                             setting,
+                                                             self.page_size,
+                                                             self.prefix.as_ref())));
+                return BotLoopDisposition::Continue;
+            }
+        };
+
+        match result {
+            Ok(_) => {
+                self.send_response(message,
+                                   &format!("`{}` is now {}. :white_check_mark:",
+                                            setting.name(),
+                                            describe_setting(&settings,
+                                                             setting,
+                                                             self.page_size,
+                                                             self.prefix.as_ref())));
+                self.server_settings.insert(server_id, settings);
+            }
+            Err(reason) => {
+                warn!("Error saving setting {} on server {}: {}",
+                      setting.name(),
+                      server_id,
+                      reason);
+                self.send_response(message, RESPONSE_SAVE_ERR);
+            }
+        }
+
+        BotLoopDisposition::Continue
+    }
+
+    fn show_settings(&self, message: &Message, server_id: &ServerId) {
+        let default_settings = ServerSettings::default();
+        let settings = self.server_settings
+            .get(server_id)
+            .unwrap_or(&default_settings);
+
+        let _ = self.discord
+            .send_embed(message.channel_id,
+                        &format!("<@{}>", message.author.id),
+                        |e| {
+                            e.title("Settings for this server :gear:")
+                                .description("Change a setting with `settings set <setting> \
+                                              <value>`, or go back to the default with \
+                                              `settings reset <setting>`.")
+                                .fields(|f| {
+                                    Setting::all()
+                                        .iter()
+                                        .fold(f, |f, setting| {
+                                            f.field(setting.name(),
+                                                    &format!("{}\n{}",
+                                                             describe_setting(settings,
+                                                                              *setting,
+                                                                              self.page_size,
+                                                                              self.prefix
+                                                                                  .as_ref()),
+                                                             setting.description()),
+                                                    false)
+                                        })
+                                })
+                        });
+    }
+
+    // Excludes a channel or user on this server from tracking, leaderboards or both, e.g.
+    // "exclude #spam", "exclude @user leaderboards"; or includes them again, e.g. "include #spam"
+    // Only members who can manage the server can use these commands
+    fn exclude(&mut self, message: &Message, args: &str, exclude: bool) -> BotLoopDisposition {
+        let server_id = match self.public_text_channels.get(&message.channel_id) {
+            Some(channel) => channel.server_id,
+            None => {
+                self.send_response(message, RESPONSE_USE_COMMAND_IN_PUBLIC_CHANNEL);
+                return BotLoopDisposition::Continue;
+            }
+        };
+
+        if !self.check_server_manager(message, &server_id) {
+            return BotLoopDisposition::Continue;
+        }
+
+        let (target, args) = extract_first_word(args);
+
+        // Excluding from tracking and leaderboards by default, and including in both
+        let exclusion = match args.trim().to_lowercase().as_ref() {
+            _ if !exclude => Exclusion::default(),
+            "" | "all" | "both" => {
+                Exclusion {
+                    tracking: true,
+                    leaderboards: true,
+                }
+            }
+            "tracking" => {
+                Exclusion {
+                    tracking: true,
+                    leaderboards: false,
+                }
+            }
+            "leaderboards" | "stats" => {
+                Exclusion {
+                    tracking: false,
+                    leaderboards: true,
+                }
+            }
+            _ => {
+                self.send_response(message,
+                                   "Use `exclude #channel` or `exclude @user`, optionally \
+                                    followed by `tracking` or `leaderboards`. :no_entry_sign:");
+                return BotLoopDisposition::Continue;
+            }
+        };
+
+        let (result, target) = match arg::get_type(target) {
+            arg::Type::ChannelId(channel_id) => {
+                match self.public_text_channels.get(&channel_id) {
+                    Some(channel) if channel.server_id == server_id => {}
+                    _ => {
+                        self.send_response(message,
+                                           "Please mention a channel on this server. :shrug:");
+                        return BotLoopDisposition::Continue;
+                    }
+                }
+
+                let result = self.db
+                    .set_channel_exclusion(&channel_id, &server_id, &exclusion);
+
+                if result.is_ok() {
+                    if exclusion == Exclusion::default() {
+                        self.excluded_channels.remove(&channel_id);
+                    } else {
+                        self.excluded_channels.insert(channel_id, exclusion);
+                    }
+                }
+
+                (result, format!("<#{}>", channel_id))
+            }
+            arg::Type::UserId(user_id) => {
+                let result = self.db
+                    .set_user_exclusion(&server_id, &user_id, &exclusion);
+
+                if result.is_ok() {
+                    if exclusion == Exclusion::default() {
+                        self.excluded_users.remove(&(server_id, user_id));
+                    } else {
+                        self.excluded_users.insert((server_id, user_id), exclusion);
+                    }
+                }
+
+                (result, format!("<@{}>", user_id))
+            }
+            _ => {
+                self.send_response(message,
+                                   if exclude {
+                                       "Use `exclude #channel` or `exclude @user`, optionally \
+                                        followed by `tracking` or `leaderboards`. \
+                                        :no_entry_sign:"
+                                   } else {
+                                       "Use `include #channel` or `include @user`. \
+                                        :white_check_mark:"
+                                   });
+                return BotLoopDisposition::Continue;
+            }
+        };
+
+        match result {
+            Ok(_) => {
+                if exclude {
+                    self.send_response(message,
+                                       &format!("{} is now excluded from {}. :no_entry_sign:",
+                                                target,
+                                                exclusion.description()));
+                } else {
+                    self.send_response(message,
+                                       &format!("{} is no longer excluded. :white_check_mark:",
+                                                target));
+                }
+            }
+            Err(reason) => {
+                warn!("Error saving exclusion of {} on server {}: {}",
+                      target,
+                      server_id,
+                      reason);
+                self.send_response(message, RESPONSE_SAVE_ERR);
+            }
+        }
+
+        BotLoopDisposition::Continue
+    }
+
+    // Lists the channels and users excluded on this server
+    fn exclusions(&self, message: &Message) -> BotLoopDisposition {
+        let server_id = match self.public_text_channels.get(&message.channel_id) {
+            Some(channel) => channel.server_id,
+            None => {
+                self.send_response(message, RESPONSE_USE_COMMAND_IN_PUBLIC_CHANNEL);
+                return BotLoopDisposition::Continue;
+            }
+        };
+
+        let mut channels = self.excluded_channels
+            .iter()
+            .filter(|&(channel_id, _)| {
+                        self.public_text_channels
+                            .get(channel_id)
+                            .map_or(false, |channel| channel.server_id == server_id)
+                    })
+            .map(|(channel_id, exclusion)| {
+                     format!("<#{}>: {}\n", channel_id, exclusion.description())
+                 })
+            .collect::<Vec<_>>();
+        channels.sort();
+
+        let mut users = self.excluded_users
+            .iter()
+            .filter(|&(&(user_server_id, _), _)| user_server_id == server_id)
+            .map(|(&(_, user_id), exclusion)| {
+                     format!("<@{}>: {}\n", user_id, exclusion.description())
+                 })
+            .collect::<Vec<_>>();
+        users.sort();
+
+        if channels.is_empty() && users.is_empty() {
+            self.send_response(message,
+                               "Nothing is excluded on this server. Use `exclude #channel` or \
+                                `exclude @user` to exclude a channel or user. :shrug:");
+            return BotLoopDisposition::Continue;
+        }
+
+        let _ = self.discord
+            .send_embed(message.channel_id,
+                        &format!("<@{}>", message.author.id),
+                        |e| {
+                            e.title("Exclusions on this server :no_entry_sign:")
+                                .description("Emoji aren't recorded in channels or from users \
+                                              excluded from tracking, and those already \
+                                              recorded are left out of statistics if excluded \
+                                              from leaderboards.")
+                                .fields(|f| {
+                                    let f = if channels.is_empty() {
+                                        f
+                                    } else {
+                                        f.field("Channels", &channels.concat(), false)
+                                    };
+
+                                    if users.is_empty() {
+                                        f
+                                    } else {
+                                        f.field("Users", &users.concat(), false)
+                                    }
+                                })
+                        });
+
+        BotLoopDisposition::Continue
+    }
+
+    // What the user may do on the server, or anywhere if server_id is None
+    // Returns None if the user's permissions on the server couldn't be determined
+    fn permission_level(&self,
+                        server_id: Option<&ServerId>,
+                        user_id: &UserId)
+                        -> Option<PermissionLevel> {
+        if self.is_bot_admin(user_id) {
+            return Some(PermissionLevel::BotAdmin);
+        }
+
+        match server_id.map(|server_id| self.can_manage_server(server_id, user_id)) {
+            Some(Some(true)) => Some(PermissionLevel::ServerManager),
+            Some(None) => None,
+            Some(Some(false)) | None => Some(PermissionLevel::Member),
+        }
+    }
+
+    // Responds to the message and returns false unless its author can manage the server
+    fn check_server_manager(&self, message: &Message, server_id: &ServerId) -> bool {
+        match self.permission_level(Some(server_id), &message.author.id) {
+            Some(level) if level >= PermissionLevel::ServerManager => true,
+            Some(_) => {
+                self.send_response(message,
+                                   "You need the Manage Server permission to use this command. \
+                                    :lock:");
+                false
+            }
+            None => {
+                self.send_response(message,
+                                   "Sorry! I couldn't check your permissions on this server. \
+                                    Please try again later. :warning:");
+                false
+            }
+        }
+    }
+
+    fn is_bot_admin(&self, user_id: &UserId) -> bool {
+        self.bot_admins.is_admin(user_id)
+    }
+
+    // Whether the user owns the server or has the Manage Server or Administrator permission on it
+    // The user's roles are taken from those tracked in the database; Discord is only asked for
+    // them if none are tracked, e.g. for members who have no roles
+    // Returns None if the user's roles couldn't be retrieved
+    fn can_manage_server(&self, server_id: &ServerId, user_id: &UserId) -> Option<bool> {
+        if let Some(server) = self.servers.get(server_id) {
+            if server.owner_id == Some(*user_id) {
+                return Some(true);
+            }
+        }
+
+        let has_permission = |role_ids: &[RoleId]| {
+            // Everyone has the permissions of the @everyone role
+            role_ids
+                .iter()
+                .chain(Some(server_id.everyone()).iter())
+                .filter_map(|role_id| self.role_permissions.get(role_id))
+                .fold(Permissions::empty(), |permissions, role_permissions| {
+                    permissions | *role_permissions
+                })
+                .intersects(MANAGE_SERVER | ADMINISTRATOR)
+        };
+
+        let tracked_role_ids = match self.db.get_member_roles(server_id, user_id) {
+            Ok(role_ids) => role_ids,
+            Err(reason) => {
+                warn!("Unable to retrieve the roles of member {} of server {}: {}",
+                      user_id,
+                      server_id,
+                      reason);
+                Vec::new()
+            }
+        };
+
+        if !tracked_role_ids.is_empty() || has_permission(&[]) {
+            return Some(has_permission(&tracked_role_ids));
+        }
+
+        match self.discord.get_member(*server_id, *user_id) {
+            Ok(member) => Some(has_permission(&member.roles)),
+            Err(reason) => {
+                warn!("Unable to get member {} of server {}: {}",
+                      user_id,
+                      server_id,
+                      reason);
+                None
+            }
+        }
+    }
+
+    fn help(&self, message: &Message) -> BotLoopDisposition {
+        if self.help_text.is_some() {
+            self.send_response(message, self.help_text.as_ref().unwrap());
+        }
+
+        BotLoopDisposition::Continue
+    }
+
+    fn stats_global(&mut self, message: &Message, args: &str) -> BotLoopDisposition {
+        let (options, other_args) = self.stats_options(message, args);
+
+        match other_args.first().map(|arg| arg.to_lowercase()) {
+            Some(ref arg) if arg == "servers" => return self.stats_global_servers(message, args),
+            Some(ref arg) if arg == "custom" => return self.stats_global_custom(message, args),
+            Some(ref arg) if arg == "server" || arg == "here" => {
+                return self.stats_global_vs_server(message, args);
+            }
+            Some(ref arg) if arg == "optin" => return self.set_server_ranked(message, true),
+            Some(ref arg) if arg == "optout" => return self.set_server_ranked(message, false),
+            _ => {}
+        }
+
+        let earth_emoji_list = [":earth_africa:", ":earth_americas:", ":earth_asia:"];
+        let earth = thread_rng().choose(&earth_emoji_list).unwrap();
+
+        let leaderboard = Leaderboard {
+            kind: LeaderboardKind::TopEmoji(Scope::Global),
+            title: format!("Top used emoji globally{} {}",
+                           options.filter.description(),
+                           earth),
+            description: None,
+            filter: options.filter,
+            page: self.page(message, options.page),
+        };
+
+        self.send_leaderboard(message,
+                              leaderboard,
+                              &format!("I've never seen anyone use any emoji{}. :shrug:",
+                                       options.filter.description()));
+
+        BotLoopDisposition::Continue
+    }
+
+    // Lists the most used custom emoji of the servers that have opted into the global rankings
+    fn stats_global_custom(&mut self, message: &Message, args: &str) -> BotLoopDisposition {
+        let (options, _) = self.stats_options(message, args);
+
+        let leaderboard = Leaderboard {
+            kind: LeaderboardKind::TopGlobalCustomEmoji,
+            title: format!("Top used custom emoji globally{} :earth_americas:",
+                           options.filter.description()),
+            description: None,
+            filter: options.filter,
+            page: self.page(message, options.page),
+        };
+
+        self.send_leaderboard(message,
+                              leaderboard,
+                              &format!("I've never seen anyone use the custom emoji of a ranked \
+                                        server{}. :shrug:",
+                                       options.filter.description()));
+
+        BotLoopDisposition::Continue
+    }
+
+    // Ranks the servers that have opted in by how many emoji are used on them
+    fn stats_global_servers(&mut self, message: &Message, args: &str) -> BotLoopDisposition {
+        let (options, _) = self.stats_options(message, args);
+
+        let server_id = self.public_text_channels
+            .get(&message.channel_id)
+            .map(|channel| channel.server_id);
+
+        let description = match server_id {
+            Some(ref server_id) => {
+                match self.db.is_server_ranked(server_id) {
+                    Ok(true) => None,
+                    Ok(false) => {
+                        Some("This server isn't ranked. Its owner can add it with \
+                              `global optin`."
+                                     .to_string())
+                    }
+                    Err(reason) => {
+                        warn!("Error checking whether server {} is ranked: {}",
+                              server_id,
+                              reason);
+                        None
+                    }
+                }
+            }
+            None => None,
+        };
+
+        let leaderboard = Leaderboard {
+            kind: LeaderboardKind::TopServers(server_id),
+            title: format!("Servers using the most emoji{} :trophy:",
+                           options.filter.description()),
+            description,
+            filter: options.filter,
+            page: self.page(message, options.page),
+        };
+
+        self.send_leaderboard(message,
+                              leaderboard,
+                              &format!("No servers have been ranked{}. A server's owner can \
+                                        add it with `global optin`. :shrug:",
+                                       options.filter.description()));
+
+        BotLoopDisposition::Continue
+    }
+
+    // Shows this server's top emoji alongside where each ranks on all servers
+    fn stats_global_vs_server(&mut self, message: &Message, args: &str) -> BotLoopDisposition {
+        let server_id = match self.public_text_channels.get(&message.channel_id) {
+            Some(channel) => channel.server_id,
+            None => {
+                self.send_response(message, RESPONSE_USE_COMMAND_IN_PUBLIC_CHANNEL);
+                return BotLoopDisposition::Continue;
+            }
+        };
+
+        let (options, _) = self.stats_options(message, args);
+
+        let leaderboard = Leaderboard {
+            kind: LeaderboardKind::ServerVsGlobal(server_id),
+            title: format!("This server's top emoji compared with all servers{} \
+                            :earth_americas:",
+                           options.filter.description()),
+            description: None,
+            filter: options.filter,
+            page: self.page(message, options.page),
+        };
+
+        self.send_leaderboard(message,
+                              leaderboard,
+                              &format!("I've never seen anyone use any emoji on this \
+                                        server{}. :shrug:",
+                                       options.filter.description()));
+
+        BotLoopDisposition::Continue
+    }
+
+    // Adds this server to, or removes it from, the global server rankings
+    // Only the server's owner and bot administrators can do this
+    fn set_server_ranked(&self, message: &Message, ranked: bool) -> BotLoopDisposition {
+        let server_id = match self.public_text_channels.get(&message.channel_id) {
+            Some(channel) => channel.server_id,
+            None => {
+                self.send_response(message, RESPONSE_USE_COMMAND_IN_PUBLIC_CHANNEL);
+                return BotLoopDisposition::Continue;
+            }
+        };
+
+        let is_owner = self.servers
+            .get(&server_id)
+            .and_then(|server| server.owner_id) == Some(message.author.id);
+
+        if !is_owner && !self.is_bot_admin(&message.author.id) {
+            self.send_response(message,
+                               "Only this server's owner can change whether it's ranked. :lock:");
+            return BotLoopDisposition::Continue;
+        }
+
+        match self.db.set_server_ranked(&server_id, ranked) {
+            Ok(_) => {
+                self.send_response(message,
+                                   if ranked {
+                                       "This server will now be ranked against other servers \
+                                        in `global servers`. :trophy:"
+                                   } else {
+                                       "This server will no longer be ranked against other \
+                                        servers. :white_check_mark:"
+                                   });
+            }
+            Err(reason) => {
+                warn!("Error setting whether server {} is ranked: {}",
+                      server_id,
+                      reason);
+                self.send_response(message, RESPONSE_SAVE_ERR);
+            }
+        }
+
+        BotLoopDisposition::Continue
+    }
+
+    fn stats_server(&mut self, message: &Message, args: &str) -> BotLoopDisposition {
+        if self.private_channels.contains_key(&message.channel_id) {
+            self.send_response(message, RESPONSE_USE_COMMAND_IN_PUBLIC_CHANNEL);
+            return BotLoopDisposition::Continue;
+        }
+
+        let server_id = match self.public_text_channels.get(&message.channel_id) {
+            Some(channel) => channel.server_id,
+            None => {
+                warn!("Unknown public text channel ({})", message.channel_id);
+                self.send_response(message, RESPONSE_STATS_ERR);
+                return BotLoopDisposition::Continue;
+            }
+        };
+
+        let (options, _) = self.stats_options(message, args);
+        let scope = Scope::Server(server_id);
+
+        let leaderboard = Leaderboard {
+            description: self.get_activity_description(&scope, &options.filter),
+            kind: self.top_emoji_kind(message, scope),
+            title: format!("Statistics for this server{} :chart_with_upwards_trend:",
+                           options.filter.description()),
+            filter: options.filter,
+            page: self.page(message, options.page),
+        };
+
+        self.send_leaderboard(message,
+                              leaderboard,
+                              &format!("I've never seen anyone use any emoji on this \
+                                        server{}. :shrug:",
+                                       options.filter.description()));
+
+        BotLoopDisposition::Continue
+    }
+
+    // Lists the server's custom emoji that have been used least, to help decide which to remove
+    fn stats_least_used(&mut self,
+                        message: &Message,
+                        args: &str,
+                        unused_only: bool)
+                        -> BotLoopDisposition {
+        if self.private_channels.contains_key(&message.channel_id) {
+            self.send_response(message, RESPONSE_USE_COMMAND_IN_PUBLIC_CHANNEL);
+            return BotLoopDisposition::Continue;
+        }
+
+        let server_id = match self.public_text_channels.get(&message.channel_id) {
+            Some(channel) => channel.server_id,
+            None => {
+                warn!("Unknown public text channel ({})", message.channel_id);
+                self.send_response(message, RESPONSE_STATS_ERR);
+                return BotLoopDisposition::Continue;
+            }
+        };
+
+        let (options, _) = self.stats_options(message, args);
+
+        let leaderboard = Leaderboard {
+            kind: LeaderboardKind::LeastUsedEmoji(server_id, unused_only),
+            title: format!("{} custom emoji on this server{} :wastebasket:",
+                           if unused_only { "Unused" } else { "Least used" },
+                           options.filter.description()),
+            description: None,
+            filter: options.filter,
+            page: self.page(message, options.page),
+        };
+
+        let empty_response = if unused_only {
+            format!("Every custom emoji on this server has been used{}. :tada:",
+                    options.filter.description())
+        } else {
+            "This server doesn't have any custom emoji. :shrug:".to_string()
+        };
+
+        self.send_leaderboard(message, leaderboard, &empty_response);
+
+        BotLoopDisposition::Continue
+    }
+
+    fn stats_channel(&mut self,
+                     message: &Message,
+                     channel_id: Option<&ChannelId>,
+                     args: &str)
+                     -> BotLoopDisposition {
+        if self.private_channels.contains_key(&message.channel_id) {
+            self.send_response(message, RESPONSE_USE_COMMAND_IN_PUBLIC_CHANNEL);
+            return BotLoopDisposition::Continue;
+        }
+
+        let channel_id = channel_id.unwrap_or(&message.channel_id);
+        let (options, _) = self.stats_options(message, args);
+
+        let stats_description = match self.public_text_channels.get(&channel_id) {
+            Some(channel) => {
+                format!("Statistics for #{}{} :chart_with_upwards_trend:",
+                        channel.name,
+                        options.filter.description())
+            }
+            None => {
+                format!("Channel statistics{} :chart_with_upwards_trend:",
+                        options.filter.description())
+            }
+        };
+
+        let scope = Scope::Channel(*channel_id);
+
+        let leaderboard = Leaderboard {
+            description: self.get_activity_description(&scope, &options.filter),
+            kind: self.top_emoji_kind(message, scope),
+            title: stats_description,
+            filter: options.filter,
+            page: self.page(message, options.page),
+        };
+
+        self.send_leaderboard(message,
+                              leaderboard,
+                              &format!("I've never seen anyone use any emoji in that \
+                                        channel{}. :shrug:",
+                                       options.filter.description()));
+
+        BotLoopDisposition::Continue
+    }
+
+    // Lists the emoji most often typed into the same messages as the emoji
+    fn stats_pairs(&mut self, message: &Message, args: &str) -> BotLoopDisposition {
+        let (options, other_args) = self.stats_options(message, args);
+
+        if options.filter.source == UsageSource::Reactions {
+            self.respond_reactions_not_paired(message);
+            return BotLoopDisposition::Continue;
+        }
+
+        let emoji = match other_args.first().and_then(|arg| self.find_emoji(arg)) {
+            Some(emoji) => emoji,
+            None => {
+                self.send_response(message,
+                                   "Please use an emoji that I track. :shrug:");
+                return BotLoopDisposition::Continue;
+            }
+        };
+
+        let server_id = self.public_text_channels
+            .get(&message.channel_id)
+            .map(|channel| channel.server_id);
+
+        let leaderboard = Leaderboard {
+            kind: LeaderboardKind::PairedEmoji(emoji.clone(), server_id),
+            title: format!("Emoji used with {}{}{} :link:",
+                           emoji.pattern(),
+                           if server_id.is_some() {
+                               " on this server"
+                           } else {
+                               ""
+                           },
+                           options.filter.description()),
+            description: None,
+            filter: options.filter,
+            page: self.page(message, options.page),
+        };
+
+        self.send_leaderboard(message,
+                              leaderboard,
+                              &format!("I've never seen {} used with another emoji{}. :shrug:",
+                                       emoji.pattern(),
+                                       options.filter.description()));
+
+        BotLoopDisposition::Continue
+    }
+
+    // Lists the pairs of emoji most often typed into the same messages
+    fn stats_combos(&mut self, message: &Message, args: &str) -> BotLoopDisposition {
+        let (options, _) = self.stats_options(message, args);
+
+        if options.filter.source == UsageSource::Reactions {
+            self.respond_reactions_not_paired(message);
+            return BotLoopDisposition::Continue;
+        }
+
+        let server_id = self.public_text_channels
+            .get(&message.channel_id)
+            .map(|channel| channel.server_id);
+
+        let leaderboard = Leaderboard {
+            kind: LeaderboardKind::TopEmojiPairs(server_id),
+            title: format!("Top emoji combos{}{} :handshake:",
+                           if server_id.is_some() {
+                               " on this server"
+                           } else {
+                               " globally"
+                           },
+                           options.filter.description()),
+            description: None,
+            filter: options.filter,
+            page: self.page(message, options.page),
+        };
+
+        self.send_leaderboard(message,
+                              leaderboard,
+                              &format!("I've never seen two emoji used together{}. :shrug:",
+                                       options.filter.description()));
+
         BotLoopDisposition::Continue
     }
 
-    fn stats_global(&self, message: &Message) -> BotLoopDisposition {
-        let top_emoji = match self.db.get_global_top_emoji() {
-            Ok(results) => results,
+    // Counts the emoji used on this server by the role's members
+    fn stats_role(&mut self,
+                  message: &Message,
+                  role_id: &RoleId,
+                  args: &str)
+                  -> BotLoopDisposition {
+        if self.private_channels.contains_key(&message.channel_id) {
+            self.send_response(message, RESPONSE_USE_COMMAND_IN_PUBLIC_CHANNEL);
+            return BotLoopDisposition::Continue;
+        }
+
+        let server_id = match self.public_text_channels.get(&message.channel_id) {
+            Some(channel) => channel.server_id,
+            None => {
+                warn!("Unknown public text channel ({})", message.channel_id);
+                self.send_response(message, RESPONSE_STATS_ERR);
+                return BotLoopDisposition::Continue;
+            }
+        };
+
+        let (options, _) = self.stats_options(message, args);
+        let scope = Scope::Role(*role_id, server_id);
+
+        let leaderboard = Leaderboard {
+            description: self.get_activity_description(&scope, &options.filter),
+            kind: self.top_emoji_kind(message, scope),
+            title: format!("Statistics for {}{} :chart_with_upwards_trend:",
+                           self.get_role_label(role_id),
+                           options.filter.description()),
+            filter: options.filter,
+            page: self.page(message, options.page),
+        };
+
+        self.send_leaderboard(message,
+                              leaderboard,
+                              &format!("I've never seen anyone in <@&{}> use any emoji{}. \
+                                        :shrug:",
+                                       role_id,
+                                       options.filter.description()));
+
+        BotLoopDisposition::Continue
+    }
+
+    fn stats_user(&mut self,
+                  message: &Message,
+                  user_id: Option<&UserId>,
+                  args: &str)
+                  -> BotLoopDisposition {
+        let user_id = user_id.unwrap_or(&message.author.id);
+        let (options, _) = self.stats_options(message, args);
+
+        if *user_id == self.bot_user_id {
+            self.send_response(message, "You're so silly! :smile:");
+            return BotLoopDisposition::Continue;
+        }
+
+        if self.opted_out_users.contains(user_id) {
+            self.send_response(message,
+                               if *user_id == message.author.id {
+                                   "You've opted out of statistics, so I don't keep track of \
+                                    your emoji. Use `optin` to opt back in. :see_no_evil:"
+                               } else {
+                                   "That user has opted out of statistics. :see_no_evil:"
+                               });
+            return BotLoopDisposition::Continue;
+        }
+
+        // If the bot knows which server is associated with the public text channel, get statistics
+        // for both Unicode emoji and custom emoji on the same server
+        // Otherwise, just get statistics for Unicode emoji
+        let server = match self.public_text_channels.get(&message.channel_id) {
+            Some(channel) => Some(channel.server_id),
+            None => None,
+        };
+
+        let user_name = self.get_user_label(user_id);
+
+        let stats_description = if *user_id == message.author.id {
+            format!("Your favourite emoji{} :two_hearts:",
+                    options.filter.description())
+        } else {
+            format!("{}'s favourite emoji{} :two_hearts:",
+                    user_name,
+                    options.filter.description())
+        };
+
+        let rank_description = match self.db
+                  .get_user_rank(user_id, server.as_ref(), None, &options.filter) {
+            Ok(Some(rank)) => {
+                Some(format!("{} #{} of {} emoji user{} {}{} (top {}%). :trophy:",
+                             if *user_id == message.author.id {
+                                 "You are".to_string()
+                             } else {
+                                 format!("<@{}> is", user_id)
+                             },
+                             rank.rank,
+                             rank.num_users,
+                             if rank.num_users == 1 { "" } else { "s" },
+                             if server.is_some() {
+                                 "on this server"
+                             } else {
+                                 "everywhere"
+                             },
+                             options.filter.description(),
+                             rank.top_percent()))
+            }
+            Ok(None) => None,
+            Err(reason) => {
+                warn!("Unable to retrieve rank of user {} ({}): {}",
+                      user_name,
+                      user_id,
+                      reason);
+                None
+            }
+        };
+
+        let leaderboard = Leaderboard {
+            kind: LeaderboardKind::TopEmoji(Scope::User(*user_id, server)),
+            title: stats_description,
+            description: rank_description,
+            filter: options.filter,
+            page: self.page(message, options.page),
+        };
+
+        self.send_leaderboard(message,
+                              leaderboard,
+                              &format!("I've never seen <@{}> use any emoji{}. :shrug:",
+                                       user_id,
+                                       options.filter.description()));
+
+        BotLoopDisposition::Continue
+    }
+
+    // Summarises how often messages contain emoji, for the description of a leaderboard
+    fn get_activity_description(&self, scope: &Scope, filter: &UsageFilter) -> Option<String> {
+        match self.db.get_activity(scope, filter) {
+            Ok(ref activity) if activity.num_messages > 0 => Some(create_activity_line(activity)),
+            Ok(_) => None,
+            Err(reason) => {
+                warn!("Unable to retrieve message activity ({:?}): {}", scope, reason);
+                None
+            }
+        }
+    }
+
+    // The server's page size is used if it has one
+    fn page(&self, message: &Message, number: u32) -> Page {
+        Page {
+            number,
+            size: self.get_settings(message)
+                .and_then(|settings| settings.page_size())
+                .unwrap_or(self.page_size),
+        }
+    }
+
+    // Separates statistics options from the rest of a command's arguments, as parse_stats_options
+    // does, using the server's default period if none is given
+    fn stats_options<'a>(&self, message: &Message, args: &'a str) -> (StatsOptions, Vec<&'a str>) {
+        let (mut options, other_args) = parse_stats_options(args);
+
+        if !options.period_specified {
+            if let Some(period) = self.get_settings(message)
+                   .and_then(|settings| settings.period()) {
+                options.filter.period = period;
+                options.period_specified = true;
+            }
+        }
+
+        (options, other_args)
+    }
+
+    // Returns the settings of the server on which the message was sent, if it has any
+    fn get_settings(&self, message: &Message) -> Option<&ServerSettings> {
+        self.public_text_channels
+            .get(&message.channel_id)
+            .and_then(|channel| self.server_settings.get(&channel.server_id))
+    }
+
+    // The server's prefix if it has set one, or the bot's prefix otherwise
+    fn command_prefix(&self, channel_id: &ChannelId) -> Option<&str> {
+        let server_prefix = self.public_text_channels
+            .get(channel_id)
+            .and_then(|channel| self.server_settings.get(&channel.server_id))
+            .and_then(|settings| settings.prefix());
+
+        match server_prefix {
+            Some(prefix) if prefix.is_empty() => None,
+            Some(prefix) => Some(prefix),
+            None => self.prefix.as_ref().map(String::as_str),
+        }
+    }
+
+    fn is_command_disabled(&self, message: &Message, command: &str) -> bool {
+        self.get_settings(message)
+            .map_or(false, |settings| settings.is_command_disabled(command))
+    }
+
+    // Whether emoji used by the user in the channel aren't recorded
+    fn is_excluded_from_tracking(&self, channel_id: &ChannelId, user_id: &UserId) -> bool {
+        if self.excluded_channels
+               .get(channel_id)
+               .map_or(false, |exclusion| exclusion.tracking) {
+            return true;
+        }
+
+        match self.public_text_channels.get(channel_id) {
+            Some(channel) => {
+                self.excluded_users
+                    .get(&(channel.server_id, *user_id))
+                    .map_or(false, |exclusion| exclusion.tracking)
+            }
+            None => false,
+        }
+    }
+
+    // The top users are shown alongside the top emoji unless the server has turned them off
+    fn top_emoji_kind(&self, message: &Message, scope: Scope) -> LeaderboardKind {
+        if self.show_user_leaderboards(message) {
+            LeaderboardKind::TopEmojiAndUsers(scope)
+        } else {
+            LeaderboardKind::TopEmoji(scope)
+        }
+    }
+
+    fn show_user_leaderboards(&self, message: &Message) -> bool {
+        self.get_settings(message)
+            .map_or(true, |settings| settings.show_user_leaderboards())
+    }
+
+    // Replies with a page of a leaderboard
+    // If there is more than one page, arrow reactions are added so that it can be paged through
+    fn send_leaderboard(&mut self,
+                        message: &Message,
+                        leaderboard: Leaderboard,
+                        empty_response: &str) {
+        let (fields, is_full_page) = match self.get_leaderboard_fields(&leaderboard) {
+            Some(page) => page,
+            None => {
+                self.send_response(message, RESPONSE_STATS_ERR);
+                return;
+            }
+        };
+
+        if fields.is_empty() {
+            if leaderboard.page.number == 1 {
+                self.send_response(message, empty_response);
+            } else {
+                self.send_response(message,
+                                   &format!("There's no page {} of those statistics. :shrug:",
+                                            leaderboard.page.number));
+            }
+            return;
+        }
+
+        let paginated = is_full_page || leaderboard.page.number > 1;
+
+        let reply = match self.discord
+                  .send_embed(message.channel_id,
+                              &format!("<@{}>", message.author.id),
+                              |e| create_leaderboard_embed(e, &leaderboard, &fields, paginated)) {
+            Ok(reply) => reply,
+            Err(reason) => {
+                warn!("Unable to send statistics in channel {}: {}",
+                      message.channel_id,
+                      reason);
+                return;
+            }
+        };
+
+        if paginated {
+            for arrow in &[PREVIOUS_PAGE_EMOJI, NEXT_PAGE_EMOJI] {
+                if let Err(reason) = self.discord
+                       .add_reaction(reply.channel_id,
+                                     reply.id,
+                                     ReactionEmoji::Unicode(arrow.to_string())) {
+                    warn!("Unable to add page reactions to message {}: {}",
+                          reply.id,
+                          reason);
+                    return;
+                }
+            }
+
+            self.leaderboards.insert(reply.id, leaderboard);
+            self.leaderboard_order.push_back(reply.id);
+
+            while self.leaderboard_order.len() > MAX_LEADERBOARDS {
+                if let Some(message_id) = self.leaderboard_order.pop_front() {
+                    self.leaderboards.remove(&message_id);
+                }
+            }
+        }
+    }
+
+    // Shows another page of a leaderboard when one of its arrow reactions is added or removed
+    // Returns true if the reaction was an arrow on a leaderboard, so it shouldn't be counted
+    fn turn_leaderboard_page(&mut self, reaction: &Reaction) -> bool {
+        let arrow = match reaction.emoji {
+            ReactionEmoji::Unicode(ref emoji) => emoji.trim_right_matches('\u{FE0F}'),
+            ReactionEmoji::Custom { .. } => return false,
+        };
+
+        let forward = if arrow == PREVIOUS_PAGE_EMOJI.trim_right_matches('\u{FE0F}') {
+            false
+        } else if arrow == NEXT_PAGE_EMOJI.trim_right_matches('\u{FE0F}') {
+            true
+        } else {
+            return false;
+        };
+
+        let mut leaderboard = match self.leaderboards.get(&reaction.message_id) {
+            Some(leaderboard) => leaderboard.clone(),
+            None => return false,
+        };
+
+        // The bot's own arrows are added when the leaderboard is posted
+        if reaction.user_id == self.bot_user_id {
+            return true;
+        }
+
+        if forward {
+            leaderboard.page.number += 1;
+        } else if leaderboard.page.number > 1 {
+            leaderboard.page.number -= 1;
+        } else {
+            return true;
+        }
+
+        match self.get_leaderboard_fields(&leaderboard) {
+            Some((ref fields, _)) if !fields.is_empty() => {
+                if let Err(reason) = self.discord
+                       .edit_embed(reaction.channel_id,
+                                   reaction.message_id,
+                                   |e| create_leaderboard_embed(e, &leaderboard, fields, true)) {
+                    warn!("Unable to show page {} in message {}: {}",
+                          leaderboard.page.number,
+                          reaction.message_id,
+                          reason);
+                    return true;
+                }
+
+                self.leaderboards.insert(reaction.message_id, leaderboard);
+            }
+            _ => {}
+        }
+
+        true
+    }
+
+    // Retrieves a page of a leaderboard as embed fields (name, value, inline) and whether the page
+    // is full, i.e. whether there may be another page
+    // Returns no fields if the page is empty, or None if there was an error
+    fn get_leaderboard_fields(&self,
+                              leaderboard: &Leaderboard)
+                              -> Option<(Vec<(String, String, bool)>, bool)> {
+        let filter = &leaderboard.filter;
+        let page = &leaderboard.page;
+
+        match leaderboard.kind {
+            LeaderboardKind::TopEmoji(ref scope) => {
+                let top_emoji = match self.db.get_top_emoji(scope, filter, page) {
+                    Ok(results) => results,
+                    Err(reason) => {
+                        warn!("Unable to retrieve top used emoji ({:?}): {}", scope, reason);
+                        return None;
+                    }
+                };
+
+                if top_emoji.is_empty() {
+                    return Some((Vec::new(), false));
+                }
+
+                let is_full_page = top_emoji.len() as i64 == page.limit();
+
+                Some((vec![(leaderboard.title.clone(), create_emoji_usage_line(top_emoji), false)],
+                      is_full_page))
+            }
+            LeaderboardKind::TopGlobalCustomEmoji => {
+                let top_emoji = match self.db.get_global_top_custom_emoji(filter, page) {
+                    Ok(results) => results,
+                    Err(reason) => {
+                        warn!("Unable to retrieve top used custom emoji globally: {}", reason);
+                        return None;
+                    }
+                };
+
+                if top_emoji.is_empty() {
+                    return Some((Vec::new(), false));
+                }
+
+                let is_full_page = top_emoji.len() as i64 == page.limit();
+
+                Some((vec![(leaderboard.title.clone(), create_emoji_usage_line(top_emoji), false)],
+                      is_full_page))
+            }
+            LeaderboardKind::TopEmojiAndUsers(ref scope) => {
+                let top_emoji = match self.db.get_top_emoji(scope, filter, page) {
+                    Ok(results) => results,
+                    Err(reason) => {
+                        warn!("Unable to retrieve top used emoji ({:?}): {}", scope, reason);
+                        return None;
+                    }
+                };
+
+                if top_emoji.is_empty() {
+                    return Some((Vec::new(), false));
+                }
+
+                let top_users = match self.db.get_top_users(scope, filter, page) {
+                    Ok(results) => results,
+                    Err(reason) => {
+                        warn!("Unable to retrieve top users ({:?}): {}", scope, reason);
+                        return None;
+                    }
+                };
+
+                let is_full_page = top_emoji.len() as i64 == page.limit() ||
+                                   top_users.len() as i64 == page.limit();
+
+                Some((vec![("Top emoji".to_string(), create_emoji_usage_line(top_emoji), true),
+                           ("Top users".to_string(), create_top_users_line(top_users), true)],
+                      is_full_page))
+            }
+            LeaderboardKind::LeastUsedEmoji(ref server_id, unused_only) => {
+                let least_used_emoji = match self.db
+                          .get_server_least_used_emoji(server_id, filter, unused_only, page) {
+                    Ok(results) => results,
+                    Err(reason) => {
+                        warn!("Unable to retrieve least used emoji on server ({}): {}",
+                              server_id,
+                              reason);
+                        return None;
+                    }
+                };
+
+                if least_used_emoji.is_empty() {
+                    return Some((Vec::new(), false));
+                }
+
+                let is_full_page = least_used_emoji.len() as i64 == page.limit();
+
+                Some((vec![(leaderboard.title.clone(),
+                            create_least_used_line(least_used_emoji),
+                            false)],
+                      is_full_page))
+            }
+            LeaderboardKind::PairedEmoji(ref emoji, ref server_id) => {
+                let paired_emoji = match self.db
+                          .get_paired_emoji(emoji, server_id.as_ref(), filter, page) {
+                    Ok(results) => results,
+                    Err(reason) => {
+                        warn!("Unable to retrieve emoji paired with {}: {}",
+                              emoji.pattern(),
+                              reason);
+                        return None;
+                    }
+                };
+
+                if paired_emoji.is_empty() {
+                    return Some((Vec::new(), false));
+                }
+
+                let is_full_page = paired_emoji.len() as i64 == page.limit();
+
+                Some((vec![(leaderboard.title.clone(),
+                            create_paired_emoji_line(emoji, paired_emoji),
+                            false)],
+                      is_full_page))
+            }
+            LeaderboardKind::TopEmojiPairs(ref server_id) => {
+                let pairs = match self.db.get_top_emoji_pairs(server_id.as_ref(), filter, page) {
+                    Ok(results) => results,
+                    Err(reason) => {
+                        warn!("Unable to retrieve top emoji pairs ({:?}): {}",
+                              server_id,
+                              reason);
+                        return None;
+                    }
+                };
+
+                if pairs.is_empty() {
+                    return Some((Vec::new(), false));
+                }
+
+                let is_full_page = pairs.len() as i64 == page.limit();
+
+                Some((vec![(leaderboard.title.clone(), create_emoji_pairs_line(pairs), false)],
+                      is_full_page))
+            }
+            LeaderboardKind::TopServers(ref server_id) => {
+                let servers = match self.db.get_top_servers(filter, page) {
+                    Ok(results) => results,
+                    Err(reason) => {
+                        warn!("Unable to retrieve top servers: {}", reason);
+                        return None;
+                    }
+                };
+
+                if servers.is_empty() {
+                    return Some((Vec::new(), false));
+                }
+
+                let is_full_page = servers.len() as i64 == page.limit();
+
+                Some((vec![(leaderboard.title.clone(),
+                            create_top_servers_line(servers, server_id.as_ref(), page.offset()),
+                            false)],
+                      is_full_page))
+            }
+            LeaderboardKind::ServerVsGlobal(ref server_id) => {
+                let top_emoji = match self.db
+                          .get_server_top_emoji_vs_global(server_id, filter, page) {
+                    Ok(results) => results,
+                    Err(reason) => {
+                        warn!("Unable to retrieve top emoji on server {} compared with all \
+                               servers: {}",
+                              server_id,
+                              reason);
+                        return None;
+                    }
+                };
+
+                if top_emoji.is_empty() {
+                    return Some((Vec::new(), false));
+                }
+
+                let is_full_page = top_emoji.len() as i64 == page.limit();
+
+                Some((vec![(leaderboard.title.clone(),
+                            create_server_vs_global_line(top_emoji),
+                            false)],
+                      is_full_page))
+            }
+        }
+    }
+
+    fn stats_emoji(&self, message: &Message, emoji: &Emoji, args: &str) {
+        let (options, _) = self.stats_options(message, args);
+
+        let count = match self.db.get_emoji_usage(emoji, None, &options.filter) {
+            Ok(Some(count)) if count > 0 => count,
+            Ok(_) => {
+                self.send_response(message,
+                                   &format!("I've never seen anyone use {}{}.",
+                                            emoji.pattern(),
+                                            options.filter.description()));
+                return;
+            }
             Err(reason) => {
-                warn!("Unable to retrieve global top used emoji: {}", reason);
+                warn!("Error obtaining emoji usage stats for emoji {}: {}",
+                      emoji.pattern(),
+                      reason);
                 self.send_response(message, RESPONSE_STATS_ERR);
-                return BotLoopDisposition::Continue;
+                return;
             }
         };
 
-        if top_emoji.len() == 0 {
-            self.send_response(message, "I've never seen anyone use any emoji. :shrug:");
+        let variants = if options.show_variants {
+            self.get_variant_breakdown(emoji, &options.filter)
         } else {
-            let stats = create_emoji_usage_line(top_emoji);
+            String::new()
+        };
+
+        let rank = if options.show_rank {
+            self.get_emoji_rank(message, emoji, &options.filter)
+        } else {
+            String::new()
+        };
+
+        let description = format!("{} has been used {} time{}{}{}.{}",
+                                  emoji.pattern(),
+                                  count,
+                                  if count == 1 { "" } else { "s" },
+                                  options.filter.description(),
+                                  variants,
+                                  rank);
+
+        let server_id = self.public_text_channels
+            .get(&message.channel_id)
+            .map(|channel| channel.server_id);
+        let page = self.page(message, options.page);
+        let fields = self.get_emoji_details(emoji,
+                                            count,
+                                            server_id,
+                                            self.show_user_leaderboards(message),
+                                            &options.filter,
+                                            &page);
+
+        let _ = self.discord
+            .send_embed(message.channel_id,
+                        &format!("<@{}>", message.author.id),
+                        |e| {
+                            e.title(&format!("Statistics for {}{}",
+                                             emoji.pattern(),
+                                             options.filter.description()))
+                                .description(&description)
+                                .fields(|f| {
+                                    fields
+                                        .iter()
+                                        .fold(f, |f, &(name, ref value)| f.field(name, value, true))
+                                })
+                        });
+    }
+
+    // Describes the emoji's usage on this server compared with everywhere, its rank among all
+    // emoji, when it was first and last used, and who used it most and where, as embed fields
+    // Users and channels are only listed on a server, and users only if show_users is true
+    fn get_emoji_details(&self,
+                         emoji: &Emoji,
+                         count: i64,
+                         server_id: Option<ServerId>,
+                         show_users: bool,
+                         filter: &UsageFilter,
+                         page: &Page)
+                         -> Vec<(&'static str, String)> {
+        let mut fields = Vec::new();
+
+        if let Some(ref server_id) = server_id {
+            match self.db.get_emoji_usage(emoji, Some(server_id), filter) {
+                Ok(server_count) => {
+                    let server_count = server_count.unwrap_or(0);
+
+                    fields.push(("On this server",
+                                 format!("{} time{} ({:.0}% of all uses)",
+                                         server_count,
+                                         if server_count == 1 { "" } else { "s" },
+                                         server_count as f64 * 100.0 / count as f64)));
+                }
+                Err(reason) => {
+                    warn!("Error obtaining usage of emoji {} on server {}: {}",
+                          emoji.pattern(),
+                          server_id,
+                          reason);
+                }
+            }
+        }
+
+        match self.db
+                  .get_emoji_rank(emoji, server_id.as_ref(), filter) {
+            Ok(Some(rank)) => {
+                fields.push(("Rank",
+                             format!("#{} of {} emoji {}",
+                                     rank.rank,
+                                     rank.num_emoji,
+                                     if server_id.is_some() {
+                                         "on this server"
+                                     } else {
+                                         "everywhere"
+                                     })));
+            }
+            Ok(None) => {}
+            Err(reason) => {
+                warn!("Error obtaining rank of emoji {}: {}", emoji.pattern(), reason);
+            }
+        }
+
+        match self.db
+                  .get_emoji_first_and_last_use(emoji, server_id.as_ref(), filter) {
+            Ok(Some((first_used, last_used))) => {
+                fields.push(("First used",
+                             format!("{}", HumanTime::from(Timespec::new(first_used, 0) -
+                                                           get_time()))));
+                fields.push(("Last used",
+                             format!("{}", HumanTime::from(Timespec::new(last_used, 0) -
+                                                           get_time()))));
+            }
+            Ok(None) => {}
+            Err(reason) => {
+                warn!("Error obtaining first and last use of emoji {}: {}",
+                      emoji.pattern(),
+                      reason);
+            }
+        }
+
+        if let Some(ref server_id) = server_id {
+            if show_users {
+                match self.db
+                          .get_emoji_top_users(emoji, Some(server_id), filter, page) {
+                    Ok(users) => {
+                        if !users.is_empty() {
+                            fields.push(("Top users", create_emoji_users_line(users)));
+                        }
+                    }
+                    Err(reason) => {
+                        warn!("Error obtaining top users of emoji {} on server {}: {}",
+                              emoji.pattern(),
+                              server_id,
+                              reason);
+                    }
+                }
+            }
+
+            match self.db
+                      .get_emoji_top_channels(emoji, server_id, filter, page) {
+                Ok(channels) => {
+                    if !channels.is_empty() {
+                        fields.push(("Top channels", create_emoji_channels_line(channels)));
+                    }
+                }
+                Err(reason) => {
+                    warn!("Error obtaining top channels of emoji {} on server {}: {}",
+                          emoji.pattern(),
+                          server_id,
+                          reason);
+                }
+            }
+        }
+
+        fields
+    }
+
+    // Describes where the message's author ranks among the users of an emoji on this server, e.g.
+    // " You've used it 12 times: #3 of 40 users on this server (top 8%)."
+    fn get_emoji_rank(&self, message: &Message, emoji: &Emoji, filter: &UsageFilter) -> String {
+        let server = self.public_text_channels
+            .get(&message.channel_id)
+            .map(|channel| channel.server_id);
+
+        match self.db
+                  .get_user_rank(&message.author.id, server.as_ref(), Some(emoji), filter) {
+            Ok(Some(rank)) => {
+                format!(" You've used it {} time{}: #{} of {} user{} {} (top {}%). :trophy:",
+                        rank.use_count,
+                        if rank.use_count == 1 { "" } else { "s" },
+                        rank.rank,
+                        rank.num_users,
+                        if rank.num_users == 1 { "" } else { "s" },
+                        if server.is_some() {
+                            "on this server"
+                        } else {
+                            "everywhere"
+                        },
+                        rank.top_percent())
+            }
+            Ok(None) => {
+                format!(" You haven't used it{}{}.",
+                        if server.is_some() { " on this server" } else { "" },
+                        filter.description())
+            }
+            Err(reason) => {
+                warn!("Error obtaining rank of user {} for emoji {}: {}",
+                      message.author.id,
+                      emoji.pattern(),
+                      reason);
+                String::new()
+            }
+        }
+    }
+
+    // Lists how many times each skin tone or gender variant of an emoji was used, e.g.
+    // ": 👍🏻 10, 👍🏽 40"
+    fn get_variant_breakdown(&self, emoji: &Emoji, filter: &UsageFilter) -> String {
+        match self.db.get_variant_usage(emoji, filter) {
+            Ok(ref variant_usage) if !variant_usage.is_empty() => {
+                let variants = variant_usage
+                    .iter()
+                    .map(|&(ref variant, count)| format!("{} {}", variant.pattern(), count))
+                    .collect::<Vec<_>>();
+
+                format!(": {}", variants.join(", "))
+            }
+            Ok(_) => String::new(),
+            Err(reason) => {
+                warn!("Error obtaining variant usage stats for emoji {}: {}",
+                      emoji.pattern(),
+                      reason);
+                String::new()
+            }
+        }
+    }
+
+    fn trend(&self, message: &Message, args: &str) -> BotLoopDisposition {
+        let (mut options, other_args) = self.stats_options(message, args);
+
+        if !options.period_specified {
+            options.filter.period = Period::Days(30);
+        }
+
+        let mut bucket = None;
+        let mut target = None;
+
+        for arg in other_args {
+            match parse_bucket(arg) {
+                Some(arg_bucket) => bucket = Some(arg_bucket),
+                None => target = Some(arg),
+            }
+        }
+
+        let bucket = bucket.unwrap_or(Bucket::for_period(&options.filter.period));
+
+        let server_id = self.public_text_channels
+            .get(&message.channel_id)
+            .map(|channel| channel.server_id);
+
+        let (scope, emoji, subject) = match target {
+            None => {
+                match server_id {
+                    Some(server_id) => {
+                        (Scope::Server(server_id), None, "On this server".to_string())
+                    }
+                    None => (Scope::Global, None, "Globally".to_string()),
+                }
+            }
+            Some(target) => {
+                match arg::get_type(target) {
+                    arg::Type::UserId(user_id) => {
+                        (Scope::User(user_id, server_id), None, format!("By <@{}>", user_id))
+                    }
+                    arg::Type::ChannelId(channel_id) => {
+                        (Scope::Channel(channel_id), None, format!("In <#{}>", channel_id))
+                    }
+                    arg::Type::RoleId(role_id) if server_id.is_some() => {
+                        (Scope::Role(role_id, server_id.unwrap()),
+                         None,
+                         format!("By <@&{}>", role_id))
+                    }
+                    _ => {
+                        match self.find_emoji(target) {
+                            Some(emoji) => {
+                                let subject = format!("Use of {}", emoji.pattern());
+                                (Scope::Global, Some(emoji), subject)
+                            }
+                            None => {
+                                self.send_response(message,
+                                                   "Please mention a user, role or channel, or use \
+                                                    an emoji that I track. :shrug:");
+                                return BotLoopDisposition::Continue;
+                            }
+                        }
+                    }
+                }
+            }
+        };
+
+        // Show the trend of all emoji usage and the trends of the top emoji,
+        // or just the trend of the emoji that was asked about
+        let mut trends = Vec::new();
+
+        match emoji {
+            Some(emoji) => trends.push((emoji.pattern().to_string(), Some(emoji.clone()))),
+            None => {
+                trends.push(("All emoji".to_string(), None));
+
+                let page = Page {
+                    number: 1,
+                    size: NUM_TREND_TOP_EMOJI as u32,
+                };
+
+                match self.db.get_top_emoji(&scope, &options.filter, &page) {
+                    Ok(top_emoji) => {
+                        for (top_emoji, _) in top_emoji {
+                            trends.push((top_emoji.pattern().to_string(), Some(top_emoji)));
+                        }
+                    }
+                    Err(reason) => {
+                        warn!("Unable to retrieve top emoji for trend ({:?}): {}", scope, reason);
+                        self.send_response(message, RESPONSE_STATS_ERR);
+                        return BotLoopDisposition::Continue;
+                    }
+                }
+            }
+        }
+
+        let mut trend_lines = Vec::new();
+
+        for (label, trend_emoji) in trends {
+            match self.db
+                      .get_usage_trend(&scope, trend_emoji.as_ref(), &options.filter, bucket) {
+                Ok(mut trend) => {
+                    if trend.len() > MAX_TREND_POINTS {
+                        let num_points_to_skip = trend.len() - MAX_TREND_POINTS;
+                        trend.drain(..num_points_to_skip);
+                    }
+
+                    if trend.iter().any(|&(_, count)| count > 0) {
+                        trend_lines.push((label, trend));
+                    }
+                }
+                Err(reason) => {
+                    warn!("Unable to retrieve usage trend ({:?}): {}", scope, reason);
+                    self.send_response(message, RESPONSE_STATS_ERR);
+                    return BotLoopDisposition::Continue;
+                }
+            }
+        }
+
+        if trend_lines.is_empty() {
+            self.send_response(message,
+                               &format!("I haven't seen any emoji usage to chart{}. :shrug:",
+                                        options.filter.description()));
+            return BotLoopDisposition::Continue;
+        }
 
-            let earth_emoji_list = [":earth_africa:", ":earth_americas:", ":earth_asia:"];
-            let earth = thread_rng().choose(&earth_emoji_list).unwrap();
+        let mut stats = format!("{} from {} to {}\n",
+                                subject,
+                                trend_lines[0].1.first().unwrap().0,
+                                trend_lines[0].1.last().unwrap().0);
 
-            let _ = self.discord
-                .send_embed(message.channel_id,
-                            &format!("<@{}>", message.author.id),
-                            |e| {
-                                e.fields(|f| {
-                                             f.field(&format!("Top used emoji globally {}", earth),
-                                                     &stats,
-                                                     false)
-                                         })
-                            });
+        for (label, trend) in trend_lines {
+            stats += &create_trend_line(&label, &trend);
         }
 
+        let _ = self.discord
+            .send_embed(message.channel_id,
+                        &format!("<@{}>", message.author.id),
+                        |e| {
+                            e.title(&format!("{} emoji usage{} :chart_with_upwards_trend:",
+                                             bucket.description(),
+                                             options.filter.description()))
+                                .description(&stats)
+                        });
+
         BotLoopDisposition::Continue
     }
 
-    fn stats_server(&self, message: &Message) -> BotLoopDisposition {
-        if self.private_channels.contains_key(&message.channel_id) {
-            self.send_response(message, RESPONSE_USE_COMMAND_IN_PUBLIC_CHANNEL);
-            return BotLoopDisposition::Continue;
-        }
+    // Shows how many emoji are used per message and who uses the most
+    fn ratio(&self, message: &Message, args: &str) -> BotLoopDisposition {
+        let (options, other_args) = self.stats_options(message, args);
 
-        let server_id = match self.public_text_channels.get(&message.channel_id) {
-            Some(channel) => channel.server_id,
-            None => {
-                warn!("Unknown public text channel ({})", message.channel_id);
-                self.send_response(message, RESPONSE_STATS_ERR);
-                return BotLoopDisposition::Continue;
+        let server_id = self.public_text_channels
+            .get(&message.channel_id)
+            .map(|channel| channel.server_id);
+
+        let (scope, subject) = match other_args.first() {
+            None | Some(&"server") | Some(&"s") => {
+                match server_id {
+                    Some(server_id) => (Scope::Server(server_id), "this server".to_string()),
+                    None => (Scope::Global, "all servers".to_string()),
+                }
+            }
+            Some(target) => {
+                match arg::get_type(target) {
+                    arg::Type::UserId(user_id) => {
+                        (Scope::User(user_id, server_id), format!("<@{}>", user_id))
+                    }
+                    arg::Type::ChannelId(channel_id) => {
+                        (Scope::Channel(channel_id), format!("<#{}>", channel_id))
+                    }
+                    arg::Type::RoleId(role_id) if server_id.is_some() => {
+                        (Scope::Role(role_id, server_id.unwrap()), format!("<@&{}>", role_id))
+                    }
+                    _ => {
+                        self.send_response(message,
+                                           "Please mention a user, role or channel. :shrug:");
+                        return BotLoopDisposition::Continue;
+                    }
+                }
             }
         };
 
-        let top_emoji = match self.db.get_server_top_emoji(&server_id) {
-            Ok(results) => results,
+        let activity = match self.db.get_activity(&scope, &options.filter) {
+            Ok(activity) => activity,
             Err(reason) => {
-                warn!("Unable to retrieve top used emoji on server ({}): {}",
-                      server_id,
-                      reason);
+                warn!("Unable to retrieve message activity ({:?}): {}", scope, reason);
                 self.send_response(message, RESPONSE_STATS_ERR);
                 return BotLoopDisposition::Continue;
             }
         };
 
-        if top_emoji.len() == 0 {
+        if activity.num_messages == 0 {
             self.send_response(message,
-                               "I've never seen anyone use any emoji on this server. :shrug:");
-        } else {
-            let top_users = match self.db.get_server_top_users(&server_id) {
-                Ok(results) => results,
+                               &format!("I haven't seen any messages{}. :shrug:",
+                                        options.filter.description()));
+            return BotLoopDisposition::Continue;
+        }
+
+        let page = self.page(message, options.page);
+        let mut fields = Vec::new();
+
+        if self.show_user_leaderboards(message) {
+            match self.db
+                      .get_most_expressive_users(&scope, &options.filter, &page) {
+                Ok(users) => {
+                    if !users.is_empty() {
+                        fields.push(("Most expressive users",
+                                     create_expressive_users_line(users)));
+                    }
+                }
                 Err(reason) => {
-                    warn!("Unable to retrieve top users on server ({}): {}",
-                          server_id,
+                    warn!("Unable to retrieve most expressive users ({:?}): {}",
+                          scope,
                           reason);
-                    self.send_response(message, RESPONSE_STATS_ERR);
-                    return BotLoopDisposition::Continue;
                 }
-            };
-
-            let user_stats = create_top_users_line(top_users);
-
-            let emoji_stats = create_emoji_usage_line(top_emoji);
+            }
+        }
 
-            let _ = self.discord
-                .send_embed(message.channel_id,
-                            &format!("<@{}>", message.author.id),
-                            |e| {
-                                e.title("Statistics for this server :chart_with_upwards_trend:")
-                                    .fields(|f| {
-                                                f.field("Top emoji", &emoji_stats, true)
-                                                    .field("Top users", &user_stats, true)
-                                            })
-                            });
+        if let Scope::Server(ref server_id) = scope {
+            match self.db
+                      .get_most_expressive_channels(server_id, &options.filter, &page) {
+                Ok(channels) => {
+                    if !channels.is_empty() {
+                        fields.push(("Most expressive channels",
+                                     create_expressive_channels_line(channels)));
+                    }
+                }
+                Err(reason) => {
+                    warn!("Unable to retrieve most expressive channels on server {}: {}",
+                          server_id,
+                          reason);
+                }
+            }
         }
 
+        let description = format!("In {}: {}", subject, create_activity_line(&activity));
+
+        let _ = self.discord
+            .send_embed(message.channel_id,
+                        &format!("<@{}>", message.author.id),
+                        |e| {
+                            e.title(&format!("Emoji per message{} :speech_balloon:",
+                                             options.filter.description()))
+                                .description(&description)
+                                .fields(|f| {
+                                    fields
+                                        .iter()
+                                        .fold(f, |f, &(name, ref value)| f.field(name, value, true))
+                                })
+                        });
+
         BotLoopDisposition::Continue
     }
 
-    fn stats_channel(&self,
-                     message: &Message,
-                     channel_id: Option<&ChannelId>)
-                     -> BotLoopDisposition {
-        if self.private_channels.contains_key(&message.channel_id) {
-            self.send_response(message, RESPONSE_USE_COMMAND_IN_PUBLIC_CHANNEL);
+    // Compares two users, two channels or two emoji side by side
+    fn compare(&self, message: &Message, args: &str) -> BotLoopDisposition {
+        let (options, other_args) = self.stats_options(message, args);
+
+        if other_args.len() != 2 {
+            self.send_response(message,
+                               "Please give two users, roles, channels or emoji to compare, e.g. \
+                                `compare #general #memes`. :scales:");
             return BotLoopDisposition::Continue;
         }
 
-        let channel_id = channel_id.unwrap_or(&message.channel_id);
-
-        let stats_description = match self.public_text_channels.get(&channel_id) {
-            Some(channel) => {
-                format!("Statistics for #{} :chart_with_upwards_trend:",
-                        channel.name)
+        let server_id = self.public_text_channels
+            .get(&message.channel_id)
+            .map(|channel| channel.server_id);
+        let page = self.page(message, options.page);
+        let title = format!("Comparison{} :scales:", options.filter.description());
+
+        let scopes = match (arg::get_type(other_args[0]), arg::get_type(other_args[1])) {
+            (arg::Type::UserId(first), arg::Type::UserId(second)) => {
+                Some((Scope::User(first, server_id),
+                      Scope::User(second, server_id),
+                      self.get_user_label(&first),
+                      self.get_user_label(&second)))
             }
-            None => "Channel statistics :chart_with_upwards_trend:".to_string(),
-        };
-
-        let top_emoji = match self.db.get_channel_top_emoji(&channel_id) {
-            Ok(results) => results,
-            Err(reason) => {
-                warn!("Unable to retrieve top used emoji on channel ({}): {}",
-                      message.channel_id,
-                      reason);
-                self.send_response(message, RESPONSE_STATS_ERR);
-                return BotLoopDisposition::Continue;
+            (arg::Type::ChannelId(first), arg::Type::ChannelId(second)) => {
+                Some((Scope::Channel(first),
+                      Scope::Channel(second),
+                      self.get_channel_label(&first),
+                      self.get_channel_label(&second)))
+            }
+            (arg::Type::RoleId(first), arg::Type::RoleId(second)) if server_id.is_some() => {
+                Some((Scope::Role(first, server_id.unwrap()),
+                      Scope::Role(second, server_id.unwrap()),
+                      self.get_role_label(&first),
+                      self.get_role_label(&second)))
             }
+            _ => None,
         };
 
-        if top_emoji.len() == 0 {
-            self.send_response(message,
-                               "I've never seen anyone use any emoji in that channel. :shrug:");
-        } else {
-            let top_users = match self.db.get_channel_top_users(&channel_id) {
-                Ok(results) => results,
+        if let Some((first, second, first_label, second_label)) = scopes {
+            let comparison = match self.db
+                      .compare_scopes(&first, &second, &options.filter, &page) {
+                Ok(comparison) => comparison,
                 Err(reason) => {
-                    warn!("Unable to retrieve top users in channel ({}): {}",
-                          channel_id,
-                          reason);
+                    warn!("Unable to compare {:?} and {:?}: {}", first, second, reason);
                     self.send_response(message, RESPONSE_STATS_ERR);
                     return BotLoopDisposition::Continue;
                 }
             };
 
-            let user_stats = create_top_users_line(top_users);
+            if comparison.first.total == 0 && comparison.second.total == 0 {
+                self.send_response(message,
+                                   &format!("I haven't seen either of them use any emoji{}. \
+                                             :shrug:",
+                                            options.filter.description()));
+                return BotLoopDisposition::Continue;
+            }
 
-            let emoji_stats = create_emoji_usage_line(top_emoji);
+            let description = format!("**{}** used {} emoji ({} different)\n\
+                                       **{}** used {} emoji ({} different)\n\
+                                       {} emoji were used by both ({:.0}% overlap)",
+                                      first_label,
+                                      comparison.first.total,
+                                      comparison.first.num_distinct,
+                                      second_label,
+                                      comparison.second.total,
+                                      comparison.second.num_distinct,
+                                      comparison.num_shared,
+                                      comparison.overlap_percent());
 
             let _ = self.discord
                 .send_embed(message.channel_id,
                             &format!("<@{}>", message.author.id),
                             |e| {
-                                e.title(&stats_description)
-                                    .fields(|f| {
-                                                f.field("Top emoji", &emoji_stats, true)
-                                                    .field("Top users", &user_stats, true)
-                                            })
+                                create_comparison_embed(e,
+                                                        &title,
+                                                        &description,
+                                                        (&first_label, &second_label),
+                                                        &comparison,
+                                                        |emoji| emoji.pattern().to_string())
                             });
+
+            return BotLoopDisposition::Continue;
         }
 
-        BotLoopDisposition::Continue
-    }
+        let (first, second) = match (self.find_emoji(other_args[0]),
+                                     self.find_emoji(other_args[1])) {
+            (Some(first), Some(second)) => (first, second),
+            _ => {
+                self.send_response(message,
+                                   "Please compare two users, two roles, two channels or two emoji \
+                                    that I track. :shrug:");
+                return BotLoopDisposition::Continue;
+            }
+        };
 
-    fn stats_user(&self, message: &Message, user_id: Option<&UserId>) -> BotLoopDisposition {
-        let user_id = user_id.unwrap_or(&message.author.id);
+        let comparison = match self.db
+                  .compare_emoji(&first, &second, server_id.as_ref(), &options.filter, &page) {
+            Ok(comparison) => comparison,
+            Err(reason) => {
+                warn!("Unable to compare {} and {}: {}",
+                      first.pattern(),
+                      second.pattern(),
+                      reason);
+                self.send_response(message, RESPONSE_STATS_ERR);
+                return BotLoopDisposition::Continue;
+            }
+        };
 
-        if *user_id == self.bot_user_id {
-            self.send_response(message, "You're so silly! :smile:");
+        if comparison.first.total == 0 && comparison.second.total == 0 {
+            self.send_response(message,
+                               &format!("I haven't seen anyone use either emoji{}. :shrug:",
+                                        options.filter.description()));
             return BotLoopDisposition::Continue;
         }
 
-        // If the bot knows which server is associated with the public text channel, get statistics
-        // for both Unicode emoji and custom emoji on the same server
-        // Otherwise, just get statistics for Unicode emoji
-        let server = match self.public_text_channels.get(&message.channel_id) {
-            Some(channel) => Some(&channel.server_id),
-            None => None,
-        };
+        let description = format!("{} was used {} time{} by {} user{}\n\
+                                   {} was used {} time{} by {} user{}\n\
+                                   {} user{} used both ({:.0}% overlap)",
+                                  first.pattern(),
+                                  comparison.first.total,
+                                  if comparison.first.total == 1 { "" } else { "s" },
+                                  comparison.first.num_distinct,
+                                  if comparison.first.num_distinct == 1 { "" } else { "s" },
+                                  second.pattern(),
+                                  comparison.second.total,
+                                  if comparison.second.total == 1 { "" } else { "s" },
+                                  comparison.second.num_distinct,
+                                  if comparison.second.num_distinct == 1 { "" } else { "s" },
+                                  comparison.num_shared,
+                                  if comparison.num_shared == 1 { "" } else { "s" },
+                                  comparison.overlap_percent());
+
+        let _ = self.discord
+            .send_embed(message.channel_id,
+                        &format!("<@{}>", message.author.id),
+                        |e| {
+                            create_comparison_embed(e,
+                                                    &title,
+                                                    &description,
+                                                    (first.pattern(), second.pattern()),
+                                                    &comparison,
+                                                    |user_name| user_name.clone())
+                        });
 
-        let user_name = match self.db.get_user_name(user_id) {
-            Ok(maybe_user_name) => {
-                match maybe_user_name {
-                    Some(user_name) => user_name,
-                    None => "(Unknown user)".to_string(),
-                }
-            }
+        BotLoopDisposition::Continue
+    }
+
+    fn get_user_label(&self, user_id: &UserId) -> String {
+        match self.db.get_user_name(user_id) {
+            Ok(Some(user_name)) => user_name,
+            Ok(None) => "(Unknown user)".to_string(),
             Err(reason) => {
                 debug!("Error retrieving user name for user ({}) from database: {}",
                        user_id,
                        reason);
                 "(Unknown user)".to_string()
             }
-        };
-
-        let stats_description = if *user_id == message.author.id {
-            "Your favourite emoji :two_hearts:".to_string()
-        } else {
-            format!("{}'s favourite emoji :two_hearts:", user_name)
-        };
+        }
+    }
 
-        let top_emoji = match self.db.get_user_top_emoji(&user_id, server) {
-            Ok(results) => results,
+    fn get_role_label(&self, role_id: &RoleId) -> String {
+        match self.db.get_role_name(role_id) {
+            Ok(Some(role_name)) => format!("@{}", role_name),
+            Ok(None) => "(Unknown role)".to_string(),
             Err(reason) => {
-                warn!("Unable to retrieve top emoji used by user {} ({}): {}",
-                      user_name,
-                      user_id,
-                      reason);
-                self.send_response(message, RESPONSE_STATS_ERR);
-                return BotLoopDisposition::Continue;
+                debug!("Error retrieving role name for role ({}) from database: {}",
+                       role_id,
+                       reason);
+                "(Unknown role)".to_string()
             }
-        };
+        }
+    }
 
-        if top_emoji.len() == 0 {
-            self.send_response(message,
-                               &format!("I've never seen <@{}> use any emoji. :shrug:", user_id));
-        } else {
-            let stats = create_emoji_usage_line(top_emoji);
+    fn get_channel_label(&self, channel_id: &ChannelId) -> String {
+        match self.public_text_channels.get(channel_id) {
+            Some(channel) => format!("#{}", channel.name),
+            None => "(Unknown channel)".to_string(),
+        }
+    }
 
-            let _ = self.discord
-                .send_embed(message.channel_id,
-                            &format!("<@{}>", message.author.id),
-                            |e| e.fields(|f| f.field(&stats_description, &stats, false)));
+    // Looks up an emoji by name, or suggests emoji with similar names
+    // Names may be more than one word, e.g. "face with tears of joy week"
+    fn stats_emoji_name(&self, message: &Message, first_word: &str, args: &str) {
+        let (_, other_args) = parse_stats_options(args);
+        let mut name = first_word.to_string();
+
+        for arg in other_args {
+            name += " ";
+            name += arg;
         }
 
-        BotLoopDisposition::Continue
+        let server_id = self.public_text_channels
+            .get(&message.channel_id)
+            .map(|channel| channel.server_id);
+
+        match self.find_emoji_by_name(&name, server_id) {
+            NameMatch::Found(emoji) => self.stats_emoji(message, &emoji, args),
+            NameMatch::Suggestions(suggestions) => {
+                let suggestions = suggestions
+                    .iter()
+                    .map(|&(ref emoji, ref name)| format!("{} `{}`", emoji.pattern(), name))
+                    .collect::<Vec<_>>();
+
+                self.send_response(message,
+                                   &format!("Did you mean {}? :thinking:",
+                                            suggestions.join(", ")));
+            }
+            NameMatch::NotFound => {
+                self.help(message);
+            }
+        }
     }
 
-    fn stats_emoji(&self, message: &Message, emoji: &Emoji) {
-        match self.db.get_emoji_usage(emoji) {
-            Ok(maybe_count) => {
-                match maybe_count {
-                    Some(count) if count > 0 => {
-                        self.send_response(message,
-                                           &format!("{} has been used {} time{}.",
-                                                   emoji.pattern(),
-                                                   count,
-                                                   if count == 1 { "" } else { "s" }));
-                    }
-                    _ => {
-                        self.send_response(message,
-                                           &format!("I've never seen anyone use {}.",
-                                                   emoji.pattern()));
-                    }
+    // Returns the tracked emoji that the text represents, if there is one
+    // The text may be the emoji itself or, if it is unambiguous, its name
+    fn find_emoji(&self, text: &str) -> Option<Emoji> {
+        // The lock is released before looking the name up, which takes it again
+        let emoji = find_emoji(&self.emoji.read().unwrap(), text);
+
+        match emoji {
+            Some(emoji) => Some(emoji),
+            None => {
+                match self.find_emoji_by_name(text, None) {
+                    NameMatch::Found(emoji) => Some(emoji),
+                    NameMatch::Suggestions(_) |
+                    NameMatch::NotFound => None,
                 }
             }
-            Err(reason) => {
-                warn!("Error obtaining emoji usage stats for emoji {}: {}",
-                      emoji.pattern(),
-                      reason);
-                self.send_response(message, RESPONSE_STATS_ERR);
-            }
         }
     }
 
+    // Unicode emoji are named in the emoji data file; custom emoji are named on their servers
+    fn find_emoji_by_name(&self, name: &str, server_id: Option<ServerId>) -> NameMatch {
+        let tracked_emoji = self.emoji.read().unwrap();
+        let custom_emoji_names = tracked_emoji
+            .iter()
+            .filter_map(|emoji| match *emoji {
+                            Emoji::Custom(ref custom_emoji) if !custom_emoji.is_external() => {
+                                Some((emoji, custom_emoji.name.as_str()))
+                            }
+                            _ => None,
+                        });
+        let unicode_emoji_names = self.unicode_emoji_names
+            .iter()
+            .map(|(emoji, name)| (emoji, name.as_str()));
+
+        find_emoji_by_name(custom_emoji_names.chain(unicode_emoji_names), name, server_id)
+    }
+
     fn respond_auth_required(&self, message: &Message) {
         self.send_response(message, "Please authenticate first. :lock:");
     }
+
+    // Pairs are emoji typed into the same message, which reactions never are
+    fn respond_reactions_not_paired(&self, message: &Message) {
+        self.send_response(message,
+                           "Pairs and combos only count emoji typed into messages, not \
+                            reactions. :shrug:");
+    }
 }
 
 fn create_emoji_usage_line(emoji_usage: Vec<(Emoji, i64)>) -> String {
@@ -1032,6 +3595,80 @@ fn create_emoji_usage_line(emoji_usage: Vec<(Emoji, i64)>) -> String {
     stats
 }
 
+fn create_leaderboard_embed(e: EmbedBuilder,
+                            leaderboard: &Leaderboard,
+                            fields: &[(String, String, bool)],
+                            paginated: bool)
+                            -> EmbedBuilder {
+    let e = match leaderboard.kind {
+        LeaderboardKind::TopEmojiAndUsers(_) => e.title(&leaderboard.title),
+        _ => e,
+    };
+
+    let e = match leaderboard.description {
+        Some(ref description) => e.description(description),
+        None => e,
+    };
+
+    let e = e.fields(|f| {
+                         fields
+                             .iter()
+                             .fold(f, |f, &(ref name, ref value, inline)| {
+                                 f.field(name, value, inline)
+                             })
+                     });
+
+    if paginated {
+        e.footer(|f| f.text(&format!("Page {}", leaderboard.page.number)))
+    } else {
+        e
+    }
+}
+
+fn create_least_used_line(emoji_usage: Vec<(Emoji, i64, i64)>) -> String {
+    let mut stats = String::new();
+
+    for (emoji, count, first_seen) in emoji_usage {
+        let age = HumanTime::from(Timespec::new(first_seen, 0) - get_time());
+
+        stats += &format!("{} used {} time{} (first seen {})\n",
+                          emoji.pattern(),
+                          count,
+                          if count == 1 { "" } else { "s" },
+                          age);
+    }
+
+    stats
+}
+
+fn create_paired_emoji_line(emoji: &Emoji, paired_emoji: Vec<(Emoji, i64)>) -> String {
+    let mut stats = String::new();
+
+    for (paired_emoji, count) in paired_emoji {
+        stats += &format!("{} {} in {} message{}\n",
+                          emoji.pattern(),
+                          paired_emoji.pattern(),
+                          count,
+                          if count == 1 { "" } else { "s" });
+    }
+
+    stats
+}
+
+fn create_emoji_pairs_line(pairs: Vec<(Emoji, Emoji, i64)>) -> String {
+    let mut stats = String::new();
+
+    for (first, second, count) in pairs {
+        stats += &format!("{} {} in {} message{}\n",
+                          first.pattern(),
+                          second.pattern(),
+                          count,
+                          if count == 1 { "" } else { "s" });
+    }
+
+    stats
+}
+
 fn create_top_users_line(emoji_usage: Vec<(String, i64)>) -> String {
     let mut stats = String::new();
 
@@ -1041,3 +3678,175 @@ fn create_top_users_line(emoji_usage: Vec<(String, i64)>) -> String {
 
     stats
 }
+
+// Describes a setting's value, or its default if it hasn't been set
+fn describe_setting(settings: &ServerSettings,
+                    setting: Setting,
+                    default_page_size: u32,
+                    default_prefix: Option<&String>)
+                    -> String {
+    match settings.get(setting) {
+        Some(value) if !value.is_empty() => format!("`{}`", value),
+        Some(_) => "none".to_string(),
+        None => {
+            match setting {
+                Setting::PageSize => format!("the default ({})", default_page_size),
+                Setting::Period => "the default (`ever`)".to_string(),
+                Setting::UserLeaderboards => "the default (`on`)".to_string(),
+                Setting::Prefix => {
+                    match default_prefix {
+                        Some(prefix) => format!("the default (`{}`)", prefix),
+                        None => "none".to_string(),
+                    }
+                }
+                Setting::DisabledCommands => "none".to_string(),
+            }
+        }
+    }
+}
+
+// The highlighted server's name is shown in bold
+fn create_top_servers_line(servers: Vec<(ServerId, String, i64)>,
+                           highlighted_server_id: Option<&ServerId>,
+                           offset: i64)
+                           -> String {
+    let mut stats = String::new();
+
+    for (position, (server_id, server_name, count)) in servers.into_iter().enumerate() {
+        let server_name = if Some(&server_id) == highlighted_server_id {
+            format!("**{}**", server_name)
+        } else {
+            server_name
+        };
+
+        stats += &format!("#{} {} used {} emoji\n",
+                          offset + position as i64 + 1,
+                          server_name,
+                          count)
+    }
+
+    stats
+}
+
+fn create_server_vs_global_line(emoji_usage: Vec<(Emoji, i64, i64)>) -> String {
+    let mut stats = String::new();
+
+    for (emoji, count, global_rank) in emoji_usage {
+        stats += &format!("{} used {} time{} here, #{} on all servers\n",
+                          emoji.pattern(),
+                          count,
+                          if count == 1 { "" } else { "s" },
+                          global_rank)
+    }
+
+    stats
+}
+
+fn create_emoji_users_line(users: Vec<(String, i64)>) -> String {
+    let mut stats = String::new();
+
+    for (user_name, count) in users {
+        stats += &format!("{} used it {} time{}\n",
+                          user_name,
+                          count,
+                          if count == 1 { "" } else { "s" })
+    }
+
+    stats
+}
+
+fn create_emoji_channels_line(channels: Vec<(ChannelId, i64)>) -> String {
+    let mut stats = String::new();
+
+    for (channel_id, count) in channels {
+        stats += &format!("<#{}> {} time{}\n",
+                          channel_id,
+                          count,
+                          if count == 1 { "" } else { "s" })
+    }
+
+    stats
+}
+
+fn create_comparison_embed<T, F>(e: EmbedBuilder,
+                                 title: &str,
+                                 description: &str,
+                                 labels: (&str, &str),
+                                 comparison: &Comparison<T>,
+                                 item_label: F)
+                                 -> EmbedBuilder
+    where F: Fn(&T) -> String
+{
+    let mut first = String::new();
+    let mut second = String::new();
+    let mut shared = String::new();
+
+    for &(ref item, count) in &comparison.first.top {
+        first += &format!("{} {}\n", item_label(item), count);
+    }
+
+    for &(ref item, count) in &comparison.second.top {
+        second += &format!("{} {}\n", item_label(item), count);
+    }
+
+    for &(ref item, first_count, second_count) in &comparison.shared {
+        shared += &format!("{} {} vs {}\n", item_label(item), first_count, second_count);
+    }
+
+    let fields = [(labels.0, first), (labels.1, second), ("Both", shared)];
+
+    e.title(title)
+        .description(description)
+        .fields(|f| {
+                    fields
+                        .iter()
+                        .filter(|&&(_, ref value)| !value.is_empty())
+                        .fold(f, |f, &(name, ref value)| f.field(name, value, true))
+                })
+}
+
+fn create_activity_line(activity: &ActivityStats) -> String {
+    format!("{:.2} emoji per message; {:.1}% of {} message{} contained emoji.",
+            activity.emoji_per_message(),
+            activity.emoji_message_percent(),
+            activity.num_messages,
+            if activity.num_messages == 1 { "" } else { "s" })
+}
+
+fn create_expressive_users_line(users: Vec<(String, f64)>) -> String {
+    let mut stats = String::new();
+
+    for (user_name, ratio) in users {
+        stats += &format!("{} uses {:.2} emoji per message\n", user_name, ratio)
+    }
+
+    stats
+}
+
+fn create_expressive_channels_line(channels: Vec<(ChannelId, f64)>) -> String {
+    let mut stats = String::new();
+
+    for (channel_id, ratio) in channels {
+        stats += &format!("<#{}> has {:.2} emoji per message\n", channel_id, ratio)
+    }
+
+    stats
+}
+
+fn create_trend_line(label: &str, trend: &[(String, i64)]) -> String {
+    let counts = trend.iter().map(|&(_, count)| count).collect::<Vec<_>>();
+    let total = counts.iter().sum::<i64>();
+    let &(ref peak_bucket, peak_count) = trend
+        .iter()
+        .rev()
+        .max_by_key(|&&(_, count)| count)
+        .unwrap();
+
+    format!("`{}` {} used {} time{} (peak {} on {})\n",
+            create_sparkline(&counts),
+            label,
+            total,
+            if total == 1 { "" } else { "s" },
+            peak_count,
+            peak_bucket)
+}
@@ -2,12 +2,13 @@ extern crate discord;
 
 use arg;
 use self::discord::Discord;
-use self::discord::model::{ChannelId, LiveServer, Message, ServerId, ServerInfo};
+use self::discord::model::{ChannelId, LiveServer, Message, ServerId, ServerInfo, UserId};
 
 pub struct BasicServerInfo {
     pub id: ServerId,
     pub name: String,
     pub icon: Option<String>,
+    pub owner_id: Option<UserId>, // Not included in ServerInfo
 }
 
 impl From<LiveServer> for BasicServerInfo {
@@ -16,6 +17,7 @@ impl From<LiveServer> for BasicServerInfo {
             id: live_server.id,
             name: live_server.name,
             icon: live_server.icon,
+            owner_id: Some(live_server.owner_id),
         }
     }
 }
@@ -26,6 +28,7 @@ impl From<ServerInfo> for BasicServerInfo {
             id: server_info.id,
             name: server_info.name,
             icon: server_info.icon,
+            owner_id: None,
         }
     }
 }
@@ -64,6 +67,31 @@ pub fn extract_preceding_arg<'a>(s: &'a str) -> (Option<arg::Type<'a>>, &str) {
     (None, s)
 }
 
+// If s begins with the prefix, ignoring case, returns the rest of s
+// A prefix ending in a letter or digit must be followed by whitespace, so that the prefix "es"
+// doesn't match "essentially"
+pub fn extract_prefixed_command<'a>(s: &'a str, prefix: &str) -> Option<&'a str> {
+    let s = s.trim_left();
+
+    if prefix.is_empty() || s.len() < prefix.len() || !s.is_char_boundary(prefix.len()) {
+        return None;
+    }
+
+    let (maybe_prefix, rest) = s.split_at(prefix.len());
+
+    if maybe_prefix.to_lowercase() != prefix.to_lowercase() {
+        return None;
+    }
+
+    let needs_separator = prefix.chars().last().map_or(false, char::is_alphanumeric);
+
+    if needs_separator && !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+
+    Some(rest)
+}
+
 // Removes all characters not used in commands from the beginning of a &str
 pub fn remove_non_command_characters(s: &str) -> &str {
     let mut s_chars = s.chars();
@@ -103,9 +131,27 @@ pub fn extract_first_word(s: &str) -> (&str, &str) {
     (first_word, the_rest)
 }
 
+// Renders the values as a sparkline, scaled so that the largest value is a full block
+// Zero is always the lowest block and any other value is at least the second-lowest
+pub fn create_sparkline(values: &[i64]) -> String {
+    const BLOCKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+    let max = values.iter().cloned().max().unwrap_or(0);
+
+    values
+        .iter()
+        .map(|&value| if value <= 0 || max <= 0 {
+                 BLOCKS[0]
+             } else {
+                 BLOCKS[((value * 7 + max - 1) / max) as usize]
+             })
+        .collect()
+}
+
 mod tests {
     #[allow(unused_imports)]
-    use super::{extract_preceding_arg, remove_non_command_characters, extract_first_word};
+    use super::{extract_preceding_arg, extract_prefixed_command, remove_non_command_characters,
+                extract_first_word, create_sparkline};
     #[allow(unused_imports)]
     use super::discord::model::{ChannelId, EmojiId, RoleId, UserId};
     #[allow(unused_imports)]
@@ -141,6 +187,25 @@ mod tests {
         test!("  <:emoji:123>  abc  " => (EmojiId(123), "  abc  "));
     }
 
+    #[test]
+    fn test_extract_prefixed_command() {
+        macro_rules! test {
+            ($test_string:expr, $prefix:expr => $expected_value:expr) => {
+                assert_eq!(extract_prefixed_command($test_string, $prefix), $expected_value);
+            };
+        }
+
+        test!("!es server", "!es" => Some(" server"));
+        test!("  !ES server", "!es" => Some(" server"));
+        test!("!es", "!es" => Some(""));
+        test!("!esserver", "!es" => None);
+        test!("!server", "!" => Some("server"));
+        test!("!e", "!es" => None);
+        test!("server", "!es" => None);
+        test!("!es server", "" => None);
+        test!("é server", "!" => None);
+    }
+
     #[test]
     fn test_remove_non_command_characters() {
         macro_rules! test {
@@ -171,4 +236,20 @@ mod tests {
         test!("ab cd" => ("ab", "cd"));
         test!("ab  cd \t " => ("ab", "cd \t "));
     }
+
+    #[test]
+    fn test_create_sparkline() {
+        macro_rules! test {
+            ($values:expr => $expected_value:expr) => {
+                assert_eq!(create_sparkline(&$values), $expected_value);
+            };
+        }
+
+        test!([] => "");
+        test!([0, 0] => "▁▁");
+        test!([0, 1, 2, 3, 4, 5, 6, 7] => "▁▂▃▄▅▆▇█");
+        test!([1, 1000] => "▂█");
+        test!([5] => "█");
+        test!([-1, 2] => "▁█");
+    }
 }
@@ -0,0 +1,206 @@
+extern crate discord;
+
+use std::collections::{HashMap, HashSet};
+use super::model::{CustomEmoji, Emoji};
+use super::tokenizer::{tokenize, Token};
+
+use self::discord::model::EmojiId;
+
+const EMOJI_PRESENTATION_SELECTOR: char = '\u{FE0F}';
+const TEXT_PRESENTATION_SELECTOR: char = '\u{FE0E}';
+
+// Returns each of the emoji that appears in the text along with the number of times it appears
+// Emoji are returned in the order they first appear
+pub fn count_emoji(emoji: &HashSet<Emoji>, text: &str) -> Vec<(Emoji, i32)> {
+    let mut emoji_counts: Vec<(Emoji, i32)> = Vec::new();
+    let mut positions: HashMap<Emoji, usize> = HashMap::new();
+
+    for token in tokenize(text) {
+        if let Some(emoji) = find_token(emoji, &token) {
+            if let Some(&index) = positions.get(&emoji) {
+                emoji_counts[index].1 += 1;
+                continue;
+            }
+
+            positions.insert(emoji.clone(), emoji_counts.len());
+            emoji_counts.push((emoji, 1));
+        }
+    }
+
+    emoji_counts
+}
+
+// Returns the emoji if the text consists of a single known emoji or a variant of one
+pub fn find_emoji(emoji: &HashSet<Emoji>, text: &str) -> Option<Emoji> {
+    let tokens = tokenize(text);
+
+    if tokens.len() != 1 {
+        return None;
+    }
+
+    let whole_text = match tokens[0] {
+        Token::Custom { .. } => text.starts_with('<') && text.ends_with('>'),
+        Token::Unicode(sequence) => sequence == text,
+    };
+
+    if whole_text {
+        find_token(emoji, &tokens[0])
+    } else {
+        None
+    }
+}
+
+// Finds the known emoji corresponding to a token
+// Any well-formed custom emoji is returned, even if it isn't known, since it may be from a server
+// the bot isn't on
+// Variation selectors are optional, so e.g. "❤️" is counted as "❤" if only the latter is known
+// Skin tone and gender variants of known emoji are returned as they are, e.g. "👍🏽" if "👍" is known
+fn find_token(emoji: &HashSet<Emoji>, token: &Token) -> Option<Emoji> {
+    match *token {
+        Token::Custom { name, id, animated } => {
+            // Custom emoji are identified by ID alone
+            let token_emoji = Emoji::Custom(CustomEmoji::new(None, EmojiId(id), name, animated));
+            let known_emoji = emoji.get(&token_emoji);
+
+            match known_emoji {
+                // Discord doesn't say which server emoji are animated, so an emoji is only known
+                // to be animated once it has been used
+                Some(&Emoji::Custom(ref known_emoji)) if animated && !known_emoji.animated => {
+                    Some(Emoji::Custom(CustomEmoji::new(known_emoji.server_id,
+                                                        known_emoji.id,
+                                                        known_emoji.name.clone(),
+                                                        true)))
+                }
+                Some(known_emoji) => Some(known_emoji.clone()),
+                // Emoji from servers the bot isn't on are counted as external emoji
+                None => Some(token_emoji),
+            }
+        }
+        Token::Unicode(sequence) => {
+            let exact = Emoji::Unicode(sequence.to_string());
+
+            if emoji.contains(&exact) {
+                return Some(exact);
+            }
+
+            let without_selectors = sequence
+                .chars()
+                .filter(|&c| c != EMOJI_PRESENTATION_SELECTOR && c != TEXT_PRESENTATION_SELECTOR)
+                .collect::<String>();
+            let without_selectors = Emoji::Unicode(without_selectors);
+
+            if emoji.contains(&without_selectors) {
+                return Some(without_selectors);
+            }
+
+            match exact.base() {
+                Some(ref base) if emoji.contains(base) => Some(exact),
+                _ => None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate discord;
+
+    use std::collections::HashSet;
+    use super::{count_emoji, find_emoji, CustomEmoji, Emoji};
+
+    use self::discord::model::{EmojiId, ServerId};
+
+    fn unicode(emoji: &str) -> Emoji {
+        Emoji::Unicode(emoji.to_string())
+    }
+
+    fn custom(server_id: Option<u64>, id: u64, name: &str, animated: bool) -> Emoji {
+        Emoji::Custom(CustomEmoji::new(server_id.map(ServerId), EmojiId(id), name, animated))
+    }
+
+    fn known_emoji() -> HashSet<Emoji> {
+        let mut emoji = HashSet::new();
+        emoji.insert(unicode("❤"));
+        emoji.insert(unicode("👍"));
+        emoji.insert(unicode("1️⃣"));
+        emoji.insert(custom(Some(10), 1, "thonk", false));
+        emoji
+    }
+
+    // Custom emoji are equal if their IDs are, so the server and pattern are compared instead
+    fn custom_details(emoji: Option<Emoji>) -> Option<(Option<ServerId>, String)> {
+        match emoji {
+            Some(Emoji::Custom(emoji)) => Some((emoji.server_id, emoji.pattern)),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn count_unicode() {
+        let emoji = known_emoji();
+
+        assert!(count_emoji(&emoji, "").is_empty());
+        assert!(count_emoji(&emoji, "no emoji here").is_empty());
+        assert_eq!(count_emoji(&emoji, "👍❤👍"), vec![(unicode("👍"), 2), (unicode("❤"), 1)]);
+        assert!(count_emoji(&emoji, "😀").is_empty());
+    }
+
+    #[test]
+    fn count_variation_selectors() {
+        let emoji = known_emoji();
+
+        assert_eq!(count_emoji(&emoji, "❤\u{FE0F} ❤\u{FE0E} ❤"), vec![(unicode("❤"), 3)]);
+        assert_eq!(count_emoji(&emoji, "1️⃣"), vec![(unicode("1️⃣"), 1)]);
+    }
+
+    #[test]
+    fn count_variants() {
+        let emoji = known_emoji();
+
+        assert_eq!(count_emoji(&emoji, "👍🏽 👍🏽 👍"),
+                   vec![(unicode("👍🏽"), 2), (unicode("👍"), 1)]);
+        assert!(count_emoji(&emoji, "😀🏽").is_empty());
+    }
+
+    #[test]
+    fn count_custom() {
+        let emoji = known_emoji();
+
+        // Animated and static uses of the same emoji are counted together
+        assert_eq!(count_emoji(&emoji, "<:thonk:1> <a:thonk:1>"),
+                   vec![(custom(Some(10), 1, "thonk", false), 2)]);
+        assert_eq!(count_emoji(&emoji, "<:other:2>"),
+                   vec![(custom(None, 2, "other", false), 1)]);
+    }
+
+    #[test]
+    fn find_unicode() {
+        let emoji = known_emoji();
+
+        assert_eq!(find_emoji(&emoji, "❤"), Some(unicode("❤")));
+        assert_eq!(find_emoji(&emoji, "❤\u{FE0F}"), Some(unicode("❤")));
+        assert_eq!(find_emoji(&emoji, "❤\u{FE0E}"), Some(unicode("❤")));
+        assert_eq!(find_emoji(&emoji, "👍🏽"), Some(unicode("👍🏽")));
+        assert_eq!(find_emoji(&emoji, "😀"), None);
+        assert_eq!(find_emoji(&emoji, "❤❤"), None);
+        assert_eq!(find_emoji(&emoji, "a❤"), None);
+        assert_eq!(find_emoji(&emoji, "❤ "), None);
+        assert_eq!(find_emoji(&emoji, ""), None);
+    }
+
+    #[test]
+    fn find_custom() {
+        let emoji = known_emoji();
+
+        assert_eq!(custom_details(find_emoji(&emoji, "<:thonk:1>")),
+                   Some((Some(ServerId(10)), "<:thonk:1>".to_string())));
+        // The known emoji is returned as animated once it is used as an animated emoji
+        assert_eq!(custom_details(find_emoji(&emoji, "<a:thonk:1>")),
+                   Some((Some(ServerId(10)), "<a:thonk:1>".to_string())));
+        // Unknown custom emoji are external
+        assert_eq!(custom_details(find_emoji(&emoji, "<a:other:2>")),
+                   Some((None, "<a:other:2>".to_string())));
+        assert_eq!(find_emoji(&emoji, "a<:thonk:1>"), None);
+        assert_eq!(find_emoji(&emoji, "<:thonk:1><:thonk:1>"), None);
+    }
+}
@@ -1,22 +1,35 @@
 extern crate discord;
 extern crate postgres;
 
-use self::discord::model::{ChannelId, MessageId, PublicChannel, ServerId, User, UserId};
-use super::model::{Emoji, CustomEmoji};
-use postgres::rows::Rows;
+use self::discord::model::{ChannelId, EmojiId, MessageId, PublicChannel, Role, RoleId, ServerId,
+                           User, UserId};
+use super::emoji_data::UnicodeEmoji;
+use super::model::{ActivityStats, BackfillProgress, Bucket, Comparison, Emoji, EmojiRank,
+                   CustomEmoji, Exclusion, Page, Scope, UsageFilter, UserRank,
+                   ANONYMOUS_USER_ID};
+use postgres::params::ConnectParams;
+use postgres::rows::{Row, Rows};
+
+// Users and channels with fewer messages than this aren't ranked by emoji per message, so that
+// a single message full of emoji doesn't top the list
+const MIN_EXPRESSIVE_MESSAGES: i64 = 10;
 
 pub struct Database {
     conn: postgres::Connection,
+    params: ConnectParams,
 }
 
 impl Database {
-    pub fn new<T>(params: T) -> postgres::Result<Database>
-        where T: postgres::params::IntoConnectParams
-    {
-        let conn = postgres::Connection::connect(params, postgres::TlsMode::None)?;
+    pub fn new(params: ConnectParams) -> postgres::Result<Database> {
+        let conn = postgres::Connection::connect(params.clone(), postgres::TlsMode::None)?;
         create_tables(&conn)?;
 
-        Ok(Database { conn })
+        Ok(Database { conn, params })
+    }
+
+    // The parameters used to connect to the database, so that other threads can connect too
+    pub fn connect_params(&self) -> ConnectParams {
+        self.params.clone()
     }
 
     pub fn add_channel(&self, channel: &PublicChannel) -> postgres::Result<()> {
@@ -35,6 +48,245 @@ impl Database {
         Ok(())
     }
 
+    pub fn add_server(&self, server_id: &ServerId, name: &str) -> postgres::Result<()> {
+        const QUERY_INSERT_SERVER: &str = r#"
+        INSERT INTO server (id, name)
+        VALUES ($1, $2)
+        ON CONFLICT (id) DO UPDATE
+            SET name = excluded.name;"#;
+
+        self.conn
+            .execute(QUERY_INSERT_SERVER, &[&(server_id.0 as i64), &name])?;
+
+        Ok(())
+    }
+
+    // Servers are only ranked against each other once they opt in
+    pub fn set_server_ranked(&self, server_id: &ServerId, ranked: bool) -> postgres::Result<()> {
+        const QUERY_UPDATE_SERVER_RANKED: &str = r#"
+        UPDATE server
+        SET ranked = $2
+        WHERE id = $1;"#;
+
+        self.conn
+            .execute(QUERY_UPDATE_SERVER_RANKED, &[&(server_id.0 as i64), &ranked])?;
+
+        Ok(())
+    }
+
+    pub fn is_server_ranked(&self, server_id: &ServerId) -> postgres::Result<bool> {
+        const QUERY_SELECT_SERVER_RANKED: &str = r#"
+        SELECT ranked
+        FROM server
+        WHERE id = $1;"#;
+
+        let result = self.conn
+            .query(QUERY_SELECT_SERVER_RANKED, &[&(server_id.0 as i64)])?;
+
+        match result.iter().next() {
+            Some(row) => Ok(row.get::<usize, bool>(0)),
+            None => Ok(false),
+        }
+    }
+
+    // Returns every server's settings as (server ID, setting name, value)
+    pub fn get_server_settings(&self) -> postgres::Result<Vec<(ServerId, String, String)>> {
+        const QUERY_SELECT_SERVER_SETTINGS: &str = r#"
+        SELECT server_id, name, value
+        FROM server_settings;"#;
+
+        let result = self.conn.query(QUERY_SELECT_SERVER_SETTINGS, &[])?;
+
+        Ok(result
+               .iter()
+               .map(|row| {
+                        (ServerId(row.get::<usize, i64>(0) as u64),
+                         row.get::<usize, String>(1),
+                         row.get::<usize, String>(2))
+                    })
+               .collect())
+    }
+
+    // Returns the value of a setting on the server the channel belongs to, if it has been set
+    pub fn get_channel_server_setting(&self,
+                                      channel_id: &ChannelId,
+                                      name: &str)
+                                      -> postgres::Result<Option<String>> {
+        const QUERY_SELECT_CHANNEL_SERVER_SETTING: &str = r#"
+        SELECT s.value
+        FROM server_settings s
+            INNER JOIN channel c ON s.server_id = c.server_id
+        WHERE c.id = $1 AND s.name = $2;"#;
+
+        let result = self.conn
+            .query(QUERY_SELECT_CHANNEL_SERVER_SETTING,
+                   &[&(channel_id.0 as i64), &name])?;
+
+        Ok(result.iter().next().map(|row| row.get::<usize, String>(0)))
+    }
+
+    pub fn set_server_setting(&self,
+                              server_id: &ServerId,
+                              name: &str,
+                              value: &str)
+                              -> postgres::Result<()> {
+        const QUERY_INSERT_SERVER_SETTING: &str = r#"
+        INSERT INTO server_settings (server_id, name, value)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (server_id, name) DO UPDATE
+            SET value = excluded.value;"#;
+
+        self.conn
+            .execute(QUERY_INSERT_SERVER_SETTING,
+                     &[&(server_id.0 as i64), &name, &value])?;
+
+        Ok(())
+    }
+
+    pub fn remove_server_setting(&self, server_id: &ServerId, name: &str) -> postgres::Result<()> {
+        const QUERY_REMOVE_SERVER_SETTING: &str = r#"
+        DELETE FROM server_settings
+        WHERE server_id = $1 AND name = $2;"#;
+
+        self.conn
+            .execute(QUERY_REMOVE_SERVER_SETTING, &[&(server_id.0 as i64), &name])?;
+
+        Ok(())
+    }
+
+    // Returns every excluded channel as (channel ID, server ID, exclusion)
+    pub fn get_excluded_channels(&self)
+                                 -> postgres::Result<Vec<(ChannelId, ServerId, Exclusion)>> {
+        const QUERY_SELECT_EXCLUDED_CHANNELS: &str = r#"
+        SELECT channel_id, server_id, from_tracking, from_leaderboards
+        FROM excluded_channel;"#;
+
+        let result = self.conn.query(QUERY_SELECT_EXCLUDED_CHANNELS, &[])?;
+
+        Ok(result
+               .iter()
+               .map(|row| {
+                        (ChannelId(row.get::<usize, i64>(0) as u64),
+                         ServerId(row.get::<usize, i64>(1) as u64),
+                         Exclusion {
+                             tracking: row.get::<usize, bool>(2),
+                             leaderboards: row.get::<usize, bool>(3),
+                         })
+                    })
+               .collect())
+    }
+
+    // Returns every excluded user as (server ID, user ID, exclusion)
+    pub fn get_excluded_users(&self) -> postgres::Result<Vec<(ServerId, UserId, Exclusion)>> {
+        const QUERY_SELECT_EXCLUDED_USERS: &str = r#"
+        SELECT server_id, user_id, from_tracking, from_leaderboards
+        FROM excluded_user;"#;
+
+        let result = self.conn.query(QUERY_SELECT_EXCLUDED_USERS, &[])?;
+
+        Ok(result
+               .iter()
+               .map(|row| {
+                        (ServerId(row.get::<usize, i64>(0) as u64),
+                         UserId(row.get::<usize, i64>(1) as u64),
+                         Exclusion {
+                             tracking: row.get::<usize, bool>(2),
+                             leaderboards: row.get::<usize, bool>(3),
+                         })
+                    })
+               .collect())
+    }
+
+    // An exclusion from nothing removes the channel from the exclusion list
+    pub fn set_channel_exclusion(&self,
+                                 channel_id: &ChannelId,
+                                 server_id: &ServerId,
+                                 exclusion: &Exclusion)
+                                 -> postgres::Result<()> {
+        const QUERY_INSERT_EXCLUDED_CHANNEL: &str = r#"
+        INSERT INTO excluded_channel (channel_id, server_id, from_tracking, from_leaderboards)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (channel_id) DO UPDATE
+            SET from_tracking = excluded.from_tracking,
+                from_leaderboards = excluded.from_leaderboards;"#;
+        const QUERY_REMOVE_EXCLUDED_CHANNEL: &str = r#"
+        DELETE FROM excluded_channel
+        WHERE channel_id = $1;"#;
+
+        if exclusion.tracking || exclusion.leaderboards {
+            self.conn
+                .execute(QUERY_INSERT_EXCLUDED_CHANNEL,
+                         &[&(channel_id.0 as i64),
+                           &(server_id.0 as i64),
+                           &exclusion.tracking,
+                           &exclusion.leaderboards])?;
+        } else {
+            self.conn
+                .execute(QUERY_REMOVE_EXCLUDED_CHANNEL, &[&(channel_id.0 as i64)])?;
+        }
+
+        Ok(())
+    }
+
+    // An exclusion from nothing removes the user from the server's exclusion list
+    pub fn set_user_exclusion(&self,
+                              server_id: &ServerId,
+                              user_id: &UserId,
+                              exclusion: &Exclusion)
+                              -> postgres::Result<()> {
+        const QUERY_INSERT_EXCLUDED_USER: &str = r#"
+        INSERT INTO excluded_user (server_id, user_id, from_tracking, from_leaderboards)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (server_id, user_id) DO UPDATE
+            SET from_tracking = excluded.from_tracking,
+                from_leaderboards = excluded.from_leaderboards;"#;
+        const QUERY_REMOVE_EXCLUDED_USER: &str = r#"
+        DELETE FROM excluded_user
+        WHERE server_id = $1 AND user_id = $2;"#;
+
+        if exclusion.tracking || exclusion.leaderboards {
+            self.conn
+                .execute(QUERY_INSERT_EXCLUDED_USER,
+                         &[&(server_id.0 as i64),
+                           &(user_id.0 as i64),
+                           &exclusion.tracking,
+                           &exclusion.leaderboards])?;
+        } else {
+            self.conn
+                .execute(QUERY_REMOVE_EXCLUDED_USER,
+                         &[&(server_id.0 as i64), &(user_id.0 as i64)])?;
+        }
+
+        Ok(())
+    }
+
+    // Returns whether emoji used by the user in the channel shouldn't be recorded
+    // The channel must already have been added
+    pub fn is_excluded_from_tracking(&self,
+                                     channel_id: &ChannelId,
+                                     user_id: &UserId)
+                                     -> postgres::Result<bool> {
+        const QUERY_GET_EXCLUDED_FROM_TRACKING: &str = r#"
+        SELECT EXISTS (SELECT 1
+                       FROM excluded_channel xc
+                       WHERE xc.channel_id = c.id AND xc.from_tracking)
+            OR EXISTS (SELECT 1
+                       FROM excluded_user xu
+                       WHERE xu.server_id = c.server_id AND xu.user_id = $2
+                           AND xu.from_tracking)
+        FROM channel c
+        WHERE c.id = $1;"#;
+
+        let result = self.conn
+            .query(QUERY_GET_EXCLUDED_FROM_TRACKING,
+                   &[&(channel_id.0 as i64), &(user_id.0 as i64)])?;
+
+        match result.iter().next() {
+            Some(row) => Ok(row.get::<usize, bool>(0)),
+            None => Ok(false),
+        }
+    }
+
     pub fn add_user(&self, user: &User) -> postgres::Result<()> {
         const QUERY_INSERT_USER: &str = r#"
         INSERT INTO user_ (id, name, discriminator)
@@ -52,31 +304,325 @@ impl Database {
         Ok(())
     }
 
-    pub fn add_emoji(&self, emoji: &Emoji, server_id: Option<&ServerId>) -> postgres::Result<()> {
-        const QUERY_INSERT_CUSTOM_EMOJI: &str = r#"
-        INSERT INTO emoji (server_id, id, name, is_custom_emoji)
-        VALUES ($1, $2, $3, TRUE)
+    pub fn get_opted_out_users(&self) -> postgres::Result<Vec<UserId>> {
+        const QUERY_SELECT_OPTED_OUT_USERS: &str = r#"
+        SELECT user_id
+        FROM opted_out_user;"#;
+
+        let result = self.conn.query(QUERY_SELECT_OPTED_OUT_USERS, &[])?;
+
+        Ok(result
+               .iter()
+               .map(|row| UserId(row.get::<usize, i64>(0) as u64))
+               .collect())
+    }
+
+    pub fn is_user_opted_out(&self, user_id: &UserId) -> postgres::Result<bool> {
+        const QUERY_GET_USER_OPTED_OUT: &str = r#"
+        SELECT user_id
+        FROM opted_out_user
+        WHERE user_id = $1;"#;
+
+        let result = self.conn
+            .query(QUERY_GET_USER_OPTED_OUT, &[&(user_id.0 as i64)])?;
+
+        Ok(result.len() != 0)
+    }
+
+    // Opted-out users' emoji are recorded against ANONYMOUS_USER_ID rather than the user
+    pub fn set_user_opted_out(&self, user_id: &UserId, opted_out: bool) -> postgres::Result<()> {
+        const QUERY_INSERT_OPTED_OUT_USER: &str = r#"
+        INSERT INTO opted_out_user (user_id)
+        VALUES ($1)
+        ON CONFLICT (user_id) DO NOTHING;"#;
+        const QUERY_REMOVE_OPTED_OUT_USER: &str = r#"
+        DELETE FROM opted_out_user
+        WHERE user_id = $1;"#;
+
+        self.conn
+            .execute(if opted_out {
+                         QUERY_INSERT_OPTED_OUT_USER
+                     } else {
+                         QUERY_REMOVE_OPTED_OUT_USER
+                     },
+                     &[&(user_id.0 as i64)])?;
+
+        Ok(())
+    }
+
+    // Returns the users who authenticated as bot administrators with the password
+    pub fn get_bot_admins(&self) -> postgres::Result<Vec<UserId>> {
+        const QUERY_SELECT_BOT_ADMINS: &str = r#"
+        SELECT user_id
+        FROM bot_admin;"#;
+
+        let result = self.conn.query(QUERY_SELECT_BOT_ADMINS, &[])?;
+
+        Ok(result
+               .iter()
+               .map(|row| UserId(row.get::<usize, i64>(0) as u64))
+               .collect())
+    }
+
+    pub fn set_bot_admin(&self, user_id: &UserId, admin: bool) -> postgres::Result<()> {
+        const QUERY_INSERT_BOT_ADMIN: &str = r#"
+        INSERT INTO bot_admin (user_id)
+        VALUES ($1)
+        ON CONFLICT (user_id) DO NOTHING;"#;
+        const QUERY_REMOVE_BOT_ADMIN: &str = r#"
+        DELETE FROM bot_admin
+        WHERE user_id = $1;"#;
+
+        self.conn
+            .execute(if admin {
+                         QUERY_INSERT_BOT_ADMIN
+                     } else {
+                         QUERY_REMOVE_BOT_ADMIN
+                     },
+                     &[&(user_id.0 as i64)])?;
+
+        Ok(())
+    }
+
+    // Moves all of the user's messages, reactions and legacy usage totals to ANONYMOUS_USER_ID,
+    // so that they still count towards emoji totals, and deletes everything else recorded about
+    // the user apart from their having opted out
+    pub fn forget_user(&self, user_id: &UserId) -> postgres::Result<()> {
+        const QUERY_ANONYMISE_MESSAGES: &str = r#"
+        UPDATE message
+        SET user_id = $2
+        WHERE user_id = $1;"#;
+        // An anonymous reaction may already exist for the same emoji on the same message, in
+        // which case the user's reaction is added to its count
+        const QUERY_ANONYMISE_REACTIONS: &str = r#"
+        INSERT INTO reaction (message_id, channel_id, user_id, emoji_id, reacted, reaction_count)
+            SELECT message_id, channel_id, $2, emoji_id, reacted, reaction_count
+            FROM reaction
+            WHERE user_id = $1
+        ON CONFLICT (message_id, user_id, emoji_id) DO UPDATE
+            SET reaction_count = reaction.reaction_count + excluded.reaction_count;"#;
+        const QUERY_REMOVE_REACTIONS: &str = r#"
+        DELETE FROM reaction
+        WHERE user_id = $1;"#;
+        const QUERY_ANONYMISE_LEGACY_USAGE: &str = r#"
+        INSERT INTO emoji_usage (channel_id, user_id, emoji_id, use_count)
+            SELECT channel_id, $2, emoji_id, use_count
+            FROM emoji_usage
+            WHERE user_id = $1
+        ON CONFLICT (channel_id, emoji_id, user_id) DO UPDATE
+            SET use_count = emoji_usage.use_count + excluded.use_count;"#;
+        const QUERY_REMOVE_LEGACY_USAGE: &str = r#"
+        DELETE FROM emoji_usage
+        WHERE user_id = $1;"#;
+        const QUERY_REMOVE_ALL_MEMBER_ROLES: &str = r#"
+        DELETE FROM member_role
+        WHERE user_id = $1;"#;
+        const QUERY_REMOVE_ALL_USER_EXCLUSIONS: &str = r#"
+        DELETE FROM excluded_user
+        WHERE user_id = $1;"#;
+        const QUERY_REMOVE_BOT_ADMIN: &str = r#"
+        DELETE FROM bot_admin
+        WHERE user_id = $1;"#;
+        const QUERY_REMOVE_USER: &str = r#"
+        DELETE FROM user_
+        WHERE id = $1;"#;
+
+        let user_id = user_id.0 as i64;
+        let anonymous_user_id = ANONYMOUS_USER_ID.0 as i64;
+
+        let trans = self.conn.transaction()?;
+
+        trans
+            .execute(QUERY_ANONYMISE_MESSAGES, &[&user_id, &anonymous_user_id])?;
+        trans
+            .execute(QUERY_ANONYMISE_REACTIONS, &[&user_id, &anonymous_user_id])?;
+        trans.execute(QUERY_REMOVE_REACTIONS, &[&user_id])?;
+        trans
+            .execute(QUERY_ANONYMISE_LEGACY_USAGE,
+                     &[&user_id, &anonymous_user_id])?;
+        trans.execute(QUERY_REMOVE_LEGACY_USAGE, &[&user_id])?;
+        trans.execute(QUERY_REMOVE_ALL_MEMBER_ROLES, &[&user_id])?;
+        trans
+            .execute(QUERY_REMOVE_ALL_USER_EXCLUSIONS, &[&user_id])?;
+        trans.execute(QUERY_REMOVE_BOT_ADMIN, &[&user_id])?;
+        trans.execute(QUERY_REMOVE_USER, &[&user_id])?;
+
+        trans.commit()
+    }
+
+    pub fn add_role(&self, server_id: &ServerId, role: &Role) -> postgres::Result<()> {
+        const QUERY_INSERT_ROLE: &str = r#"
+        INSERT INTO role (id, server_id, name)
+        VALUES ($1, $2, $3)
         ON CONFLICT (id) DO UPDATE
             SET name = excluded.name;"#;
 
+        self.conn
+            .execute(QUERY_INSERT_ROLE,
+                     &[&(role.id.0 as i64), &(server_id.0 as i64), &role.name])?;
+
+        Ok(())
+    }
+
+    // Replaces the server's roles, removing those that no longer exist along with their members
+    pub fn set_server_roles(&self, server_id: &ServerId, roles: &[Role]) -> postgres::Result<()> {
+        const QUERY_REMOVE_OLD_MEMBER_ROLES: &str = r#"
+        DELETE FROM member_role
+        WHERE server_id = $1 AND NOT (role_id = ANY($2));"#;
+        const QUERY_REMOVE_OLD_ROLES: &str = r#"
+        DELETE FROM role
+        WHERE server_id = $1 AND NOT (id = ANY($2));"#;
+
+        let role_ids = roles
+            .iter()
+            .map(|role| role.id.0 as i64)
+            .collect::<Vec<_>>();
+
+        let trans = self.conn.transaction()?;
+
+        trans
+            .execute(QUERY_REMOVE_OLD_MEMBER_ROLES,
+                     &[&(server_id.0 as i64), &role_ids])?;
+        trans
+            .execute(QUERY_REMOVE_OLD_ROLES, &[&(server_id.0 as i64), &role_ids])?;
+        trans.commit()?;
+
+        for role in roles {
+            self.add_role(server_id, role)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn remove_role(&self, role_id: &RoleId) -> postgres::Result<()> {
+        const QUERY_REMOVE_ROLE_MEMBERS: &str = r#"
+        DELETE FROM member_role
+        WHERE role_id = $1;"#;
+        const QUERY_REMOVE_ROLE: &str = r#"
+        DELETE FROM role
+        WHERE id = $1;"#;
+
+        let trans = self.conn.transaction()?;
+
+        trans
+            .execute(QUERY_REMOVE_ROLE_MEMBERS, &[&(role_id.0 as i64)])?;
+        trans.execute(QUERY_REMOVE_ROLE, &[&(role_id.0 as i64)])?;
+
+        trans.commit()
+    }
+
+    pub fn get_role_name(&self, role_id: &RoleId) -> postgres::Result<Option<String>> {
+        const QUERY_SELECT_ROLE: &str = r#"
+        SELECT name
+        FROM role
+        WHERE id = $1;"#;
+
+        let result = self.conn.query(QUERY_SELECT_ROLE, &[&(role_id.0 as i64)])?;
+
+        match result.iter().next() {
+            Some(row) => Ok(row.get::<usize, Option<String>>(0)),
+            None => Ok(None),
+        }
+    }
+
+    // Returns the roles recorded for the user on the server, which don't include @everyone
+    pub fn get_member_roles(&self,
+                            server_id: &ServerId,
+                            user_id: &UserId)
+                            -> postgres::Result<Vec<RoleId>> {
+        const QUERY_SELECT_MEMBER_ROLES: &str = r#"
+        SELECT role_id
+        FROM member_role
+        WHERE server_id = $1 AND user_id = $2;"#;
+
+        let result = self.conn
+            .query(QUERY_SELECT_MEMBER_ROLES,
+                   &[&(server_id.0 as i64), &(user_id.0 as i64)])?;
+
+        Ok(result
+               .iter()
+               .map(|row| RoleId(row.get::<usize, i64>(0) as u64))
+               .collect())
+    }
+
+    // Replaces the roles that the user has on the server; the @everyone role isn't recorded
+    pub fn set_member_roles(&self,
+                            server_id: &ServerId,
+                            user_id: &UserId,
+                            role_ids: &[RoleId])
+                            -> postgres::Result<()> {
+        const QUERY_INSERT_MEMBER_ROLE: &str = r#"
+        INSERT INTO member_role (server_id, user_id, role_id)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (user_id, role_id) DO NOTHING;"#;
+
+        let trans = self.conn.transaction()?;
+
+        remove_member_roles(&trans, server_id, user_id)?;
+
+        for role_id in role_ids {
+            trans
+                .execute(QUERY_INSERT_MEMBER_ROLE,
+                         &[&(server_id.0 as i64), &(user_id.0 as i64), &(role_id.0 as i64)])?;
+        }
+
+        trans.commit()
+    }
+
+    // Forgets the roles of a user who has left the server
+    pub fn remove_member(&self, server_id: &ServerId, user_id: &UserId) -> postgres::Result<()> {
+        let trans = self.conn.transaction()?;
+
+        remove_member_roles(&trans, server_id, user_id)?;
+
+        trans.commit()
+    }
+
+    // External custom emoji keep the server they were already known to be from, if any
+    pub fn add_emoji(&self, emoji: &Emoji) -> postgres::Result<()> {
+        const QUERY_INSERT_CUSTOM_EMOJI: &str = r#"
+        INSERT INTO emoji (server_id, id, name, is_custom_emoji, animated)
+        VALUES ($1, $2, $3, TRUE, $4)
+        ON CONFLICT (id) DO UPDATE
+            SET server_id = COALESCE(excluded.server_id, emoji.server_id),
+                name = excluded.name,
+                animated = emoji.animated OR excluded.animated,
+                available = emoji.available OR excluded.server_id IS NOT NULL;"#;
+
+        // Variants refer to their base emoji, which must already be in the database
         const QUERY_INSERT_UNICODE_EMOJI: &str = r#"
-        INSERT INTO emoji (server_id, name, is_custom_emoji)
-        VALUES (NULL, $1, FALSE);"#;
+        INSERT INTO emoji (server_id, name, is_custom_emoji, base_id)
+        VALUES (NULL, $1, FALSE, (SELECT id FROM emoji WHERE name = $2));"#;
+
+        const QUERY_UPDATE_UNICODE_EMOJI_BASE: &str = r#"
+        UPDATE emoji
+        SET base_id = (SELECT id FROM emoji WHERE name = $2)
+        WHERE id = $1 AND base_id IS NULL;"#;
 
         match *emoji {
             Emoji::Custom(ref emoji) => {
                 self.conn
                     .execute(QUERY_INSERT_CUSTOM_EMOJI,
-                             &[&(server_id.unwrap().0 as i64),
+                             &[&emoji.server_id.map(|server_id| server_id.0 as i64),
                                &(emoji.id.0 as i64),
-                               &emoji.name])?;
+                               &emoji.name,
+                               &emoji.animated])?;
             }
-            Emoji::Unicode(ref emoji) => {
+            Emoji::Unicode(ref name) => {
+                let base_name = emoji.base().map(|base| base.pattern().to_string());
+
                 // Only insert Unicode emoji if they aren't already in the database
-                match self.get_emoji_id(emoji.clone())? {
-                    Some(_) => {}
+                match self.get_emoji_id(name.clone())? {
+                    Some(id) => {
+                        if base_name.is_some() {
+                            self.conn
+                                .execute(QUERY_UPDATE_UNICODE_EMOJI_BASE,
+                                         &[&(id as i64), &base_name])?;
+                        }
+                    }
                     None => {
-                        self.conn.execute(QUERY_INSERT_UNICODE_EMOJI, &[&emoji])?;
+                        self.conn
+                            .execute(QUERY_INSERT_UNICODE_EMOJI, &[&name, &base_name])?;
                     }
                 }
             }
@@ -85,6 +631,60 @@ impl Database {
         Ok(())
     }
 
+    // Marks the server's custom emoji that aren't in emoji_ids as no longer available, e.g. because
+    // they were deleted, and those that are as available
+    pub fn set_available_server_emoji(&self,
+                                      server_id: &ServerId,
+                                      emoji_ids: &[EmojiId])
+                                      -> postgres::Result<()> {
+        const QUERY_UPDATE_SERVER_EMOJI_AVAILABLE: &str = r#"
+        UPDATE emoji
+        SET available = (id = ANY($2))
+        WHERE server_id = $1 AND is_custom_emoji = TRUE;"#;
+
+        let emoji_ids = emoji_ids
+            .iter()
+            .map(|emoji_id| emoji_id.0 as i64)
+            .collect::<Vec<_>>();
+
+        self.conn
+            .execute(QUERY_UPDATE_SERVER_EMOJI_AVAILABLE,
+                     &[&(server_id.0 as i64), &emoji_ids])?;
+
+        Ok(())
+    }
+
+    // Records the name, group and subgroup given to a Unicode emoji in the Unicode emoji data
+    pub fn set_unicode_emoji_details(&self, emoji: &UnicodeEmoji) -> postgres::Result<()> {
+        const QUERY_UPDATE_UNICODE_EMOJI_DETAILS: &str = r#"
+        UPDATE emoji
+        SET description = $2, emoji_group = $3, emoji_subgroup = $4
+        WHERE name = $1 AND is_custom_emoji = FALSE;"#;
+
+        self.conn
+            .execute(QUERY_UPDATE_UNICODE_EMOJI_DETAILS,
+                     &[&emoji.emoji, &emoji.name, &emoji.group, &emoji.subgroup])?;
+
+        Ok(())
+    }
+
+    // Returns whether a custom emoji has been seen animated
+    // Discord doesn't say which server emoji are animated, so this is only known once it is used
+    pub fn is_custom_emoji_animated(&self, emoji_id: &EmojiId) -> postgres::Result<bool> {
+        const QUERY_GET_EMOJI_ANIMATED: &str = r#"
+        SELECT animated
+        FROM emoji
+        WHERE id = $1 AND is_custom_emoji = TRUE;"#;
+
+        let result = self.conn
+            .query(QUERY_GET_EMOJI_ANIMATED, &[&(emoji_id.0 as i64)])?;
+
+        match result.iter().next() {
+            Some(row) => Ok(row.get::<usize, bool>(0)),
+            None => Ok(false),
+        }
+    }
+
     pub fn message_exists(&self, message_id: &MessageId) -> postgres::Result<bool> {
         const QUERY_GET_MESSAGE_EXIST: &str = r#"
         SELECT id
@@ -97,66 +697,343 @@ impl Database {
         Ok(result.len() != 0)
     }
 
-    pub fn record_message_stats(&self,
-                                message_id: &MessageId,
-                                channel_id: &ChannelId,
-                                user_id: &UserId,
-                                emoji_count: i32)
-                                -> postgres::Result<()> {
+    // timestamp is the time the message was posted, as an ISO 8601 string
+    pub fn record_message(&self,
+                          message_id: &MessageId,
+                          channel_id: &ChannelId,
+                          user_id: &UserId,
+                          timestamp: &str,
+                          emoji_counts: &[(Emoji, i32)])
+                          -> postgres::Result<()> {
         const QUERY_RECORD_MESSAGE_STATS: &str = r#"
-        INSERT INTO message (id, channel_id, user_id, emoji_count)
-        VALUES ($1, $2, $3, $4);"#;
+        INSERT INTO message (id, channel_id, user_id, emoji_count, posted)
+        VALUES ($1, $2, $3, $4, $5::VARCHAR::TIMESTAMPTZ);"#;
 
-        self.conn
+        let emoji_counts = self.get_emoji_counts_by_db_id(channel_id, user_id, emoji_counts)?;
+        let emoji_count = emoji_counts.iter().map(|&(_, count)| count).sum::<i32>();
+
+        let trans = self.conn.transaction()?;
+
+        trans
             .execute(QUERY_RECORD_MESSAGE_STATS,
                      &[&(message_id.0 as i64),
                        &(channel_id.0 as i64),
                        &(user_id.0 as i64),
-                       &emoji_count])?;
+                       &emoji_count,
+                       &timestamp])?;
+        insert_message_emoji(&trans, message_id, &emoji_counts)?;
 
-        Ok(())
+        trans.commit()
     }
 
-    pub fn record_emoji_usage(&self,
-                              channel_id: &ChannelId,
-                              user_id: &UserId,
-                              emoji: &Emoji,
-                              count: i32)
-                              -> postgres::Result<()> {
-        const QUERY_RECORD_EMOJI_USAGE: &str =
-            r#"
-        INSERT INTO emoji_usage (channel_id, user_id, emoji_id, use_count)
-        VALUES ($1, $2, $3, $4)
-        ON CONFLICT (channel_id, user_id, emoji_id) DO UPDATE
-            SET use_count = emoji_usage.use_count + excluded.use_count;"#;
+    // Replaces the emoji counts recorded for an edited message
+    // Returns false if the message could not be updated exactly, either because it was never
+    // recorded or because it was recorded before per-message emoji counts were stored
+    pub fn update_message(&self,
+                          message_id: &MessageId,
+                          emoji_counts: &[(Emoji, i32)])
+                          -> postgres::Result<bool> {
+        const QUERY_GET_MESSAGE: &str = r#"
+        SELECT m.channel_id, m.user_id, m.emoji_count,
+            (SELECT COUNT(*) FROM message_emoji me WHERE me.message_id = m.id)
+        FROM message m
+        WHERE m.id = $1;"#;
 
-        let emoji_id = match *emoji {
-            Emoji::Custom(ref custom_emoji) => {
-                debug!("Custom emoji {} used {} time{} by {} in channel {}",
-                       custom_emoji.pattern,
-                       count,
-                       if count == 1 { "" } else { "s" },
-                       user_id,
-                       channel_id);
-                custom_emoji.id.0 as i64
+        const QUERY_UPDATE_MESSAGE_STATS: &str = r#"
+        UPDATE message
+        SET emoji_count = $2
+        WHERE id = $1;"#;
+
+        let (channel_id, user_id) = {
+            let result = self.conn
+                .query(QUERY_GET_MESSAGE, &[&(message_id.0 as i64)])?;
+
+            if result.len() == 0 {
+                return Ok(false);
             }
-            Emoji::Unicode(ref emoji) => {
-                debug!("Emoji {} used {} time{} by {} in channel {}",
-                       emoji,
-                       count,
-                       if count == 1 { "" } else { "s" },
-                       user_id,
-                       channel_id);
-                self.get_emoji_id(emoji.clone())?.unwrap() as i64
+
+            let row = result.get(0);
+
+            if row.get::<usize, i32>(2) > 0 && row.get::<usize, i64>(3) == 0 {
+                return Ok(false);
             }
+
+            (ChannelId(row.get::<usize, i64>(0) as u64), UserId(row.get::<usize, i64>(1) as u64))
         };
 
+        let emoji_counts = self.get_emoji_counts_by_db_id(&channel_id, &user_id, emoji_counts)?;
+        let emoji_count = emoji_counts.iter().map(|&(_, count)| count).sum::<i32>();
+
+        let trans = self.conn.transaction()?;
+
+        remove_message_emoji(&trans, message_id)?;
+        insert_message_emoji(&trans, message_id, &emoji_counts)?;
+        trans
+            .execute(QUERY_UPDATE_MESSAGE_STATS,
+                     &[&(message_id.0 as i64), &emoji_count])?;
+
+        trans.commit()?;
+
+        Ok(true)
+    }
+
+    // Removes a deleted message, its emoji and its reactions from the statistics
+    pub fn remove_message(&self, message_id: &MessageId) -> postgres::Result<()> {
+        const QUERY_REMOVE_MESSAGE: &str = r#"
+        DELETE FROM message
+        WHERE id = $1;"#;
+
+        const QUERY_REMOVE_MESSAGE_REACTIONS: &str = r#"
+        DELETE FROM reaction
+        WHERE message_id = $1;"#;
+
+        let trans = self.conn.transaction()?;
+
+        remove_message_emoji(&trans, message_id)?;
+        trans
+            .execute(QUERY_REMOVE_MESSAGE, &[&(message_id.0 as i64)])?;
+        trans
+            .execute(QUERY_REMOVE_MESSAGE_REACTIONS, &[&(message_id.0 as i64)])?;
+
+        trans.commit()
+    }
+
+    // Looks up the IDs of the emoji in the emoji table, skipping any that aren't there
+    fn get_emoji_counts_by_db_id(&self,
+                                 channel_id: &ChannelId,
+                                 user_id: &UserId,
+                                 emoji_counts: &[(Emoji, i32)])
+                                 -> postgres::Result<Vec<(i64, i32)>> {
+        let mut emoji_counts_by_db_id = Vec::new();
+
+        for &(ref emoji, count) in emoji_counts {
+            debug!("Emoji {} used {} time{} by {} in channel {}",
+                   emoji.pattern(),
+                   count,
+                   if count == 1 { "" } else { "s" },
+                   user_id,
+                   channel_id);
+
+            match self.get_or_add_emoji_db_id(emoji)? {
+                Some(emoji_id) => emoji_counts_by_db_id.push((emoji_id, count)),
+                None => {
+                    warn!("Emoji {} is not in the database; not recording its usage",
+                          emoji.pattern());
+                }
+            }
+        }
+
+        Ok(emoji_counts_by_db_id)
+    }
+
+    pub fn record_reaction(&self,
+                           message_id: &MessageId,
+                           channel_id: &ChannelId,
+                           user_id: &UserId,
+                           emoji: &Emoji)
+                           -> postgres::Result<()> {
+        // Reactions by users who opted out share an anonymous row, so each one adds to its count
+        const QUERY_RECORD_REACTION: &str = r#"
+        INSERT INTO reaction (message_id, channel_id, user_id, emoji_id)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (message_id, user_id, emoji_id) DO UPDATE
+            SET reaction_count = reaction.reaction_count + 1
+            WHERE reaction.user_id = $5;"#;
+
+        let emoji_id = match self.get_or_add_emoji_db_id(emoji)? {
+            Some(emoji_id) => emoji_id,
+            None => return Ok(()),
+        };
+
+        debug!("Emoji {} added as a reaction by {} in channel {}",
+               emoji.pattern(),
+               user_id,
+               channel_id);
+
         self.conn
-            .execute(QUERY_RECORD_EMOJI_USAGE,
-                     &[&(channel_id.0 as i64),
+            .execute(QUERY_RECORD_REACTION,
+                     &[&(message_id.0 as i64),
+                       &(channel_id.0 as i64),
                        &(user_id.0 as i64),
                        &emoji_id,
-                       &count])?;
+                       &(ANONYMOUS_USER_ID.0 as i64)])?;
+
+        Ok(())
+    }
+
+    // Removes the user's own reaction if there is one, and otherwise one from the anonymous
+    // reaction on the message
+    // A user who reacted and then opted out still has their own reaction, which is the one
+    // removed; one who reacted while opted out, or has been forgotten since, only has a share of
+    // the anonymous reaction
+    pub fn remove_reaction(&self,
+                           message_id: &MessageId,
+                           user_id: &UserId,
+                           emoji: &Emoji)
+                           -> postgres::Result<()> {
+        const QUERY_REMOVE_REACTION: &str = r#"
+        DELETE FROM reaction
+        WHERE message_id = $1 AND user_id = $2 AND emoji_id = $3;"#;
+        // An anonymous reaction shared by several users loses one from its count
+        const QUERY_DECREMENT_ANONYMOUS_REACTION: &str = r#"
+        UPDATE reaction
+        SET reaction_count = reaction_count - 1
+        WHERE message_id = $1 AND user_id = $2 AND emoji_id = $3 AND reaction_count > 1;"#;
+
+        let emoji_id = match self.get_emoji_db_id(emoji)? {
+            Some(emoji_id) => emoji_id,
+            None => return Ok(()),
+        };
+
+        let trans = self.conn.transaction()?;
+
+        let removed = trans
+            .execute(QUERY_REMOVE_REACTION,
+                     &[&(message_id.0 as i64), &(user_id.0 as i64), &emoji_id])?;
+
+        if removed == 0 {
+            let anonymous_user_id = ANONYMOUS_USER_ID.0 as i64;
+            let decremented = trans
+                .execute(QUERY_DECREMENT_ANONYMOUS_REACTION,
+                         &[&(message_id.0 as i64), &anonymous_user_id, &emoji_id])?;
+
+            if decremented == 0 {
+                trans
+                    .execute(QUERY_REMOVE_REACTION,
+                             &[&(message_id.0 as i64), &anonymous_user_id, &emoji_id])?;
+            }
+        }
+
+        trans.commit()
+    }
+
+    pub fn remove_all_reactions(&self, message_id: &MessageId) -> postgres::Result<()> {
+        const QUERY_REMOVE_ALL_REACTIONS: &str = r#"
+        DELETE FROM reaction
+        WHERE message_id = $1;"#;
+
+        self.conn
+            .execute(QUERY_REMOVE_ALL_REACTIONS, &[&(message_id.0 as i64)])?;
+
+        Ok(())
+    }
+
+    // Returns the ID of the emoji in the emoji table, if it is there
+    fn get_emoji_db_id(&self, emoji: &Emoji) -> postgres::Result<Option<i64>> {
+        match *emoji {
+            Emoji::Custom(ref emoji) => Ok(Some(emoji.id.0 as i64)),
+            Emoji::Unicode(ref emoji) => {
+                Ok(self.get_emoji_id(emoji.clone())?.map(|id| id as i64))
+            }
+        }
+    }
+
+    // Returns the ID of the emoji in the emoji table, adding it first if it is a variant of an
+    // emoji that is already there
+    // External custom emoji are added the first time they are seen, and custom emoji used in their
+    // animated form are marked as animated
+    fn get_or_add_emoji_db_id(&self, emoji: &Emoji) -> postgres::Result<Option<i64>> {
+        if let Emoji::Custom(ref custom_emoji) = *emoji {
+            if custom_emoji.is_external() || custom_emoji.animated {
+                self.add_emoji(emoji)?;
+            }
+        }
+
+        if let Some(emoji_id) = self.get_emoji_db_id(emoji)? {
+            return Ok(Some(emoji_id));
+        }
+
+        match emoji.base() {
+            Some(ref base) if self.get_emoji_db_id(base)?.is_some() => {
+                self.add_emoji(emoji)?;
+                self.get_emoji_db_id(emoji)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    // Starts (or restarts) backfilling a channel's history
+    // since is in seconds since the Unix epoch; None backfills the entire history
+    pub fn add_backfill(&self,
+                        channel_id: &ChannelId,
+                        report_channel_id: &ChannelId,
+                        since: Option<i64>)
+                        -> postgres::Result<()> {
+        const QUERY_INSERT_BACKFILL: &str = r#"
+        INSERT INTO backfill (channel_id, report_channel_id, since)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (channel_id) DO UPDATE
+            SET report_channel_id = excluded.report_channel_id,
+                since = excluded.since,
+                before_message_id = NULL,
+                messages_processed = 0,
+                completed = FALSE;"#;
+
+        self.conn
+            .execute(QUERY_INSERT_BACKFILL,
+                     &[&(channel_id.0 as i64), &(report_channel_id.0 as i64), &since])?;
+
+        Ok(())
+    }
+
+    pub fn get_pending_backfills(&self) -> postgres::Result<Vec<BackfillProgress>> {
+        const QUERY_SELECT_PENDING_BACKFILLS: &str = r#"
+        SELECT channel_id, report_channel_id, since, before_message_id, messages_processed
+        FROM backfill
+        WHERE completed = FALSE
+        ORDER BY channel_id;"#;
+
+        let result = self.conn.query(QUERY_SELECT_PENDING_BACKFILLS, &[])?;
+        let mut backfills = Vec::new();
+
+        for row in result.iter() {
+            backfills.push(BackfillProgress {
+                               channel_id: ChannelId(row.get::<usize, i64>(0) as u64),
+                               report_channel_id: ChannelId(row.get::<usize, i64>(1) as u64),
+                               since: row.get::<usize, Option<i64>>(2),
+                               before_message_id: row.get::<usize, Option<i64>>(3)
+                                   .map(|id| MessageId(id as u64)),
+                               messages_processed: row.get::<usize, i64>(4),
+                           });
+        }
+
+        Ok(backfills)
+    }
+
+    // Returns the servers with channels that have a pending backfill
+    pub fn get_pending_backfill_servers(&self) -> postgres::Result<Vec<ServerId>> {
+        const QUERY_SELECT_PENDING_BACKFILL_SERVERS: &str = r#"
+        SELECT DISTINCT c.server_id
+        FROM backfill b
+            INNER JOIN channel c ON b.channel_id = c.id
+        WHERE b.completed = FALSE;"#;
+
+        let result = self.conn
+            .query(QUERY_SELECT_PENDING_BACKFILL_SERVERS, &[])?;
+
+        Ok(result
+               .iter()
+               .map(|row| ServerId(row.get::<usize, i64>(0) as u64))
+               .collect())
+    }
+
+    pub fn update_backfill(&self,
+                           backfill: &BackfillProgress,
+                           completed: bool)
+                           -> postgres::Result<()> {
+        const QUERY_UPDATE_BACKFILL: &str = r#"
+        UPDATE backfill
+        SET before_message_id = $2,
+            messages_processed = $3,
+            completed = $4
+        WHERE channel_id = $1;"#;
+
+        self.conn
+            .execute(QUERY_UPDATE_BACKFILL,
+                     &[&(backfill.channel_id.0 as i64),
+                       &backfill.before_message_id.map(|id| id.0 as i64),
+                       &backfill.messages_processed,
+                       &completed])?;
 
         Ok(())
     }
@@ -178,137 +1055,688 @@ impl Database {
         }
     }
 
-    pub fn get_global_top_emoji(&self) -> postgres::Result<Vec<(Emoji, i64)>> {
+    pub fn get_global_top_emoji(&self,
+                                filter: &UsageFilter,
+                                page: &Page)
+                                -> postgres::Result<Vec<(Emoji, i64)>> {
         const QUERY_SELECT_TOP_GLOBAL_EMOJI: &str = r#"
-        SELECT e.is_custom_emoji, e.id, e.name, SUM(eu.use_count)
-        FROM emoji_usage eu
+        SELECT e.is_custom_emoji, e.id, e.name, SUM(eu.use_count), e.animated, e.server_id
+        FROM emoji_use eu
+            INNER JOIN emoji e ON eu.emoji_id = e.id
+        WHERE (e.is_custom_emoji = FALSE OR $4)
+            AND ($1::VARCHAR IS NULL OR eu.source = $1)
+            AND ($2::BIGINT IS NULL OR eu.used_at >= to_timestamp($2))
+            AND ($3::BIGINT IS NULL OR eu.used_at < to_timestamp($3))
+            AND (e.is_custom_emoji AND e.server_id IS NULL) = $4
+        GROUP BY e.is_custom_emoji, e.id, e.name, e.animated, e.server_id
+        ORDER BY SUM(eu.use_count) DESC, e.id
+        LIMIT $5 OFFSET $6;"#;
+
+        let (source, start, end) = filter_params(filter);
+
+        let result = self.conn
+            .query(QUERY_SELECT_TOP_GLOBAL_EMOJI,
+                   &[&source, &start, &end, &filter.external, &page.limit(), &page.offset()])?;
+
+        Ok(result_into_vec_emoji(result)?)
+    }
+
+    // Only the custom emoji of servers that have opted into the global rankings are included
+    pub fn get_global_top_custom_emoji(&self,
+                                       filter: &UsageFilter,
+                                       page: &Page)
+                                       -> postgres::Result<Vec<(Emoji, i64)>> {
+        const QUERY_SELECT_TOP_GLOBAL_CUSTOM_EMOJI: &str = r#"
+        SELECT e.is_custom_emoji, e.id, e.name, SUM(eu.use_count), e.animated, e.server_id
+        FROM emoji_use eu
             INNER JOIN emoji e ON eu.emoji_id = e.id
-        WHERE e.is_custom_emoji = FALSE
-        GROUP BY e.is_custom_emoji, e.id, e.name
-        ORDER BY SUM(eu.use_count) DESC
-        LIMIT 5;"#;
+            INNER JOIN server s ON e.server_id = s.id
+        WHERE e.is_custom_emoji
+            AND s.ranked
+            AND ($1::VARCHAR IS NULL OR eu.source = $1)
+            AND ($2::BIGINT IS NULL OR eu.used_at >= to_timestamp($2))
+            AND ($3::BIGINT IS NULL OR eu.used_at < to_timestamp($3))
+        GROUP BY e.is_custom_emoji, e.id, e.name, e.animated, e.server_id
+        ORDER BY SUM(eu.use_count) DESC, e.id
+        LIMIT $4 OFFSET $5;"#;
+
+        let (source, start, end) = filter_params(filter);
 
-        let result = self.conn.query(QUERY_SELECT_TOP_GLOBAL_EMOJI, &[])?;
+        let result = self.conn
+            .query(QUERY_SELECT_TOP_GLOBAL_CUSTOM_EMOJI,
+                   &[&source, &start, &end, &page.limit(), &page.offset()])?;
 
         Ok(result_into_vec_emoji(result)?)
     }
 
     pub fn get_server_top_emoji(&self,
-                                server_id: &ServerId)
+                                server_id: &ServerId,
+                                filter: &UsageFilter,
+                                page: &Page)
                                 -> postgres::Result<Vec<(Emoji, i64)>> {
         const QUERY_SELECT_TOP_SERVER_EMOJI: &str = r#"
-        SELECT e.is_custom_emoji, e.id, e.name, SUM(eu.use_count)
-        FROM emoji_usage eu
+        SELECT e.is_custom_emoji, e.id, e.name, SUM(eu.use_count), e.animated, e.server_id
+        FROM emoji_use eu
+            INNER JOIN emoji e ON eu.emoji_id = e.id
+            INNER JOIN channel c ON eu.channel_id = c.id
+        WHERE c.server_id = $1
+            AND ($2::VARCHAR IS NULL OR eu.source = $2)
+            AND ($3::BIGINT IS NULL OR eu.used_at >= to_timestamp($3))
+            AND ($4::BIGINT IS NULL OR eu.used_at < to_timestamp($4))
+            AND (e.is_custom_emoji AND e.server_id IS NULL) = $5
+        GROUP BY e.is_custom_emoji, e.id, e.name, e.animated, e.server_id
+        ORDER BY SUM(eu.use_count) DESC, e.id
+        LIMIT $6 OFFSET $7;"#;
+
+        let (source, start, end) = filter_params(filter);
+
+        let result = self.conn
+            .query(QUERY_SELECT_TOP_SERVER_EMOJI,
+                   &[&(server_id.0 as i64),
+                     &source,
+                     &start,
+                     &end,
+                     &filter.external,
+                     &page.limit(),
+                     &page.offset()])?;
+
+        Ok(result_into_vec_emoji(result)?)
+    }
+
+    // Returns the server's top emoji, as in get_server_top_emoji, along with where each ranks among
+    // the emoji used on all servers, as (emoji, use count on the server, global rank)
+    pub fn get_server_top_emoji_vs_global(&self,
+                                          server_id: &ServerId,
+                                          filter: &UsageFilter,
+                                          page: &Page)
+                                          -> postgres::Result<Vec<(Emoji, i64, i64)>> {
+        const QUERY_SELECT_TOP_SERVER_EMOJI_VS_GLOBAL: &str = r#"
+        WITH global_usage AS (
+            SELECT eu.emoji_id, SUM(eu.use_count) AS use_count
+            FROM emoji_use eu
+                INNER JOIN emoji e ON eu.emoji_id = e.id
+            WHERE ($2::VARCHAR IS NULL OR eu.source = $2)
+                AND ($3::BIGINT IS NULL OR eu.used_at >= to_timestamp($3))
+                AND ($4::BIGINT IS NULL OR eu.used_at < to_timestamp($4))
+                AND (e.is_custom_emoji AND e.server_id IS NULL) = $5
+            GROUP BY eu.emoji_id
+        ), global_rank AS (
+            SELECT emoji_id, RANK() OVER (ORDER BY use_count DESC) AS rank
+            FROM global_usage
+        )
+        SELECT e.is_custom_emoji, e.id, e.name, SUM(eu.use_count), e.animated, e.server_id,
+            gr.rank
+        FROM emoji_use eu
             INNER JOIN emoji e ON eu.emoji_id = e.id
             INNER JOIN channel c ON eu.channel_id = c.id
+            INNER JOIN global_rank gr ON eu.emoji_id = gr.emoji_id
         WHERE c.server_id = $1
-        GROUP BY e.is_custom_emoji, e.id, e.name
-        ORDER BY SUM(eu.use_count) DESC
-        LIMIT 5;"#;
+            AND ($2::VARCHAR IS NULL OR eu.source = $2)
+            AND ($3::BIGINT IS NULL OR eu.used_at >= to_timestamp($3))
+            AND ($4::BIGINT IS NULL OR eu.used_at < to_timestamp($4))
+            AND (e.is_custom_emoji AND e.server_id IS NULL) = $5
+        GROUP BY e.is_custom_emoji, e.id, e.name, e.animated, e.server_id, gr.rank
+        ORDER BY SUM(eu.use_count) DESC, e.id
+        LIMIT $6 OFFSET $7;"#;
+
+        let (source, start, end) = filter_params(filter);
+
+        let result = self.conn
+            .query(QUERY_SELECT_TOP_SERVER_EMOJI_VS_GLOBAL,
+                   &[&(server_id.0 as i64),
+                     &source,
+                     &start,
+                     &end,
+                     &filter.external,
+                     &page.limit(),
+                     &page.offset()])?;
+
+        let mut top_emoji = Vec::new();
+
+        for row in result.iter() {
+            top_emoji.push((row_into_emoji(&row),
+                            row.get::<usize, i64>(3),
+                            row.get::<usize, i64>(6)));
+        }
+
+        Ok(top_emoji)
+    }
+
+    // Ranks the servers that have opted in by the number of emoji used on them
+    pub fn get_top_servers(&self,
+                           filter: &UsageFilter,
+                           page: &Page)
+                           -> postgres::Result<Vec<(ServerId, String, i64)>> {
+        const QUERY_SELECT_TOP_SERVERS: &str = r#"
+        SELECT s.id, s.name, SUM(eu.use_count)
+        FROM emoji_use eu
+            INNER JOIN emoji e ON eu.emoji_id = e.id
+            INNER JOIN channel c ON eu.channel_id = c.id
+            INNER JOIN server s ON c.server_id = s.id
+        WHERE s.ranked = TRUE
+            AND ($1::VARCHAR IS NULL OR eu.source = $1)
+            AND ($2::BIGINT IS NULL OR eu.used_at >= to_timestamp($2))
+            AND ($3::BIGINT IS NULL OR eu.used_at < to_timestamp($3))
+            AND (e.is_custom_emoji AND e.server_id IS NULL) = $4
+        GROUP BY s.id, s.name
+        ORDER BY SUM(eu.use_count) DESC, s.id
+        LIMIT $5 OFFSET $6;"#;
+
+        let (source, start, end) = filter_params(filter);
+
+        let result = self.conn
+            .query(QUERY_SELECT_TOP_SERVERS,
+                   &[&source, &start, &end, &filter.external, &page.limit(), &page.offset()])?;
+
+        let mut servers = Vec::new();
+
+        for row in result.iter() {
+            servers.push((ServerId(row.get::<usize, i64>(0) as u64),
+                          row.get::<usize, String>(1),
+                          row.get::<usize, i64>(2)));
+        }
+
+        Ok(servers)
+    }
+
+    pub fn get_channel_top_emoji(&self,
+                                 channel_id: &ChannelId,
+                                 filter: &UsageFilter,
+                                 page: &Page)
+                                 -> postgres::Result<Vec<(Emoji, i64)>> {
+        const QUERY_SELECT_TOP_CHANNEL_EMOJI: &str = r#"
+        SELECT e.is_custom_emoji, e.id, e.name, SUM(eu.use_count), e.animated, e.server_id
+        FROM emoji_use eu
+            INNER JOIN emoji e ON eu.emoji_id = e.id
+        WHERE eu.channel_id = $1
+            AND ($2::VARCHAR IS NULL OR eu.source = $2)
+            AND ($3::BIGINT IS NULL OR eu.used_at >= to_timestamp($3))
+            AND ($4::BIGINT IS NULL OR eu.used_at < to_timestamp($4))
+            AND (e.is_custom_emoji AND e.server_id IS NULL) = $5
+        GROUP BY e.is_custom_emoji, e.id, e.name, e.animated, e.server_id
+        ORDER BY SUM(eu.use_count) DESC, e.id
+        LIMIT $6 OFFSET $7;"#;
+
+        let (source, start, end) = filter_params(filter);
+
+        let result = self.conn
+            .query(QUERY_SELECT_TOP_CHANNEL_EMOJI,
+                   &[&(channel_id.0 as i64),
+                     &source,
+                     &start,
+                     &end,
+                     &filter.external,
+                     &page.limit(),
+                     &page.offset()])?;
+
+        Ok(result_into_vec_emoji(result)?)
+    }
+
+    pub fn get_user_top_emoji(&self,
+                              user_id: &UserId,
+                              server_id: Option<&ServerId>,
+                              filter: &UsageFilter,
+                              page: &Page)
+                              -> postgres::Result<Vec<(Emoji, i64)>> {
+        const QUERY_SELECT_TOP_USER_UNICODE_EMOJI: &str = r#"
+        SELECT e.is_custom_emoji, e.id, e.name, SUM(eu.use_count), e.animated, e.server_id
+        FROM emoji_use eu
+            INNER JOIN emoji e ON eu.emoji_id = e.id
+        WHERE (e.is_custom_emoji = FALSE OR $5) AND eu.user_id = $1
+            AND ($2::VARCHAR IS NULL OR eu.source = $2)
+            AND ($3::BIGINT IS NULL OR eu.used_at >= to_timestamp($3))
+            AND ($4::BIGINT IS NULL OR eu.used_at < to_timestamp($4))
+            AND (e.is_custom_emoji AND e.server_id IS NULL) = $5
+        GROUP BY e.is_custom_emoji, e.id, e.name, e.animated, e.server_id
+        ORDER BY SUM(eu.use_count) DESC, e.id
+        LIMIT $6 OFFSET $7;"#;
+
+        const QUERY_SELECT_TOP_USER_SERVER_EMOJI: &str = r#"
+        SELECT e.is_custom_emoji, e.id, e.name, SUM(eu.use_count), e.animated, e.server_id
+        FROM emoji_use eu
+            INNER JOIN emoji e ON eu.emoji_id = e.id
+        WHERE (eu.user_id = $1) AND (e.is_custom_emoji = FALSE OR e.server_id = $2 OR $6)
+            AND ($3::VARCHAR IS NULL OR eu.source = $3)
+            AND ($4::BIGINT IS NULL OR eu.used_at >= to_timestamp($4))
+            AND ($5::BIGINT IS NULL OR eu.used_at < to_timestamp($5))
+            AND (e.is_custom_emoji AND e.server_id IS NULL) = $6
+        GROUP BY e.is_custom_emoji, e.id, e.name, e.animated, e.server_id
+        ORDER BY SUM(eu.use_count) DESC, e.id
+        LIMIT $7 OFFSET $8;"#;
+
+        let (source, start, end) = filter_params(filter);
+
+        let result = match server_id {
+            Some(server_id) => {
+                self.conn
+                    .query(QUERY_SELECT_TOP_USER_SERVER_EMOJI,
+                           &[&(user_id.0 as i64),
+                             &(server_id.0 as i64),
+                             &source,
+                             &start,
+                             &end,
+                             &filter.external,
+                             &page.limit(),
+                             &page.offset()])?
+            }
+            None => {
+                self.conn
+                    .query(QUERY_SELECT_TOP_USER_UNICODE_EMOJI,
+                           &[&(user_id.0 as i64),
+                             &source,
+                             &start,
+                             &end,
+                             &filter.external,
+                             &page.limit(),
+                             &page.offset()])?
+            }
+        };
+
+        Ok(result_into_vec_emoji(result)?)
+    }
+
+    // Counts the emoji used on the server by the role's current members
+    pub fn get_role_top_emoji(&self,
+                              role_id: &RoleId,
+                              server_id: &ServerId,
+                              filter: &UsageFilter,
+                              page: &Page)
+                              -> postgres::Result<Vec<(Emoji, i64)>> {
+        const QUERY_SELECT_TOP_ROLE_EMOJI: &str = r#"
+        SELECT e.is_custom_emoji, e.id, e.name, SUM(eu.use_count), e.animated, e.server_id
+        FROM emoji_use eu
+            INNER JOIN emoji e ON eu.emoji_id = e.id
+            INNER JOIN channel c ON eu.channel_id = c.id
+            INNER JOIN member_role mr ON eu.user_id = mr.user_id
+        WHERE mr.role_id = $1 AND c.server_id = $2
+            AND ($3::VARCHAR IS NULL OR eu.source = $3)
+            AND ($4::BIGINT IS NULL OR eu.used_at >= to_timestamp($4))
+            AND ($5::BIGINT IS NULL OR eu.used_at < to_timestamp($5))
+            AND (e.is_custom_emoji AND e.server_id IS NULL) = $6
+        GROUP BY e.is_custom_emoji, e.id, e.name, e.animated, e.server_id
+        ORDER BY SUM(eu.use_count) DESC, e.id
+        LIMIT $7 OFFSET $8;"#;
+
+        let (source, start, end) = filter_params(filter);
+
+        let result = self.conn
+            .query(QUERY_SELECT_TOP_ROLE_EMOJI,
+                   &[&(role_id.0 as i64),
+                     &(server_id.0 as i64),
+                     &source,
+                     &start,
+                     &end,
+                     &filter.external,
+                     &page.limit(),
+                     &page.offset()])?;
+
+        Ok(result_into_vec_emoji(result)?)
+    }
+
+    // Returns the server's available custom emoji with the lowest usage, including those that
+    // haven't been used at all, as (emoji, use count, time first seen in seconds since the Unix
+    // epoch)
+    // If unused_only is true, only emoji that haven't been used in the filter's period are returned
+    pub fn get_server_least_used_emoji(&self,
+                                       server_id: &ServerId,
+                                       filter: &UsageFilter,
+                                       unused_only: bool,
+                                       page: &Page)
+                                       -> postgres::Result<Vec<(Emoji, i64, i64)>> {
+        const QUERY_SELECT_LEAST_USED_SERVER_EMOJI: &str = r#"
+        SELECT e.is_custom_emoji, e.id, e.name, COALESCE(SUM(eu.use_count), 0)::BIGINT,
+            e.animated, e.server_id, EXTRACT(EPOCH FROM e.first_seen)::BIGINT
+        FROM emoji e
+            LEFT JOIN emoji_use eu ON eu.emoji_id = e.id
+                AND ($2::VARCHAR IS NULL OR eu.source = $2)
+                AND ($3::BIGINT IS NULL OR eu.used_at >= to_timestamp($3))
+                AND ($4::BIGINT IS NULL OR eu.used_at < to_timestamp($4))
+        WHERE e.server_id = $1 AND e.is_custom_emoji = TRUE AND e.available = TRUE
+        GROUP BY e.is_custom_emoji, e.id, e.name, e.animated, e.server_id, e.first_seen
+        HAVING NOT $5 OR COALESCE(SUM(eu.use_count), 0) = 0
+        ORDER BY COALESCE(SUM(eu.use_count), 0) ASC, e.first_seen ASC, e.id
+        LIMIT $6 OFFSET $7;"#;
+
+        let (source, start, end) = filter_params(filter);
+
+        let result = self.conn
+            .query(QUERY_SELECT_LEAST_USED_SERVER_EMOJI,
+                   &[&(server_id.0 as i64),
+                     &source,
+                     &start,
+                     &end,
+                     &unused_only,
+                     &page.limit(),
+                     &page.offset()])?;
+
+        let mut least_used_emoji = Vec::new();
+
+        for row in result.iter() {
+            least_used_emoji.push((row_into_emoji(&row),
+                                   row.get::<usize, i64>(3),
+                                   row.get::<usize, i64>(6)));
+        }
+
+        Ok(least_used_emoji)
+    }
+
+    pub fn get_top_emoji(&self,
+                         scope: &Scope,
+                         filter: &UsageFilter,
+                         page: &Page)
+                         -> postgres::Result<Vec<(Emoji, i64)>> {
+        match *scope {
+            Scope::Global => self.get_global_top_emoji(filter, page),
+            Scope::Server(ref server_id) => self.get_server_top_emoji(server_id, filter, page),
+            Scope::Channel(ref channel_id) => self.get_channel_top_emoji(channel_id, filter, page),
+            Scope::User(ref user_id, ref server_id) => {
+                self.get_user_top_emoji(user_id, server_id.as_ref(), filter, page)
+            }
+            Scope::Role(ref role_id, ref server_id) => {
+                self.get_role_top_emoji(role_id, server_id, filter, page)
+            }
+        }
+    }
+
+    // Users are only ranked on servers, in channels and in roles
+    pub fn get_top_users(&self,
+                         scope: &Scope,
+                         filter: &UsageFilter,
+                         page: &Page)
+                         -> postgres::Result<Vec<(String, i64)>> {
+        match *scope {
+            Scope::Server(ref server_id) => self.get_server_top_users(server_id, filter, page),
+            Scope::Channel(ref channel_id) => self.get_channel_top_users(channel_id, filter, page),
+            Scope::Role(ref role_id, ref server_id) => {
+                self.get_role_top_users(role_id, server_id, filter, page)
+            }
+            Scope::Global | Scope::User(..) => Ok(Vec::new()),
+        }
+    }
+
+    pub fn get_server_top_users(&self,
+                                server_id: &ServerId,
+                                filter: &UsageFilter,
+                                page: &Page)
+                                -> postgres::Result<Vec<(String, i64)>> {
+        const QUERY_SELECT_TOP_SERVER_USERS: &str = r#"
+        SELECT u.name, u.discriminator, SUM(eu.use_count)
+        FROM emoji_use eu
+            INNER JOIN user_ u ON eu.user_id = u.id
+            INNER JOIN emoji e ON eu.emoji_id = e.id
+            INNER JOIN channel c ON eu.channel_id = c.id
+        WHERE c.server_id = $1
+            AND ($2::VARCHAR IS NULL OR eu.source = $2)
+            AND ($3::BIGINT IS NULL OR eu.used_at >= to_timestamp($3))
+            AND ($4::BIGINT IS NULL OR eu.used_at < to_timestamp($4))
+            AND (e.is_custom_emoji AND e.server_id IS NULL) = $5
+        GROUP BY u.id, u.name, u.discriminator
+        ORDER BY SUM(eu.use_count) DESC, u.id
+        LIMIT $6 OFFSET $7;"#;
+
+        let (source, start, end) = filter_params(filter);
+
+        let result = self.conn
+            .query(QUERY_SELECT_TOP_SERVER_USERS,
+                   &[&(server_id.0 as i64),
+                     &source,
+                     &start,
+                     &end,
+                     &filter.external,
+                     &page.limit(),
+                     &page.offset()])?;
+
+        Ok(result_into_vec_users(result)?)
+    }
+
+    pub fn get_channel_top_users(&self,
+                                 channel_id: &ChannelId,
+                                 filter: &UsageFilter,
+                                 page: &Page)
+                                 -> postgres::Result<Vec<(String, i64)>> {
+        const QUERY_SELECT_TOP_CHANNEL_USERS: &str = r#"
+        SELECT u.name, u.discriminator, SUM(eu.use_count)
+        FROM emoji_use eu
+            INNER JOIN user_ u ON eu.user_id = u.id
+            INNER JOIN emoji e ON eu.emoji_id = e.id
+        WHERE eu.channel_id = $1
+            AND ($2::VARCHAR IS NULL OR eu.source = $2)
+            AND ($3::BIGINT IS NULL OR eu.used_at >= to_timestamp($3))
+            AND ($4::BIGINT IS NULL OR eu.used_at < to_timestamp($4))
+            AND (e.is_custom_emoji AND e.server_id IS NULL) = $5
+        GROUP BY u.id, u.name, u.discriminator
+        ORDER BY SUM(eu.use_count) DESC, u.id
+        LIMIT $6 OFFSET $7;"#;
+
+        let (source, start, end) = filter_params(filter);
 
         let result = self.conn
-            .query(QUERY_SELECT_TOP_SERVER_EMOJI, &[&(server_id.0 as i64)])?;
+            .query(QUERY_SELECT_TOP_CHANNEL_USERS,
+                   &[&(channel_id.0 as i64),
+                     &source,
+                     &start,
+                     &end,
+                     &filter.external,
+                     &page.limit(),
+                     &page.offset()])?;
 
-        Ok(result_into_vec_emoji(result)?)
+        Ok(result_into_vec_users(result)?)
     }
 
-    pub fn get_channel_top_emoji(&self,
-                                 channel_id: &ChannelId)
-                                 -> postgres::Result<Vec<(Emoji, i64)>> {
-        const QUERY_SELECT_TOP_CHANNEL_EMOJI: &str = r#"
-        SELECT e.is_custom_emoji, e.id, e.name, SUM(eu.use_count)
-        FROM emoji_usage eu
+    pub fn get_role_top_users(&self,
+                              role_id: &RoleId,
+                              server_id: &ServerId,
+                              filter: &UsageFilter,
+                              page: &Page)
+                              -> postgres::Result<Vec<(String, i64)>> {
+        const QUERY_SELECT_TOP_ROLE_USERS: &str = r#"
+        SELECT u.name, u.discriminator, SUM(eu.use_count)
+        FROM emoji_use eu
+            INNER JOIN user_ u ON eu.user_id = u.id
             INNER JOIN emoji e ON eu.emoji_id = e.id
-        WHERE eu.channel_id = $1
-        GROUP BY e.is_custom_emoji, e.id, e.name
-        ORDER BY SUM(eu.use_count) DESC
-        LIMIT 5;"#;
+            INNER JOIN channel c ON eu.channel_id = c.id
+            INNER JOIN member_role mr ON eu.user_id = mr.user_id
+        WHERE mr.role_id = $1 AND c.server_id = $2
+            AND ($3::VARCHAR IS NULL OR eu.source = $3)
+            AND ($4::BIGINT IS NULL OR eu.used_at >= to_timestamp($4))
+            AND ($5::BIGINT IS NULL OR eu.used_at < to_timestamp($5))
+            AND (e.is_custom_emoji AND e.server_id IS NULL) = $6
+        GROUP BY u.id, u.name, u.discriminator
+        ORDER BY SUM(eu.use_count) DESC, u.id
+        LIMIT $7 OFFSET $8;"#;
+
+        let (source, start, end) = filter_params(filter);
 
         let result = self.conn
-            .query(QUERY_SELECT_TOP_CHANNEL_EMOJI, &[&(channel_id.0 as i64)])?;
+            .query(QUERY_SELECT_TOP_ROLE_USERS,
+                   &[&(role_id.0 as i64),
+                     &(server_id.0 as i64),
+                     &source,
+                     &start,
+                     &end,
+                     &filter.external,
+                     &page.limit(),
+                     &page.offset()])?;
 
-        Ok(result_into_vec_emoji(result)?)
+        Ok(result_into_vec_users(result)?)
     }
 
-    pub fn get_user_top_emoji(&self,
-                              user_id: &UserId,
-                              server_id: Option<&ServerId>)
-                              -> postgres::Result<Vec<(Emoji, i64)>> {
-        const QUERY_SELECT_TOP_USER_UNICODE_EMOJI: &str = r#"
-        SELECT e.is_custom_emoji, e.id, e.name, SUM(eu.use_count)
-        FROM emoji_usage eu
-            INNER JOIN emoji e ON eu.emoji_id = e.id
-        WHERE e.is_custom_emoji = FALSE AND eu.user_id = $1
-        GROUP BY e.is_custom_emoji, e.id, e.name
-        ORDER BY SUM(eu.use_count) DESC
-        LIMIT 5;"#;
-
-        const QUERY_SELECT_TOP_USER_SERVER_EMOJI: &str = r#"
-        SELECT e.is_custom_emoji, e.id, e.name, SUM(eu.use_count)
-        FROM emoji_usage eu
-            INNER JOIN emoji e ON eu.emoji_id = e.id
-        WHERE (eu.user_id = $1) AND (e.server_id IS NULL OR e.server_id = $2)
-        GROUP BY e.is_custom_emoji, e.id, e.name
-        ORDER BY SUM(eu.use_count) DESC
-        LIMIT 5;"#;
-
-        let result = match server_id {
-            Some(server_id) => {
-                self.conn
-                    .query(QUERY_SELECT_TOP_USER_SERVER_EMOJI,
-                           &[&(user_id.0 as i64), &(server_id.0 as i64)])?
-            }
-            None => {
-                self.conn
-                    .query(QUERY_SELECT_TOP_USER_UNICODE_EMOJI, &[&(user_id.0 as i64)])?
+    // Ranks the user among everyone who has used emoji on the server, or anywhere if no server is
+    // given, by how many emoji they've used, or by how many times they've used a particular emoji
+    // Returns None if the user hasn't used any
+    pub fn get_user_rank(&self,
+                         user_id: &UserId,
+                         server_id: Option<&ServerId>,
+                         emoji: Option<&Emoji>,
+                         filter: &UsageFilter)
+                         -> postgres::Result<Option<UserRank>> {
+        const QUERY_SELECT_USER_RANK: &str = r#"
+        WITH user_usage AS (
+            SELECT eu.user_id, SUM(eu.use_count) AS use_count
+            FROM emoji_use eu
+                INNER JOIN emoji e ON eu.emoji_id = e.id
+                INNER JOIN channel c ON eu.channel_id = c.id
+            WHERE ($1::BIGINT IS NULL OR c.server_id = $1)
+                AND ($2::BIGINT IS NULL OR eu.emoji_id = $2 OR eu.variant_id = $2)
+                AND ($3::VARCHAR IS NULL OR eu.source = $3)
+                AND ($4::BIGINT IS NULL OR eu.used_at >= to_timestamp($4))
+                AND ($5::BIGINT IS NULL OR eu.used_at < to_timestamp($5))
+                AND ($2::BIGINT IS NOT NULL OR (e.is_custom_emoji AND e.server_id IS NULL) = $6)
+                AND eu.user_id <> $8
+            GROUP BY eu.user_id
+        ), user_rank AS (
+            SELECT user_id, use_count,
+                RANK() OVER (ORDER BY use_count DESC) AS rank,
+                COUNT(*) OVER () AS num_users
+            FROM user_usage
+        )
+        SELECT rank, num_users, use_count::BIGINT
+        FROM user_rank
+        WHERE user_id = $7;"#;
+
+        let emoji_id = match emoji {
+            Some(emoji) => {
+                match self.get_emoji_db_id(emoji)? {
+                    Some(emoji_id) => Some(emoji_id),
+                    None => return Ok(None),
+                }
             }
+            None => None,
         };
 
-        Ok(result_into_vec_emoji(result)?)
+        let (source, start, end) = filter_params(filter);
+
+        let result = self.conn
+            .query(QUERY_SELECT_USER_RANK,
+                   &[&server_id.map(|server_id| server_id.0 as i64),
+                     &emoji_id,
+                     &source,
+                     &start,
+                     &end,
+                     &filter.external,
+                     &(user_id.0 as i64),
+                     &(ANONYMOUS_USER_ID.0 as i64)])?;
+
+        match result.iter().next() {
+            Some(row) => {
+                Ok(Some(UserRank {
+                            rank: row.get::<usize, i64>(0),
+                            num_users: row.get::<usize, i64>(1),
+                            use_count: row.get::<usize, i64>(2),
+                        }))
+            }
+            None => Ok(None),
+        }
     }
 
-    pub fn get_server_top_users(&self,
-                                server_id: &ServerId)
-                                -> postgres::Result<Vec<(String, i64)>> {
-        const QUERY_SELECT_TOP_SERVER_USERS: &str = r#"
-        SELECT u.name, u.discriminator, SUM(m.emoji_count)
-        FROM message m
-            INNER JOIN user_ u ON m.user_id = u.id
-            INNER JOIN channel c ON m.channel_id = c.id
-        WHERE c.server_id = $1
-        GROUP BY u.name, u.discriminator
-        ORDER BY SUM(m.emoji_count) DESC
-        LIMIT 5;"#;
+    // Returns the emoji most often typed into the same messages as the emoji, on the server or
+    // everywhere if no server is given, with the number of messages they shared
+    pub fn get_paired_emoji(&self,
+                            emoji: &Emoji,
+                            server_id: Option<&ServerId>,
+                            filter: &UsageFilter,
+                            page: &Page)
+                            -> postgres::Result<Vec<(Emoji, i64)>> {
+        const QUERY_SELECT_PAIRED_EMOJI: &str = r#"
+        SELECT e.is_custom_emoji, e.id, e.name, COUNT(*), e.animated, e.server_id
+        FROM emoji_pair ep
+            INNER JOIN emoji e ON ep.other_emoji_id = e.id
+            INNER JOIN channel c ON ep.channel_id = c.id
+        WHERE ep.emoji_id = (SELECT COALESCE(base_id, id) FROM emoji WHERE id = $1)
+            AND ($2::BIGINT IS NULL OR c.server_id = $2)
+            AND ($3::BIGINT IS NULL OR ep.posted >= to_timestamp($3))
+            AND ($4::BIGINT IS NULL OR ep.posted < to_timestamp($4))
+            AND (e.is_custom_emoji AND e.server_id IS NULL) = $5
+        GROUP BY e.is_custom_emoji, e.id, e.name, e.animated, e.server_id
+        ORDER BY COUNT(*) DESC, e.id
+        LIMIT $6 OFFSET $7;"#;
+
+        let emoji_id = match self.get_emoji_db_id(emoji)? {
+            Some(emoji_id) => emoji_id,
+            None => return Ok(Vec::new()),
+        };
+
+        let (_, start, end) = filter_params(filter);
 
         let result = self.conn
-            .query(QUERY_SELECT_TOP_SERVER_USERS, &[&(server_id.0 as i64)])?;
+            .query(QUERY_SELECT_PAIRED_EMOJI,
+                   &[&emoji_id,
+                     &server_id.map(|server_id| server_id.0 as i64),
+                     &start,
+                     &end,
+                     &filter.external,
+                     &page.limit(),
+                     &page.offset()])?;
 
-        Ok(result_into_vec_users(result)?)
+        Ok(result_into_vec_emoji(result)?)
     }
 
-    pub fn get_channel_top_users(&self,
-                                 channel_id: &ChannelId)
-                                 -> postgres::Result<Vec<(String, i64)>> {
-        const QUERY_SELECT_TOP_CHANNEL_USERS: &str = r#"
-        SELECT u.name, u.discriminator, SUM(m.emoji_count)
-        FROM message m
-            INNER JOIN user_ u ON m.user_id = u.id
-        WHERE m.channel_id = $1
-        GROUP BY u.name, u.discriminator
-        ORDER BY SUM(m.emoji_count) DESC
-        LIMIT 5;"#;
+    // Returns the pairs of emoji most often typed into the same messages, on the server or
+    // everywhere if no server is given, with the number of messages they shared
+    pub fn get_top_emoji_pairs(&self,
+                               server_id: Option<&ServerId>,
+                               filter: &UsageFilter,
+                               page: &Page)
+                               -> postgres::Result<Vec<(Emoji, Emoji, i64)>> {
+        const QUERY_SELECT_TOP_EMOJI_PAIRS: &str = r#"
+        SELECT e1.is_custom_emoji, e1.id, e1.name, COUNT(*), e1.animated, e1.server_id,
+            e2.is_custom_emoji, e2.id, e2.name, COUNT(*), e2.animated, e2.server_id
+        FROM emoji_pair ep
+            INNER JOIN emoji e1 ON ep.emoji_id = e1.id
+            INNER JOIN emoji e2 ON ep.other_emoji_id = e2.id
+            INNER JOIN channel c ON ep.channel_id = c.id
+        WHERE ep.emoji_id < ep.other_emoji_id
+            AND ($1::BIGINT IS NULL OR c.server_id = $1)
+            AND ($2::BIGINT IS NULL OR ep.posted >= to_timestamp($2))
+            AND ($3::BIGINT IS NULL OR ep.posted < to_timestamp($3))
+            AND (e1.is_custom_emoji AND e1.server_id IS NULL) = $4
+            AND (e2.is_custom_emoji AND e2.server_id IS NULL) = $4
+        GROUP BY e1.is_custom_emoji, e1.id, e1.name, e1.animated, e1.server_id,
+            e2.is_custom_emoji, e2.id, e2.name, e2.animated, e2.server_id
+        ORDER BY COUNT(*) DESC, e1.id, e2.id
+        LIMIT $5 OFFSET $6;"#;
+
+        let (_, start, end) = filter_params(filter);
 
         let result = self.conn
-            .query(QUERY_SELECT_TOP_CHANNEL_USERS, &[&(channel_id.0 as i64)])?;
+            .query(QUERY_SELECT_TOP_EMOJI_PAIRS,
+                   &[&server_id.map(|server_id| server_id.0 as i64),
+                     &start,
+                     &end,
+                     &filter.external,
+                     &page.limit(),
+                     &page.offset()])?;
+
+        // row
+        // column 0-5: the first emoji and the number of messages, as in result_into_vec_emoji
+        // column 6-11: the second emoji, laid out the same way
+        let mut pairs = Vec::new();
+
+        for row in result.iter() {
+            pairs.push((row_into_emoji_at(&row, 0),
+                        row_into_emoji_at(&row, 6),
+                        row.get::<usize, i64>(3)));
+        }
 
-        Ok(result_into_vec_users(result)?)
+        Ok(pairs)
     }
 
-    pub fn get_emoji_usage(&self, emoji: &Emoji) -> postgres::Result<Option<i64>> {
+    // Returns the number of times the emoji was used on the server, or everywhere if no server is
+    // given
+    pub fn get_emoji_usage(&self,
+                           emoji: &Emoji,
+                           server_id: Option<&ServerId>,
+                           filter: &UsageFilter)
+                           -> postgres::Result<Option<i64>> {
         const QUERY_EMOJI_USAGE: &str = r#"
         SELECT SUM(eu.use_count)
-        FROM emoji_usage eu
-        WHERE eu.emoji_id = $1;"#;
+        FROM emoji_use eu
+            INNER JOIN channel c ON eu.channel_id = c.id
+        WHERE (eu.emoji_id = $1 OR eu.variant_id = $1)
+            AND ($2::VARCHAR IS NULL OR eu.source = $2)
+            AND ($3::BIGINT IS NULL OR eu.used_at >= to_timestamp($3))
+            AND ($4::BIGINT IS NULL OR eu.used_at < to_timestamp($4))
+            AND ($5::BIGINT IS NULL OR c.server_id = $5);"#;
 
         let emoji_id = match *emoji {
             Emoji::Custom(ref emoji) => emoji.id.0 as i64,
@@ -325,7 +1753,15 @@ impl Database {
             }
         };
 
-        let result = self.conn.query(QUERY_EMOJI_USAGE, &[&emoji_id])?;
+        let (source, start, end) = filter_params(filter);
+
+        let result = self.conn
+            .query(QUERY_EMOJI_USAGE,
+                   &[&emoji_id,
+                     &source,
+                     &start,
+                     &end,
+                     &server_id.map(|server_id| server_id.0 as i64)])?;
 
         match result.iter().next() {
             Some(row) => {
@@ -338,6 +1774,611 @@ impl Database {
         }
     }
 
+    // Returns the users who have used the emoji the most on the server, or everywhere if no
+    // server is given
+    pub fn get_emoji_top_users(&self,
+                               emoji: &Emoji,
+                               server_id: Option<&ServerId>,
+                               filter: &UsageFilter,
+                               page: &Page)
+                               -> postgres::Result<Vec<(String, i64)>> {
+        const QUERY_SELECT_EMOJI_TOP_USERS: &str = r#"
+        SELECT u.name, u.discriminator, SUM(eu.use_count)
+        FROM emoji_use eu
+            INNER JOIN user_ u ON eu.user_id = u.id
+            INNER JOIN channel c ON eu.channel_id = c.id
+        WHERE (eu.emoji_id = $1 OR eu.variant_id = $1)
+            AND ($2::BIGINT IS NULL OR c.server_id = $2)
+            AND ($3::VARCHAR IS NULL OR eu.source = $3)
+            AND ($4::BIGINT IS NULL OR eu.used_at >= to_timestamp($4))
+            AND ($5::BIGINT IS NULL OR eu.used_at < to_timestamp($5))
+        GROUP BY u.id, u.name, u.discriminator
+        ORDER BY SUM(eu.use_count) DESC, u.id
+        LIMIT $6 OFFSET $7;"#;
+
+        let emoji_id = match self.get_emoji_db_id(emoji)? {
+            Some(emoji_id) => emoji_id,
+            None => return Ok(Vec::new()),
+        };
+
+        let (source, start, end) = filter_params(filter);
+
+        let result = self.conn
+            .query(QUERY_SELECT_EMOJI_TOP_USERS,
+                   &[&emoji_id,
+                     &server_id.map(|server_id| server_id.0 as i64),
+                     &source,
+                     &start,
+                     &end,
+                     &page.limit(),
+                     &page.offset()])?;
+
+        Ok(result_into_vec_users(result)?)
+    }
+
+    // Returns the server's channels in which the emoji has been used the most
+    pub fn get_emoji_top_channels(&self,
+                                  emoji: &Emoji,
+                                  server_id: &ServerId,
+                                  filter: &UsageFilter,
+                                  page: &Page)
+                                  -> postgres::Result<Vec<(ChannelId, i64)>> {
+        const QUERY_SELECT_EMOJI_TOP_CHANNELS: &str = r#"
+        SELECT c.id, SUM(eu.use_count)
+        FROM emoji_use eu
+            INNER JOIN channel c ON eu.channel_id = c.id
+        WHERE (eu.emoji_id = $1 OR eu.variant_id = $1)
+            AND c.server_id = $2
+            AND ($3::VARCHAR IS NULL OR eu.source = $3)
+            AND ($4::BIGINT IS NULL OR eu.used_at >= to_timestamp($4))
+            AND ($5::BIGINT IS NULL OR eu.used_at < to_timestamp($5))
+        GROUP BY c.id
+        ORDER BY SUM(eu.use_count) DESC, c.id
+        LIMIT $6 OFFSET $7;"#;
+
+        let emoji_id = match self.get_emoji_db_id(emoji)? {
+            Some(emoji_id) => emoji_id,
+            None => return Ok(Vec::new()),
+        };
+
+        let (source, start, end) = filter_params(filter);
+
+        let result = self.conn
+            .query(QUERY_SELECT_EMOJI_TOP_CHANNELS,
+                   &[&emoji_id,
+                     &(server_id.0 as i64),
+                     &source,
+                     &start,
+                     &end,
+                     &page.limit(),
+                     &page.offset()])?;
+
+        let mut channels = Vec::new();
+
+        for row in result.iter() {
+            channels.push((ChannelId(row.get::<usize, i64>(0) as u64), row.get::<usize, i64>(1)));
+        }
+
+        Ok(channels)
+    }
+
+    // Returns when the emoji was first and most recently used on the server, or anywhere if no
+    // server is given, in seconds since the Unix epoch
+    // Returns None if it hasn't been used
+    pub fn get_emoji_first_and_last_use(&self,
+                                        emoji: &Emoji,
+                                        server_id: Option<&ServerId>,
+                                        filter: &UsageFilter)
+                                        -> postgres::Result<Option<(i64, i64)>> {
+        const QUERY_SELECT_EMOJI_FIRST_AND_LAST_USE: &str = r#"
+        SELECT EXTRACT(EPOCH FROM MIN(eu.used_at))::BIGINT,
+            EXTRACT(EPOCH FROM MAX(eu.used_at))::BIGINT
+        FROM emoji_use eu
+            INNER JOIN channel c ON eu.channel_id = c.id
+        WHERE (eu.emoji_id = $1 OR eu.variant_id = $1)
+            AND ($2::BIGINT IS NULL OR c.server_id = $2)
+            AND ($3::VARCHAR IS NULL OR eu.source = $3)
+            AND ($4::BIGINT IS NULL OR eu.used_at >= to_timestamp($4))
+            AND ($5::BIGINT IS NULL OR eu.used_at < to_timestamp($5));"#;
+
+        let emoji_id = match self.get_emoji_db_id(emoji)? {
+            Some(emoji_id) => emoji_id,
+            None => return Ok(None),
+        };
+
+        let (source, start, end) = filter_params(filter);
+
+        let result = self.conn
+            .query(QUERY_SELECT_EMOJI_FIRST_AND_LAST_USE,
+                   &[&emoji_id,
+                     &server_id.map(|server_id| server_id.0 as i64),
+                     &source,
+                     &start,
+                     &end])?;
+
+        match result.iter().next() {
+            Some(row) => {
+                match (row.get::<usize, Option<i64>>(0), row.get::<usize, Option<i64>>(1)) {
+                    (Some(first), Some(last)) => Ok(Some((first, last))),
+                    _ => Ok(None),
+                }
+            }
+            None => Ok(None),
+        }
+    }
+
+    // Ranks the emoji among all emoji of the same kind used on the server, or everywhere if no
+    // server is given, by how many times they've been used
+    // External custom emoji are only ranked against other external emoji
+    // Returns None if the emoji hasn't been used
+    pub fn get_emoji_rank(&self,
+                          emoji: &Emoji,
+                          server_id: Option<&ServerId>,
+                          filter: &UsageFilter)
+                          -> postgres::Result<Option<EmojiRank>> {
+        const QUERY_SELECT_EMOJI_RANK: &str = r#"
+        WITH emoji_usage AS (
+            SELECT eu.emoji_id, SUM(eu.use_count) AS use_count
+            FROM emoji_use eu
+                INNER JOIN emoji e ON eu.emoji_id = e.id
+                INNER JOIN channel c ON eu.channel_id = c.id
+            WHERE ($2::BIGINT IS NULL OR c.server_id = $2)
+                AND ($3::VARCHAR IS NULL OR eu.source = $3)
+                AND ($4::BIGINT IS NULL OR eu.used_at >= to_timestamp($4))
+                AND ($5::BIGINT IS NULL OR eu.used_at < to_timestamp($5))
+                AND (e.is_custom_emoji AND e.server_id IS NULL) =
+                    (SELECT te.is_custom_emoji AND te.server_id IS NULL
+                     FROM emoji te
+                     WHERE te.id = $1)
+            GROUP BY eu.emoji_id
+        ), emoji_rank AS (
+            SELECT emoji_id, use_count,
+                RANK() OVER (ORDER BY use_count DESC) AS rank,
+                COUNT(*) OVER () AS num_emoji
+            FROM emoji_usage
+        )
+        SELECT rank, num_emoji, use_count::BIGINT
+        FROM emoji_rank
+        WHERE emoji_id = (SELECT COALESCE(base_id, id) FROM emoji WHERE id = $1);"#;
+
+        let emoji_id = match self.get_emoji_db_id(emoji)? {
+            Some(emoji_id) => emoji_id,
+            None => return Ok(None),
+        };
+
+        let (source, start, end) = filter_params(filter);
+
+        let result = self.conn
+            .query(QUERY_SELECT_EMOJI_RANK,
+                   &[&emoji_id,
+                     &server_id.map(|server_id| server_id.0 as i64),
+                     &source,
+                     &start,
+                     &end])?;
+
+        match result.iter().next() {
+            Some(row) => {
+                Ok(Some(EmojiRank {
+                            rank: row.get::<usize, i64>(0),
+                            num_emoji: row.get::<usize, i64>(1),
+                            use_count: row.get::<usize, i64>(2),
+                        }))
+            }
+            None => Ok(None),
+        }
+    }
+
+    // Returns the number of times each variant of an emoji was used, e.g. 👍🏽 for 👍
+    pub fn get_variant_usage(&self,
+                             emoji: &Emoji,
+                             filter: &UsageFilter)
+                             -> postgres::Result<Vec<(Emoji, i64)>> {
+        const QUERY_SELECT_VARIANT_USAGE: &str = r#"
+        SELECT e.is_custom_emoji, e.id, e.name, SUM(eu.use_count), e.animated, e.server_id
+        FROM emoji_use eu
+            INNER JOIN emoji e ON eu.variant_id = e.id
+        WHERE eu.emoji_id = $1 AND eu.variant_id <> $1
+            AND ($2::VARCHAR IS NULL OR eu.source = $2)
+            AND ($3::BIGINT IS NULL OR eu.used_at >= to_timestamp($3))
+            AND ($4::BIGINT IS NULL OR eu.used_at < to_timestamp($4))
+        GROUP BY e.is_custom_emoji, e.id, e.name, e.animated, e.server_id
+        ORDER BY SUM(eu.use_count) DESC;"#;
+
+        let emoji_id = match self.get_emoji_db_id(emoji)? {
+            Some(emoji_id) => emoji_id,
+            None => return Ok(Vec::new()),
+        };
+
+        let (source, start, end) = filter_params(filter);
+
+        let result = self.conn
+            .query(QUERY_SELECT_VARIANT_USAGE, &[&emoji_id, &source, &start, &end])?;
+
+        Ok(result_into_vec_emoji(result)?)
+    }
+
+    // Returns the number of times emoji were used in each bucket of the filter's period, oldest
+    // first, as (bucket label, count)
+    // If the period has no start, the trend begins at the first bucket with any usage
+    pub fn get_usage_trend(&self,
+                           scope: &Scope,
+                           emoji: Option<&Emoji>,
+                           filter: &UsageFilter,
+                           bucket: Bucket)
+                           -> postgres::Result<Vec<(String, i64)>> {
+        const QUERY_SELECT_USAGE_TREND: &str = r#"
+        WITH bucket_usage AS (
+            SELECT date_trunc($1, eu.used_at) AS bucket, SUM(eu.use_count) AS use_count
+            FROM emoji_use eu
+                INNER JOIN emoji e ON eu.emoji_id = e.id
+                INNER JOIN channel c ON eu.channel_id = c.id
+            WHERE eu.used_at IS NOT NULL
+                AND ($2::BIGINT IS NULL OR c.server_id = $2)
+                AND ($3::BIGINT IS NULL OR eu.channel_id = $3)
+                AND ($4::BIGINT IS NULL OR eu.user_id = $4)
+                AND ($5::BIGINT IS NULL OR eu.emoji_id = $5 OR eu.variant_id = $5)
+                AND (e.is_custom_emoji = FALSE OR $6 OR e.server_id = $7)
+                AND ($8::VARCHAR IS NULL OR eu.source = $8)
+                AND ($9::BIGINT IS NULL OR eu.used_at >= to_timestamp($9))
+                AND ($10::BIGINT IS NULL OR eu.used_at < to_timestamp($10))
+                AND ($12::BIGINT IS NULL
+                    OR eu.user_id IN (SELECT mr.user_id FROM member_role mr WHERE mr.role_id = $12))
+            GROUP BY date_trunc($1, eu.used_at)
+        )
+        SELECT to_char(b.bucket, $11), COALESCE(bu.use_count, 0)::BIGINT
+        FROM generate_series(
+                date_trunc($1, COALESCE(to_timestamp($9)::TIMESTAMP,
+                                        (SELECT MIN(bucket) FROM bucket_usage))),
+                date_trunc($1, COALESCE(to_timestamp($10)::TIMESTAMP - INTERVAL '1 second',
+                                        NOW()::TIMESTAMP)),
+                ('1 ' || $1)::INTERVAL) AS b (bucket)
+            LEFT JOIN bucket_usage bu ON b.bucket = bu.bucket
+        ORDER BY b.bucket;"#;
+
+        let emoji_id = match emoji {
+            Some(emoji) => {
+                match self.get_emoji_db_id(emoji)? {
+                    Some(emoji_id) => Some(emoji_id),
+                    None => return Ok(Vec::new()),
+                }
+            }
+            None => None,
+        };
+
+        let (server_id, channel_id, user_id, role_id, custom_emoji_server_id) = scope_params(scope);
+        // Custom emoji from every server are included when looking at a server, channel or role,
+        // or when looking at a specific emoji
+        let all_custom_emoji = match *scope {
+            Scope::Server(_) | Scope::Channel(_) | Scope::Role(..) => true,
+            Scope::Global | Scope::User(_, _) => emoji_id.is_some(),
+        };
+        let (source, start, end) = filter_params(filter);
+        let label_format = match bucket {
+            Bucket::Day | Bucket::Week => "YYYY-MM-DD",
+            Bucket::Month => "YYYY-MM",
+        };
+
+        let result = self.conn
+            .query(QUERY_SELECT_USAGE_TREND,
+                   &[&bucket.unit(),
+                     &server_id,
+                     &channel_id,
+                     &user_id,
+                     &emoji_id,
+                     &all_custom_emoji,
+                     &custom_emoji_server_id,
+                     &source,
+                     &start,
+                     &end,
+                     &label_format,
+                     &role_id])?;
+
+        let mut trend = Vec::new();
+
+        for row in result.iter() {
+            trend.push((row.get::<usize, String>(0), row.get::<usize, i64>(1)));
+        }
+
+        Ok(trend)
+    }
+
+    // Counts the messages posted and the emoji typed into them; the usage source and external
+    // emoji filters don't apply since only messages are counted
+    pub fn get_activity(&self,
+                        scope: &Scope,
+                        filter: &UsageFilter)
+                        -> postgres::Result<ActivityStats> {
+        const QUERY_SELECT_ACTIVITY: &str = r#"
+        SELECT COUNT(*),
+            COUNT(*) FILTER (WHERE m.emoji_count > 0),
+            COALESCE(SUM(m.emoji_count), 0)::BIGINT
+        FROM counted_message m
+            INNER JOIN channel c ON m.channel_id = c.id
+        WHERE ($1::BIGINT IS NULL OR c.server_id = $1)
+            AND ($2::BIGINT IS NULL OR m.channel_id = $2)
+            AND ($3::BIGINT IS NULL OR m.user_id = $3)
+            AND ($4::BIGINT IS NULL OR m.posted >= to_timestamp($4))
+            AND ($5::BIGINT IS NULL OR m.posted < to_timestamp($5))
+            AND ($6::BIGINT IS NULL
+                OR m.user_id IN (SELECT mr.user_id FROM member_role mr WHERE mr.role_id = $6));"#;
+
+        let (server_id, channel_id, user_id, role_id, user_server_id) = scope_params(scope);
+        let (_, start, end) = filter_params(filter);
+
+        let result = self.conn
+            .query(QUERY_SELECT_ACTIVITY,
+                   &[&server_id.or(user_server_id),
+                     &channel_id,
+                     &user_id,
+                     &start,
+                     &end,
+                     &role_id])?;
+
+        match result.iter().next() {
+            Some(row) => {
+                Ok(ActivityStats {
+                       num_messages: row.get::<usize, i64>(0),
+                       num_messages_with_emoji: row.get::<usize, i64>(1),
+                       num_emoji: row.get::<usize, i64>(2),
+                   })
+            }
+            None => Ok(ActivityStats::default()),
+        }
+    }
+
+    // Ranks users on a server, in a channel or in a role by the number of emoji per message they
+    // post
+    pub fn get_most_expressive_users(&self,
+                                     scope: &Scope,
+                                     filter: &UsageFilter,
+                                     page: &Page)
+                                     -> postgres::Result<Vec<(String, f64)>> {
+        const QUERY_SELECT_EXPRESSIVE_USERS: &str = r#"
+        SELECT u.name, u.discriminator, SUM(m.emoji_count)::FLOAT8 / COUNT(*)
+        FROM counted_message m
+            INNER JOIN user_ u ON m.user_id = u.id
+            INNER JOIN channel c ON m.channel_id = c.id
+        WHERE ($1::BIGINT IS NULL OR c.server_id = $1)
+            AND ($2::BIGINT IS NULL OR m.channel_id = $2)
+            AND ($3::BIGINT IS NULL OR m.posted >= to_timestamp($3))
+            AND ($4::BIGINT IS NULL OR m.posted < to_timestamp($4))
+            AND ($8::BIGINT IS NULL
+                OR m.user_id IN (SELECT mr.user_id FROM member_role mr WHERE mr.role_id = $8))
+        GROUP BY u.id, u.name, u.discriminator
+        HAVING COUNT(*) >= $5 AND SUM(m.emoji_count) > 0
+        ORDER BY SUM(m.emoji_count)::FLOAT8 / COUNT(*) DESC, u.id
+        LIMIT $6 OFFSET $7;"#;
+
+        let (server_id, channel_id, role_id) = match *scope {
+            Scope::Server(server_id) => (Some(server_id.0 as i64), None, None),
+            Scope::Channel(channel_id) => (None, Some(channel_id.0 as i64), None),
+            Scope::Role(role_id, server_id) => {
+                (Some(server_id.0 as i64), None, Some(role_id.0 as i64))
+            }
+            Scope::Global | Scope::User(..) => return Ok(Vec::new()),
+        };
+        let (_, start, end) = filter_params(filter);
+
+        let result = self.conn
+            .query(QUERY_SELECT_EXPRESSIVE_USERS,
+                   &[&server_id,
+                     &channel_id,
+                     &start,
+                     &end,
+                     &MIN_EXPRESSIVE_MESSAGES,
+                     &page.limit(),
+                     &page.offset(),
+                     &role_id])?;
+
+        let mut users = Vec::new();
+
+        for row in result.iter() {
+            users.push((row.get::<usize, String>(0), row.get::<usize, f64>(2)));
+        }
+
+        Ok(users)
+    }
+
+    // Ranks a server's channels by the number of emoji per message posted in them
+    pub fn get_most_expressive_channels(&self,
+                                        server_id: &ServerId,
+                                        filter: &UsageFilter,
+                                        page: &Page)
+                                        -> postgres::Result<Vec<(ChannelId, f64)>> {
+        const QUERY_SELECT_EXPRESSIVE_CHANNELS: &str = r#"
+        SELECT c.id, SUM(m.emoji_count)::FLOAT8 / COUNT(*)
+        FROM counted_message m
+            INNER JOIN channel c ON m.channel_id = c.id
+        WHERE c.server_id = $1
+            AND ($2::BIGINT IS NULL OR m.posted >= to_timestamp($2))
+            AND ($3::BIGINT IS NULL OR m.posted < to_timestamp($3))
+        GROUP BY c.id
+        HAVING COUNT(*) >= $4 AND SUM(m.emoji_count) > 0
+        ORDER BY SUM(m.emoji_count)::FLOAT8 / COUNT(*) DESC, c.id
+        LIMIT $5 OFFSET $6;"#;
+
+        let (_, start, end) = filter_params(filter);
+
+        let result = self.conn
+            .query(QUERY_SELECT_EXPRESSIVE_CHANNELS,
+                   &[&(server_id.0 as i64),
+                     &start,
+                     &end,
+                     &MIN_EXPRESSIVE_MESSAGES,
+                     &page.limit(),
+                     &page.offset()])?;
+
+        let mut channels = Vec::new();
+
+        for row in result.iter() {
+            channels.push((ChannelId(row.get::<usize, i64>(0) as u64), row.get::<usize, f64>(1)));
+        }
+
+        Ok(channels)
+    }
+
+    // Compares the emoji used by two users or roles, or in two channels
+    pub fn compare_scopes(&self,
+                          first: &Scope,
+                          second: &Scope,
+                          filter: &UsageFilter,
+                          page: &Page)
+                          -> postgres::Result<Comparison<Emoji>> {
+        const QUERY_COMPARE_SCOPES: &str = r#"
+        WITH side_usage AS (
+            SELECT s.side, eu.emoji_id, SUM(eu.use_count) AS use_count
+            FROM emoji_use eu
+                INNER JOIN emoji e ON eu.emoji_id = e.id
+                INNER JOIN channel c ON eu.channel_id = c.id
+                INNER JOIN (VALUES (1, $1::BIGINT, $2::BIGINT, $3::BIGINT, $4::BIGINT, $5::BIGINT),
+                                   (2, $6::BIGINT, $7::BIGINT, $8::BIGINT, $9::BIGINT, $10::BIGINT))
+                    AS s (side, server_id, channel_id, user_id, role_id, custom_emoji_server_id)
+                    ON (s.server_id IS NULL OR c.server_id = s.server_id)
+                        AND (s.channel_id IS NULL OR eu.channel_id = s.channel_id)
+                        AND (s.user_id IS NULL OR eu.user_id = s.user_id)
+                        AND (s.role_id IS NULL OR eu.user_id IN (SELECT mr.user_id
+                                                                 FROM member_role mr
+                                                                 WHERE mr.role_id = s.role_id))
+            WHERE ($11::VARCHAR IS NULL OR eu.source = $11)
+                AND ($12::BIGINT IS NULL OR eu.used_at >= to_timestamp($12))
+                AND ($13::BIGINT IS NULL OR eu.used_at < to_timestamp($13))
+                AND (e.is_custom_emoji AND e.server_id IS NULL) = $14
+                AND (s.user_id IS NULL OR e.is_custom_emoji = FALSE OR $14
+                    OR e.server_id = s.custom_emoji_server_id)
+            GROUP BY s.side, eu.emoji_id
+        ), emoji_usage AS (
+            SELECT emoji_id,
+                COALESCE(SUM(use_count) FILTER (WHERE side = 1), 0)::BIGINT AS first_count,
+                COALESCE(SUM(use_count) FILTER (WHERE side = 2), 0)::BIGINT AS second_count
+            FROM side_usage
+            GROUP BY emoji_id
+        ), ranked AS (
+            SELECT emoji_id, first_count, second_count,
+                ROW_NUMBER() OVER (ORDER BY first_count DESC, emoji_id) AS first_rank,
+                ROW_NUMBER() OVER (ORDER BY second_count DESC, emoji_id) AS second_rank,
+                ROW_NUMBER() OVER (ORDER BY LEAST(first_count, second_count) DESC,
+                                            first_count + second_count DESC,
+                                            emoji_id) AS shared_rank,
+                SUM(first_count) OVER ()::BIGINT AS first_total,
+                SUM(second_count) OVER ()::BIGINT AS second_total,
+                COUNT(*) FILTER (WHERE first_count > 0) OVER () AS first_num_emoji,
+                COUNT(*) FILTER (WHERE second_count > 0) OVER () AS second_num_emoji,
+                COUNT(*) FILTER (WHERE first_count > 0 AND second_count > 0) OVER ()
+                    AS num_shared_emoji
+            FROM emoji_usage
+        )
+        SELECT e.is_custom_emoji, e.id, e.name, r.first_count, e.animated, e.server_id,
+            r.first_count, r.second_count, r.first_rank, r.second_rank, r.shared_rank,
+            r.first_total, r.second_total, r.first_num_emoji, r.second_num_emoji,
+            r.num_shared_emoji
+        FROM ranked r
+            INNER JOIN emoji e ON r.emoji_id = e.id
+        WHERE r.first_rank <= $15 OR r.second_rank <= $15 OR r.shared_rank <= $15;"#;
+
+        let (first_server_id,
+             first_channel_id,
+             first_user_id,
+             first_role_id,
+             first_custom_emoji_server_id) = scope_params(first);
+        let (second_server_id,
+             second_channel_id,
+             second_user_id,
+             second_role_id,
+             second_custom_emoji_server_id) = scope_params(second);
+        let (source, start, end) = filter_params(filter);
+
+        let result = self.conn
+            .query(QUERY_COMPARE_SCOPES,
+                   &[&first_server_id,
+                     &first_channel_id,
+                     &first_user_id,
+                     &first_role_id,
+                     &first_custom_emoji_server_id,
+                     &second_server_id,
+                     &second_channel_id,
+                     &second_user_id,
+                     &second_role_id,
+                     &second_custom_emoji_server_id,
+                     &source,
+                     &start,
+                     &end,
+                     &filter.external,
+                     &page.limit()])?;
+
+        Ok(rows_into_comparison(result, 6, page.limit(), row_into_emoji))
+    }
+
+    // Compares the users of two emoji on a server, or everywhere if no server is given
+    pub fn compare_emoji(&self,
+                         first: &Emoji,
+                         second: &Emoji,
+                         server_id: Option<&ServerId>,
+                         filter: &UsageFilter,
+                         page: &Page)
+                         -> postgres::Result<Comparison<String>> {
+        const QUERY_COMPARE_EMOJI: &str = r#"
+        WITH side_usage AS (
+            SELECT s.side, eu.user_id, SUM(eu.use_count) AS use_count
+            FROM emoji_use eu
+                INNER JOIN channel c ON eu.channel_id = c.id
+                INNER JOIN (VALUES (1, $1::BIGINT), (2, $2::BIGINT)) AS s (side, emoji_id)
+                    ON eu.emoji_id = s.emoji_id OR eu.variant_id = s.emoji_id
+            WHERE ($3::BIGINT IS NULL OR c.server_id = $3)
+                AND ($4::VARCHAR IS NULL OR eu.source = $4)
+                AND ($5::BIGINT IS NULL OR eu.used_at >= to_timestamp($5))
+                AND ($6::BIGINT IS NULL OR eu.used_at < to_timestamp($6))
+            GROUP BY s.side, eu.user_id
+        ), user_usage AS (
+            SELECT user_id,
+                COALESCE(SUM(use_count) FILTER (WHERE side = 1), 0)::BIGINT AS first_count,
+                COALESCE(SUM(use_count) FILTER (WHERE side = 2), 0)::BIGINT AS second_count
+            FROM side_usage
+            GROUP BY user_id
+        ), ranked AS (
+            SELECT user_id, first_count, second_count,
+                ROW_NUMBER() OVER (ORDER BY first_count DESC, user_id) AS first_rank,
+                ROW_NUMBER() OVER (ORDER BY second_count DESC, user_id) AS second_rank,
+                ROW_NUMBER() OVER (ORDER BY LEAST(first_count, second_count) DESC,
+                                            first_count + second_count DESC,
+                                            user_id) AS shared_rank,
+                SUM(first_count) OVER ()::BIGINT AS first_total,
+                SUM(second_count) OVER ()::BIGINT AS second_total,
+                COUNT(*) FILTER (WHERE first_count > 0) OVER () AS first_num_users,
+                COUNT(*) FILTER (WHERE second_count > 0) OVER () AS second_num_users,
+                COUNT(*) FILTER (WHERE first_count > 0 AND second_count > 0) OVER ()
+                    AS num_shared_users
+            FROM user_usage
+        )
+        SELECT u.name, u.discriminator,
+            r.first_count, r.second_count, r.first_rank, r.second_rank, r.shared_rank,
+            r.first_total, r.second_total, r.first_num_users, r.second_num_users,
+            r.num_shared_users
+        FROM ranked r
+            INNER JOIN user_ u ON r.user_id = u.id
+        WHERE r.first_rank <= $7 OR r.second_rank <= $7 OR r.shared_rank <= $7;"#;
+
+        let first_id = self.get_emoji_db_id(first)?;
+        let second_id = self.get_emoji_db_id(second)?;
+
+        if first_id.is_none() && second_id.is_none() {
+            return Ok(Comparison::default());
+        }
+
+        let (source, start, end) = filter_params(filter);
+
+        let result = self.conn
+            .query(QUERY_COMPARE_EMOJI,
+                   &[&first_id,
+                     &second_id,
+                     &server_id.map(|server_id| server_id.0 as i64),
+                     &source,
+                     &start,
+                     &end,
+                     &page.limit()])?;
+
+        Ok(rows_into_comparison(result, 2, page.limit(), |row| row.get::<usize, String>(0)))
+    }
+
     pub fn get_user_name(&self, user_id: &UserId) -> postgres::Result<Option<String>> {
         const QUERY_SELECT_USER: &str = r#"
         SELECT u.name, u.discriminator
@@ -363,7 +2404,15 @@ fn create_tables(db_conn: &postgres::Connection) -> postgres::Result<()> {
         id BIGSERIAL NOT NULL,
         name VARCHAR(512) NOT NULL,
         is_custom_emoji BOOL NOT NULL,
-        PRIMARY KEY (id)
+        base_id BIGINT NULL,
+        description VARCHAR(512) NULL,
+        emoji_group VARCHAR(128) NULL,
+        emoji_subgroup VARCHAR(128) NULL,
+        animated BOOL NOT NULL DEFAULT FALSE,
+        first_seen TIMESTAMP NOT NULL DEFAULT NOW(),
+        available BOOL NOT NULL DEFAULT TRUE,
+        PRIMARY KEY (id),
+        FOREIGN KEY (base_id) REFERENCES emoji (id)
     );
     CREATE TABLE IF NOT EXISTS channel (
         id BIGINT NOT NULL,
@@ -371,12 +2420,59 @@ fn create_tables(db_conn: &postgres::Connection) -> postgres::Result<()> {
         name VARCHAR(512),
         PRIMARY KEY (id)
     );
+    CREATE TABLE IF NOT EXISTS server (
+        id BIGINT NOT NULL,
+        name VARCHAR(512),
+        ranked BOOL NOT NULL DEFAULT FALSE,
+        PRIMARY KEY (id)
+    );
+    CREATE TABLE IF NOT EXISTS server_settings (
+        server_id BIGINT NOT NULL,
+        name VARCHAR(64) NOT NULL,
+        value VARCHAR(2000) NOT NULL,
+        PRIMARY KEY (server_id, name)
+    );
+    CREATE TABLE IF NOT EXISTS excluded_channel (
+        channel_id BIGINT NOT NULL,
+        server_id BIGINT NOT NULL,
+        from_tracking BOOL NOT NULL,
+        from_leaderboards BOOL NOT NULL,
+        PRIMARY KEY (channel_id)
+    );
+    CREATE TABLE IF NOT EXISTS excluded_user (
+        server_id BIGINT NOT NULL,
+        user_id BIGINT NOT NULL,
+        from_tracking BOOL NOT NULL,
+        from_leaderboards BOOL NOT NULL,
+        PRIMARY KEY (server_id, user_id)
+    );
     CREATE TABLE IF NOT EXISTS user_ (
         id BIGINT NOT NULL,
         name VARCHAR(512),
         discriminator INTEGER,
         PRIMARY KEY (id)
     );
+    CREATE TABLE IF NOT EXISTS opted_out_user (
+        user_id BIGINT NOT NULL,
+        PRIMARY KEY (user_id)
+    );
+    CREATE TABLE IF NOT EXISTS bot_admin (
+        user_id BIGINT NOT NULL,
+        authenticated TIMESTAMP NOT NULL DEFAULT NOW(),
+        PRIMARY KEY (user_id)
+    );
+    CREATE TABLE IF NOT EXISTS role (
+        id BIGINT NOT NULL,
+        server_id BIGINT NOT NULL,
+        name VARCHAR(512),
+        PRIMARY KEY (id)
+    );
+    CREATE TABLE IF NOT EXISTS member_role (
+        server_id BIGINT NOT NULL,
+        user_id BIGINT NOT NULL,
+        role_id BIGINT NOT NULL,
+        PRIMARY KEY (user_id, role_id)
+    );
     CREATE TABLE IF NOT EXISTS message (
         id BIGINT,
         channel_id BIGINT NOT NULL,
@@ -386,6 +2482,45 @@ fn create_tables(db_conn: &postgres::Connection) -> postgres::Result<()> {
         PRIMARY KEY (id),
         FOREIGN KEY (channel_id) REFERENCES channel (id)
     );
+    CREATE TABLE IF NOT EXISTS message_emoji (
+        message_id BIGINT NOT NULL,
+        emoji_id BIGINT NOT NULL,
+        use_count INTEGER NOT NULL,
+        PRIMARY KEY (message_id, emoji_id),
+        FOREIGN KEY (message_id) REFERENCES message (id) ON DELETE CASCADE,
+        FOREIGN KEY (emoji_id) REFERENCES emoji (id)
+    );
+    -- Pairs of different emoji typed into the same message, stored once per message however many
+    -- times each was used; both are base emoji, and each pair is stored in both orders
+    -- Pairs are filled in from message_emoji the first time the table is created
+    DO $$
+    BEGIN
+        IF to_regclass('message_emoji_pair') IS NULL THEN
+            CREATE TABLE message_emoji_pair (
+                message_id BIGINT NOT NULL,
+                emoji_id BIGINT NOT NULL,
+                other_emoji_id BIGINT NOT NULL,
+                PRIMARY KEY (message_id, emoji_id, other_emoji_id),
+                FOREIGN KEY (message_id) REFERENCES message (id) ON DELETE CASCADE,
+                FOREIGN KEY (emoji_id) REFERENCES emoji (id),
+                FOREIGN KEY (other_emoji_id) REFERENCES emoji (id)
+            );
+            CREATE INDEX message_emoji_pair_emoji_id ON message_emoji_pair (emoji_id);
+            INSERT INTO message_emoji_pair (message_id, emoji_id, other_emoji_id)
+                WITH message_base_emoji AS (
+                    SELECT DISTINCT me.message_id, COALESCE(e.base_id, e.id) AS emoji_id
+                    FROM message_emoji me
+                        INNER JOIN emoji e ON me.emoji_id = e.id
+                )
+                SELECT a.message_id, a.emoji_id, b.emoji_id
+                FROM message_base_emoji a
+                    INNER JOIN message_base_emoji b
+                        ON a.message_id = b.message_id AND a.emoji_id <> b.emoji_id;
+        END IF;
+    END
+    $$;
+    -- Per-user totals recorded before each message's emoji were stored in message_emoji; no
+    -- longer written, but still counted in statistics that cover all time
     CREATE TABLE IF NOT EXISTS emoji_usage (
         channel_id BIGINT NOT NULL,
         user_id BIGINT NOT NULL,
@@ -394,38 +2529,305 @@ fn create_tables(db_conn: &postgres::Connection) -> postgres::Result<()> {
         PRIMARY KEY (channel_id, emoji_id, user_id),
         FOREIGN KEY (channel_id) REFERENCES channel (id),
         FOREIGN KEY (emoji_id) REFERENCES emoji (id)
-    );"#;
+    );
+    CREATE TABLE IF NOT EXISTS reaction (
+        message_id BIGINT NOT NULL,
+        channel_id BIGINT NOT NULL,
+        user_id BIGINT NOT NULL,
+        emoji_id BIGINT NOT NULL,
+        reacted TIMESTAMP NOT NULL DEFAULT NOW(),
+        -- More than one only for anonymous reactions, which users who opted out share
+        reaction_count INTEGER NOT NULL DEFAULT 1,
+        PRIMARY KEY (message_id, user_id, emoji_id),
+        FOREIGN KEY (channel_id) REFERENCES channel (id),
+        FOREIGN KEY (emoji_id) REFERENCES emoji (id)
+    );
+    CREATE TABLE IF NOT EXISTS backfill (
+        channel_id BIGINT NOT NULL,
+        report_channel_id BIGINT NOT NULL,
+        since BIGINT NULL,
+        before_message_id BIGINT NULL,
+        messages_processed BIGINT NOT NULL DEFAULT 0,
+        completed BOOL NOT NULL DEFAULT FALSE,
+        PRIMARY KEY (channel_id),
+        FOREIGN KEY (channel_id) REFERENCES channel (id)
+    );
+    ALTER TABLE emoji ADD COLUMN IF NOT EXISTS base_id BIGINT NULL REFERENCES emoji (id);
+    ALTER TABLE emoji ADD COLUMN IF NOT EXISTS description VARCHAR(512) NULL;
+    ALTER TABLE emoji ADD COLUMN IF NOT EXISTS emoji_group VARCHAR(128) NULL;
+    ALTER TABLE emoji ADD COLUMN IF NOT EXISTS emoji_subgroup VARCHAR(128) NULL;
+    ALTER TABLE emoji ADD COLUMN IF NOT EXISTS animated BOOL NOT NULL DEFAULT FALSE;
+    ALTER TABLE emoji ADD COLUMN IF NOT EXISTS first_seen TIMESTAMP NOT NULL DEFAULT NOW();
+    ALTER TABLE emoji ADD COLUMN IF NOT EXISTS available BOOL NOT NULL DEFAULT TRUE;
+    -- Messages and reactions that count towards statistics, i.e. those not in channels or by
+    -- users excluded from leaderboards
+    CREATE OR REPLACE VIEW counted_message AS
+        SELECT m.id, m.channel_id, m.user_id, m.emoji_count, m.posted
+        FROM message m
+            INNER JOIN channel c ON m.channel_id = c.id
+        WHERE NOT EXISTS (SELECT 1
+                          FROM excluded_channel xc
+                          WHERE xc.channel_id = m.channel_id AND xc.from_leaderboards)
+            AND NOT EXISTS (SELECT 1
+                            FROM excluded_user xu
+                            WHERE xu.server_id = c.server_id AND xu.user_id = m.user_id
+                                AND xu.from_leaderboards);
+    CREATE OR REPLACE VIEW counted_reaction AS
+        SELECT r.message_id, r.channel_id, r.user_id, r.emoji_id, r.reacted, r.reaction_count
+        FROM reaction r
+            INNER JOIN channel c ON r.channel_id = c.id
+        WHERE NOT EXISTS (SELECT 1
+                          FROM excluded_channel xc
+                          WHERE xc.channel_id = r.channel_id AND xc.from_leaderboards)
+            AND NOT EXISTS (SELECT 1
+                            FROM excluded_user xu
+                            WHERE xu.server_id = c.server_id AND xu.user_id = r.user_id
+                                AND xu.from_leaderboards);
+    -- emoji_id is the base emoji, so that variants are counted together; variant_id is the
+    -- emoji exactly as it was used
+    -- Usage from emoji_usage has no time, so it's left out of any dated period
+    CREATE OR REPLACE VIEW emoji_use AS
+        SELECT m.channel_id, m.user_id, COALESCE(e.base_id, e.id) AS emoji_id, me.use_count,
+            'text'::VARCHAR AS source, m.posted AS used_at, me.emoji_id AS variant_id
+        FROM message_emoji me
+            INNER JOIN counted_message m ON me.message_id = m.id
+            INNER JOIN emoji e ON me.emoji_id = e.id
+        UNION ALL
+        SELECT r.channel_id, r.user_id, COALESCE(e.base_id, e.id) AS emoji_id, r.reaction_count,
+            'reaction'::VARCHAR AS source, r.reacted AS used_at, r.emoji_id AS variant_id
+        FROM counted_reaction r
+            INNER JOIN emoji e ON r.emoji_id = e.id
+        UNION ALL
+        SELECT lu.channel_id, lu.user_id, COALESCE(e.base_id, e.id) AS emoji_id, lu.use_count,
+            'text'::VARCHAR AS source, NULL::TIMESTAMP AS used_at, lu.emoji_id AS variant_id
+        FROM emoji_usage lu
+            INNER JOIN channel c ON lu.channel_id = c.id
+            INNER JOIN emoji e ON lu.emoji_id = e.id
+        WHERE lu.use_count > 0
+            AND NOT EXISTS (SELECT 1
+                            FROM excluded_channel xc
+                            WHERE xc.channel_id = lu.channel_id AND xc.from_leaderboards)
+            AND NOT EXISTS (SELECT 1
+                            FROM excluded_user xu
+                            WHERE xu.server_id = c.server_id AND xu.user_id = lu.user_id
+                                AND xu.from_leaderboards);
+    -- The stored pairs of messages that count towards statistics
+    CREATE OR REPLACE VIEW emoji_pair AS
+        SELECT m.id AS message_id, m.channel_id, m.user_id, m.posted, p.emoji_id,
+            p.other_emoji_id
+        FROM message_emoji_pair p
+            INNER JOIN counted_message m ON p.message_id = m.id;"#;
 
     db_conn.batch_execute(QUERY_CREATE_TABLES)?;
 
     Ok(())
 }
 
+fn insert_message_emoji(trans: &postgres::Transaction,
+                        message_id: &MessageId,
+                        emoji_counts: &[(i64, i32)])
+                        -> postgres::Result<()> {
+    const QUERY_RECORD_MESSAGE_EMOJI: &str = r#"
+    INSERT INTO message_emoji (message_id, emoji_id, use_count)
+    VALUES ($1, $2, $3);"#;
+
+    // Each pair of different base emoji in the message, in both orders
+    const QUERY_RECORD_MESSAGE_EMOJI_PAIRS: &str = r#"
+    INSERT INTO message_emoji_pair (message_id, emoji_id, other_emoji_id)
+        WITH message_base_emoji AS (
+            SELECT DISTINCT COALESCE(e.base_id, e.id) AS emoji_id
+            FROM message_emoji me
+                INNER JOIN emoji e ON me.emoji_id = e.id
+            WHERE me.message_id = $1
+        )
+        SELECT $1, a.emoji_id, b.emoji_id
+        FROM message_base_emoji a
+            INNER JOIN message_base_emoji b ON a.emoji_id <> b.emoji_id;"#;
+
+    for &(emoji_id, count) in emoji_counts {
+        trans
+            .execute(QUERY_RECORD_MESSAGE_EMOJI,
+                     &[&(message_id.0 as i64), &emoji_id, &count])?;
+    }
+
+    if emoji_counts.len() > 1 {
+        trans
+            .execute(QUERY_RECORD_MESSAGE_EMOJI_PAIRS, &[&(message_id.0 as i64)])?;
+    }
+
+    Ok(())
+}
+
+fn remove_message_emoji(trans: &postgres::Transaction,
+                        message_id: &MessageId)
+                        -> postgres::Result<()> {
+    const QUERY_REMOVE_MESSAGE_EMOJI: &str = r#"
+    DELETE FROM message_emoji
+    WHERE message_id = $1;"#;
+
+    const QUERY_REMOVE_MESSAGE_EMOJI_PAIRS: &str = r#"
+    DELETE FROM message_emoji_pair
+    WHERE message_id = $1;"#;
+
+    trans
+        .execute(QUERY_REMOVE_MESSAGE_EMOJI_PAIRS, &[&(message_id.0 as i64)])?;
+    trans
+        .execute(QUERY_REMOVE_MESSAGE_EMOJI, &[&(message_id.0 as i64)])?;
+
+    Ok(())
+}
+
+fn remove_member_roles(trans: &postgres::Transaction,
+                       server_id: &ServerId,
+                       user_id: &UserId)
+                       -> postgres::Result<()> {
+    const QUERY_REMOVE_MEMBER_ROLES: &str = r#"
+    DELETE FROM member_role
+    WHERE server_id = $1 AND user_id = $2;"#;
+
+    trans
+        .execute(QUERY_REMOVE_MEMBER_ROLES,
+                 &[&(server_id.0 as i64), &(user_id.0 as i64)])?;
+
+    Ok(())
+}
+
+// Returns the query parameters for a UsageFilter: (source, start time, end time)
+fn filter_params(filter: &UsageFilter) -> (Option<&'static str>, Option<i64>, Option<i64>) {
+    let (start, end) = filter.period.bounds();
+
+    (filter.source.filter(), start, end)
+}
+
+// Returns the query parameters for a Scope: (server ID, channel ID, user ID, role ID, ID of the
+// server whose custom emoji are included)
+fn scope_params(scope: &Scope)
+                -> (Option<i64>, Option<i64>, Option<i64>, Option<i64>, Option<i64>) {
+    match *scope {
+        Scope::Global => (None, None, None, None, None),
+        Scope::Server(server_id) => {
+            (Some(server_id.0 as i64), None, None, None, Some(server_id.0 as i64))
+        }
+        Scope::Channel(channel_id) => (None, Some(channel_id.0 as i64), None, None, None),
+        Scope::User(user_id, server_id) => {
+            (None,
+             None,
+             Some(user_id.0 as i64),
+             None,
+             server_id.map(|server_id| server_id.0 as i64))
+        }
+        Scope::Role(role_id, server_id) => {
+            (Some(server_id.0 as i64),
+             None,
+             None,
+             Some(role_id.0 as i64),
+             Some(server_id.0 as i64))
+        }
+    }
+}
+
 fn result_into_vec_emoji(result: Rows) -> postgres::Result<Vec<(Emoji, i64)>> {
     // row
-    // column 0: is_custom_emoji
-    // column 1: emoji ID
-    // column 2: emoji name
+    // column 0-2, 4-5: see row_into_emoji
     // column 3: use count
     let mut vec_emoji = Vec::new();
 
     for row in result.iter() {
-        let emoji = match row.get::<usize, bool>(0) {
-            true => {
-                Emoji::Custom(CustomEmoji::new(ServerId(0),
-                                               discord::model::EmojiId(row.get::<usize, i64>(1) as
-                                                                       u64),
-                                               row.get::<usize, String>(2)))
-            }
-            false => Emoji::Unicode(row.get::<usize, String>(2)),
-        };
-
-        vec_emoji.push((emoji, row.get::<usize, i64>(3)));
+        vec_emoji.push((row_into_emoji(&row), row.get::<usize, i64>(3)));
     }
 
     Ok(vec_emoji)
 }
 
+fn row_into_emoji(row: &Row) -> Emoji {
+    row_into_emoji_at(row, 0)
+}
+
+fn row_into_emoji_at(row: &Row, column: usize) -> Emoji {
+    // row, starting from column
+    // column 0: is_custom_emoji
+    // column 1: emoji ID
+    // column 2: emoji name
+    // column 4: animated
+    // column 5: server ID (NULL for Unicode and external emoji)
+    match row.get::<usize, bool>(column) {
+        true => {
+            Emoji::Custom(CustomEmoji::new(row.get::<usize, Option<i64>>(column + 5)
+                                               .map(|server_id| ServerId(server_id as u64)),
+                                           EmojiId(row.get::<usize, i64>(column + 1) as u64),
+                                           row.get::<usize, String>(column + 2),
+                                           row.get::<usize, bool>(column + 4)))
+        }
+        false => Emoji::Unicode(row.get::<usize, String>(column + 2)),
+    }
+}
+
+fn rows_into_comparison<T, F>(result: Rows,
+                              stats_column: usize,
+                              limit: i64,
+                              row_into_item: F)
+                              -> Comparison<T>
+    where F: Fn(&Row) -> T,
+          T: Clone
+{
+    // row
+    // columns before stats_column: the item
+    // column stats_column: first side's count
+    // column stats_column + 1: second side's count
+    // column stats_column + 2-4: first side's rank, second side's rank, rank among shared items
+    // column stats_column + 5-6: first side's total, second side's total
+    // column stats_column + 7-9: number of items used by the first side, the second side and both
+    let mut comparison = Comparison::default();
+    let mut first_top = Vec::new();
+    let mut second_top = Vec::new();
+    let mut shared = Vec::new();
+
+    for row in result.iter() {
+        let item = row_into_item(&row);
+        let first_count = row.get::<usize, i64>(stats_column);
+        let second_count = row.get::<usize, i64>(stats_column + 1);
+        let first_rank = row.get::<usize, i64>(stats_column + 2);
+        let second_rank = row.get::<usize, i64>(stats_column + 3);
+        let shared_rank = row.get::<usize, i64>(stats_column + 4);
+
+        comparison.first.total = row.get::<usize, i64>(stats_column + 5);
+        comparison.second.total = row.get::<usize, i64>(stats_column + 6);
+        comparison.first.num_distinct = row.get::<usize, i64>(stats_column + 7);
+        comparison.second.num_distinct = row.get::<usize, i64>(stats_column + 8);
+        comparison.num_shared = row.get::<usize, i64>(stats_column + 9);
+
+        if first_count > 0 && first_rank <= limit {
+            first_top.push((first_rank, item.clone(), first_count));
+        }
+
+        if second_count > 0 && second_rank <= limit {
+            second_top.push((second_rank, item.clone(), second_count));
+        }
+
+        if first_count > 0 && second_count > 0 && shared_rank <= limit {
+            shared.push((shared_rank, item, first_count, second_count));
+        }
+    }
+
+    first_top.sort_by_key(|&(rank, _, _)| rank);
+    second_top.sort_by_key(|&(rank, _, _)| rank);
+    shared.sort_by_key(|&(rank, _, _, _)| rank);
+
+    comparison.first.top = first_top
+        .into_iter()
+        .map(|(_, item, count)| (item, count))
+        .collect();
+    comparison.second.top = second_top
+        .into_iter()
+        .map(|(_, item, count)| (item, count))
+        .collect();
+    comparison.shared = shared
+        .into_iter()
+        .map(|(_, item, first_count, second_count)| (item, first_count, second_count))
+        .collect();
+
+    comparison
+}
 
 fn result_into_vec_users(result: Rows) -> postgres::Result<Vec<(String, i64)>> {
     // row
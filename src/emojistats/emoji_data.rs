@@ -0,0 +1,256 @@
+use std::char;
+use std::collections::HashSet;
+
+const EMOJI_PRESENTATION_SELECTOR: char = '\u{FE0F}';
+
+// An emoji listed in one of the Unicode emoji data files
+#[derive(Debug, PartialEq)]
+pub struct UnicodeEmoji {
+    pub emoji: String,
+    pub name: Option<String>,
+    pub group: Option<String>, // e.g. "Smileys & Emotion"; only emoji-test.txt has groups
+    pub subgroup: Option<String>, // e.g. "face-smiling"
+}
+
+// Parses the emoji listed in emoji-test.txt or in emoji-sequences.txt and
+// emoji-zwj-sequences.txt, which share the same basic format:
+//
+// emoji-test.txt:      1F600 ; fully-qualified # 😀 E1.0 grinning face
+// emoji-sequences.txt: 231A..231B ; Basic_Emoji ; watch # E0.6 [2] (⌚..⌛)
+//
+// Only the fully-qualified emoji in emoji-test.txt are used, since the other forms are the same
+// emoji without variation selectors
+// Emoji presentation selectors are removed so that each emoji has a single form; they are
+// optional when emoji are counted
+pub fn parse_emoji_data(text: &str) -> Vec<UnicodeEmoji> {
+    let mut emoji_list = Vec::new();
+    let mut seen = HashSet::new();
+    let mut group = None;
+    let mut subgroup = None;
+
+    for line in text.lines() {
+        let line = line.trim();
+
+        if line.starts_with("# group:") {
+            group = Some(line["# group:".len()..].trim().to_string());
+            subgroup = None;
+            continue;
+        } else if line.starts_with("# subgroup:") {
+            subgroup = Some(line["# subgroup:".len()..].trim().to_string());
+            continue;
+        }
+
+        let (data, comment) = match line.find('#') {
+            Some(pos) => (&line[..pos], &line[(pos + 1)..]),
+            None => (line, ""),
+        };
+
+        let fields = data.split(';').map(|field| field.trim()).collect::<Vec<_>>();
+
+        let (emoji_in_line, name) = match fields.len() {
+            // emoji-test.txt
+            2 => {
+                if fields[1] != "fully-qualified" {
+                    continue;
+                }
+
+                match parse_sequence(fields[0]) {
+                    Some(emoji) => (vec![emoji], parse_test_name(comment)),
+                    None => continue,
+                }
+            }
+            // emoji-sequences.txt and emoji-zwj-sequences.txt
+            3 => {
+                match parse_codepoints(fields[0]) {
+                    Some(emoji) => {
+                        // A range of emoji shares one description, so it can't be used as a name
+                        let name = if emoji.len() == 1 && !fields[2].is_empty() {
+                            Some(fields[2].to_string())
+                        } else {
+                            None
+                        };
+
+                        (emoji, name)
+                    }
+                    None => continue,
+                }
+            }
+            _ => continue,
+        };
+
+        for emoji in emoji_in_line {
+            let emoji = emoji
+                .chars()
+                .filter(|&c| c != EMOJI_PRESENTATION_SELECTOR)
+                .collect::<String>();
+
+            if emoji.is_empty() || !seen.insert(emoji.clone()) {
+                continue;
+            }
+
+            emoji_list.push(UnicodeEmoji {
+                                emoji,
+                                name: name.clone(),
+                                group: group.clone(),
+                                subgroup: subgroup.clone(),
+                            });
+        }
+    }
+
+    emoji_list
+}
+
+// Parses a sequence of codepoints, e.g. "1F468 200D 1F469", or a range of single codepoints, e.g.
+// "231A..231B"
+fn parse_codepoints(codepoints: &str) -> Option<Vec<String>> {
+    match codepoints.find("..") {
+        Some(pos) => {
+            let first = parse_codepoint(&codepoints[..pos])? as u32;
+            let last = parse_codepoint(&codepoints[(pos + 2)..])? as u32;
+
+            if first > last {
+                return None;
+            }
+
+            Some((first..(last + 1))
+                     .filter_map(char::from_u32)
+                     .map(|c| c.to_string())
+                     .collect())
+        }
+        None => parse_sequence(codepoints).map(|emoji| vec![emoji]),
+    }
+}
+
+fn parse_sequence(codepoints: &str) -> Option<String> {
+    let mut emoji = String::new();
+
+    for codepoint in codepoints.split_whitespace() {
+        emoji.push(parse_codepoint(codepoint)?);
+    }
+
+    if emoji.is_empty() { None } else { Some(emoji) }
+}
+
+fn parse_codepoint(codepoint: &str) -> Option<char> {
+    u32::from_str_radix(codepoint.trim(), 16)
+        .ok()
+        .and_then(char::from_u32)
+}
+
+// Extracts the name from an emoji-test.txt comment, e.g. "😀 E1.0 grinning face"
+// Older versions of the file don't include the Emoji version, e.g. "😀 grinning face"
+fn parse_test_name(comment: &str) -> Option<String> {
+    let mut words = comment.split_whitespace().skip(1).peekable();
+
+    if let Some(&word) = words.peek() {
+        if is_emoji_version(word) {
+            words.next();
+        }
+    }
+
+    let name = words.collect::<Vec<_>>().join(" ");
+
+    if name.is_empty() { None } else { Some(name) }
+}
+
+fn is_emoji_version(word: &str) -> bool {
+    word.starts_with('E') && word.len() > 1 &&
+    word[1..].chars().all(|c| c.is_digit(10) || c == '.')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_emoji_data, UnicodeEmoji};
+
+    fn emoji(emoji: &str,
+             name: Option<&str>,
+             group: Option<&str>,
+             subgroup: Option<&str>)
+             -> UnicodeEmoji {
+        UnicodeEmoji {
+            emoji: emoji.to_string(),
+            name: name.map(|name| name.to_string()),
+            group: group.map(|group| group.to_string()),
+            subgroup: subgroup.map(|subgroup| subgroup.to_string()),
+        }
+    }
+
+    #[test]
+    fn emoji_test() {
+        let text = "\
+# emoji-test.txt
+# group: Smileys & Emotion
+
+# subgroup: face-smiling
+1F600                                      ; fully-qualified     # 😀 E1.0 grinning face
+1F603 ; fully-qualified # 😃 grinning face with big eyes
+
+# subgroup: heart
+2764 FE0F                                  ; fully-qualified     # ❤️ E0.6 red heart
+2764                                       ; unqualified         # ❤ E0.6 red heart
+
+# group: Component
+1F3FB                                      ; component           # 🏻 E1.0 light skin tone
+
+# group: Symbols
+# subgroup: keycap
+0023 FE0F 20E3                             ; fully-qualified     # #️⃣ E0.6 keycap: #
+0023 20E3                                  ; unqualified         # #⃣ E0.6 keycap: #
+";
+
+        assert_eq!(parse_emoji_data(text),
+                   vec![emoji("😀",
+                              Some("grinning face"),
+                              Some("Smileys & Emotion"),
+                              Some("face-smiling")),
+                        emoji("😃",
+                              Some("grinning face with big eyes"),
+                              Some("Smileys & Emotion"),
+                              Some("face-smiling")),
+                        emoji("❤", Some("red heart"), Some("Smileys & Emotion"), Some("heart")),
+                        emoji("#⃣", Some("keycap: #"), Some("Symbols"), Some("keycap"))]);
+    }
+
+    #[test]
+    fn emoji_sequences() {
+        let text = "\
+# emoji-sequences.txt
+231A..231B    ; Basic_Emoji                  ; watch                        # E0.6   [2] (⌚..⌛)
+1F1E8 1F1E6   ; RGI_Emoji_Flag_Sequence      ; flag: Canada                 # E2.0   [1] (🇨🇦)
+0031 FE0F 20E3; Emoji_Keycap_Sequence        ; keycap: 1                    # E0.6   [1] (1️⃣)
+1F468 200D 1F469 200D 1F467 ; RGI_Emoji_ZWJ_Sequence ; family: man, woman, girl # E2.0 [1]
+";
+
+        assert_eq!(parse_emoji_data(text),
+                   vec![emoji("⌚", None, None, None),
+                        emoji("⌛", None, None, None),
+                        emoji("🇨🇦", Some("flag: Canada"), None, None),
+                        emoji("1⃣", Some("keycap: 1"), None, None),
+                        emoji("👨‍👩‍👧", Some("family: man, woman, girl"), None, None)]);
+    }
+
+    #[test]
+    fn duplicates() {
+        let text = "\
+2764 FE0F ; fully-qualified # ❤️ red heart
+2764 ; Basic_Emoji ; red heart
+";
+
+        assert_eq!(parse_emoji_data(text),
+                   vec![emoji("❤", Some("red heart"), None, None)]);
+    }
+
+    #[test]
+    fn invalid_lines() {
+        let text = "\
+# 1F600 ; fully-qualified # 😀 grinning face
+
+1F60G ; fully-qualified # bad codepoint
+110000 ; fully-qualified # out of range
+231B..231A ; Basic_Emoji ; backwards range
+1F600 ; fully-qualified ; too ; many ; fields
+";
+
+        assert_eq!(parse_emoji_data(text), vec![]);
+    }
+}
@@ -1,5 +1,14 @@
+mod counter;
 mod database;
+mod emoji_data;
 mod model;
+mod names;
+mod tokenizer;
 
+pub use self::counter::{count_emoji, find_emoji};
 pub use self::database::Database;
-pub use self::model::{CustomEmoji, Emoji};
+pub use self::emoji_data::{parse_emoji_data, UnicodeEmoji};
+pub use self::model::{ActivityStats, BackfillProgress, Bucket, Comparison, CustomEmoji, Emoji,
+                      Exclusion, Page, Period, Scope, UsageFilter, UsageSource,
+                      ANONYMOUS_USER_ID};
+pub use self::names::{find_emoji_by_name, NameMatch};
@@ -1,9 +1,351 @@
 extern crate discord;
+extern crate time;
 
 use std::hash::{Hash, Hasher};
-use self::discord::model::{EmojiId, ServerId};
+use self::discord::model::{ChannelId, EmojiId, MessageId, RoleId, ServerId, UserId};
+use self::time::{Timespec, at_utc, get_time};
 
-#[derive(Debug, Hash, PartialEq, Eq)]
+const SECONDS_PER_DAY: i64 = 86400;
+const EMOJI_PRESENTATION_SELECTOR: char = '\u{FE0F}';
+const GENDER_SUFFIXES: [&str; 2] = ["\u{200D}\u{2640}", "\u{200D}\u{2642}"];
+
+// Emoji used by users who have opted out are recorded against this user, which is never added to
+// the user table, so that they still count towards emoji totals but not towards any user
+pub const ANONYMOUS_USER_ID: UserId = UserId(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UsageSource {
+    Text, // Emoji typed into message content
+    Reactions, // Emoji added to messages as reactions
+    All,
+}
+
+impl UsageSource {
+    // The value of the "source" column in the emoji_use view, or None to include every source
+    pub fn filter(&self) -> Option<&'static str> {
+        match *self {
+            UsageSource::Text => Some("text"),
+            UsageSource::Reactions => Some("reaction"),
+            UsageSource::All => None,
+        }
+    }
+
+    // Appended to statistics descriptions to say which counts are being shown
+    pub fn description(&self) -> &'static str {
+        match *self {
+            UsageSource::Text => " in messages",
+            UsageSource::Reactions => " as reactions",
+            UsageSource::All => "",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Period {
+    AllTime,
+    Today,
+    Yesterday,
+    Days(u32), // The last n days up to now
+    Dates(Option<i64>, Option<i64>), // Inclusive range of days since the Unix epoch (UTC)
+}
+
+impl Period {
+    // Returns the (inclusive start, exclusive end) of the period in seconds since the Unix epoch
+    // None means the period is unbounded in that direction
+    pub fn bounds(&self) -> (Option<i64>, Option<i64>) {
+        self.bounds_at(get_time().sec)
+    }
+
+    pub fn bounds_at(&self, now: i64) -> (Option<i64>, Option<i64>) {
+        let start_of_today = now - now % SECONDS_PER_DAY;
+
+        match *self {
+            Period::AllTime => (None, None),
+            Period::Today => (Some(start_of_today), None),
+            Period::Yesterday => (Some(start_of_today - SECONDS_PER_DAY), Some(start_of_today)),
+            Period::Days(days) => (Some(now - days as i64 * SECONDS_PER_DAY), None),
+            Period::Dates(from, to) => {
+                (from.map(|day| day * SECONDS_PER_DAY), to.map(|day| (day + 1) * SECONDS_PER_DAY))
+            }
+        }
+    }
+
+    // Appended to statistics descriptions to say which period is being shown
+    pub fn description(&self) -> String {
+        match *self {
+            Period::AllTime => String::new(),
+            Period::Today => " today".to_string(),
+            Period::Yesterday => " yesterday".to_string(),
+            Period::Days(1) => " in the last day".to_string(),
+            Period::Days(days) => format!(" in the last {} days", days),
+            Period::Dates(Some(from), Some(to)) if from == to => {
+                format!(" on {}", format_day(from))
+            }
+            Period::Dates(Some(from), Some(to)) => {
+                format!(" from {} to {}", format_day(from), format_day(to))
+            }
+            Period::Dates(Some(from), None) => format!(" since {}", format_day(from)),
+            Period::Dates(None, Some(to)) => format!(" until {}", format_day(to)),
+            Period::Dates(None, None) => String::new(),
+        }
+    }
+}
+
+// Which emoji usage to include in statistics
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UsageFilter {
+    pub source: UsageSource,
+    pub period: Period,
+    pub external: bool, // Whether to show only external custom emoji instead of excluding them
+}
+
+impl Default for UsageFilter {
+    fn default() -> UsageFilter {
+        UsageFilter {
+            source: UsageSource::All,
+            period: Period::AllTime,
+            external: false,
+        }
+    }
+}
+
+impl UsageFilter {
+    pub fn description(&self) -> String {
+        format!("{}{}{}",
+                self.source.description(),
+                self.period.description(),
+                if self.external {
+                    " (emoji from other servers)"
+                } else {
+                    ""
+                })
+    }
+}
+
+// Whose emoji usage to include in statistics
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    Global, // Unicode emoji used anywhere
+    Server(ServerId),
+    Channel(ChannelId),
+    User(UserId, Option<ServerId>), // Unicode emoji and, if present, the server's custom emoji
+    Role(RoleId, ServerId), // Members of the role on its server
+}
+
+// Where a user stands among everyone who has used emoji
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UserRank {
+    pub rank: i64, // Starting from 1; users with the same usage share a rank
+    pub num_users: i64,
+    pub use_count: i64,
+}
+
+impl UserRank {
+    // The percentage of users at or above this user's rank, rounded up, e.g. 4 for the top 4%
+    pub fn top_percent(&self) -> i64 {
+        if self.num_users <= 0 {
+            return 100;
+        }
+
+        (self.rank * 100 + self.num_users - 1) / self.num_users
+    }
+}
+
+// Where an emoji stands among all emoji used
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EmojiRank {
+    pub rank: i64, // Starting from 1; emoji with the same usage share a rank
+    pub num_emoji: i64,
+    pub use_count: i64,
+}
+
+// How often messages contain emoji
+// Only emoji typed into messages are counted, since reactions aren't messages
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ActivityStats {
+    pub num_messages: i64,
+    pub num_messages_with_emoji: i64,
+    pub num_emoji: i64,
+}
+
+impl ActivityStats {
+    pub fn emoji_per_message(&self) -> f64 {
+        if self.num_messages <= 0 {
+            return 0.0;
+        }
+
+        self.num_emoji as f64 / self.num_messages as f64
+    }
+
+    // The percentage of messages that contain at least one emoji
+    pub fn emoji_message_percent(&self) -> f64 {
+        if self.num_messages <= 0 {
+            return 0.0;
+        }
+
+        self.num_messages_with_emoji as f64 * 100.0 / self.num_messages as f64
+    }
+}
+
+// One side of a comparison
+#[derive(Debug, Clone, PartialEq)]
+pub struct ComparisonSide<T> {
+    pub total: i64, // The number of emoji used
+    pub num_distinct: i64, // The number of different items with any use
+    pub top: Vec<(T, i64)>,
+}
+
+impl<T> Default for ComparisonSide<T> {
+    fn default() -> ComparisonSide<T> {
+        ComparisonSide {
+            total: 0,
+            num_distinct: 0,
+            top: Vec::new(),
+        }
+    }
+}
+
+// A side-by-side comparison of two users or channels, whose items are the emoji they used, or of
+// two emoji, whose items are the users who used them
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison<T> {
+    pub first: ComparisonSide<T>,
+    pub second: ComparisonSide<T>,
+    pub num_shared: i64, // The number of items used by both sides
+    pub shared: Vec<(T, i64, i64)>, // The top items used by both sides, with each side's count
+}
+
+impl<T> Default for Comparison<T> {
+    fn default() -> Comparison<T> {
+        Comparison {
+            first: ComparisonSide::default(),
+            second: ComparisonSide::default(),
+            num_shared: 0,
+            shared: Vec::new(),
+        }
+    }
+}
+
+impl<T> Comparison<T> {
+    // The percentage of all items used by either side that were used by both
+    pub fn overlap_percent(&self) -> f64 {
+        let num_either = self.first.num_distinct + self.second.num_distinct - self.num_shared;
+
+        if num_either <= 0 {
+            return 0.0;
+        }
+
+        self.num_shared as f64 * 100.0 / num_either as f64
+    }
+}
+
+// Which part of a leaderboard to show; pages are numbered from 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Page {
+    pub number: u32,
+    pub size: u32,
+}
+
+impl Page {
+    pub fn limit(&self) -> i64 {
+        self.size as i64
+    }
+
+    pub fn offset(&self) -> i64 {
+        (self.number.saturating_sub(1) as i64) * self.size as i64
+    }
+}
+
+impl Default for Page {
+    fn default() -> Page {
+        Page { number: 1, size: 5 }
+    }
+}
+
+// The length of time covered by each point in a trend
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bucket {
+    Day,
+    Week,
+    Month,
+}
+
+impl Bucket {
+    // Chooses a bucket size that gives a readable number of points for the period
+    pub fn for_period(period: &Period) -> Bucket {
+        match period.bounds() {
+            (Some(start), end) => {
+                let days = (end.unwrap_or(get_time().sec) - start) / SECONDS_PER_DAY;
+
+                if days <= 31 {
+                    Bucket::Day
+                } else if days <= 183 {
+                    Bucket::Week
+                } else {
+                    Bucket::Month
+                }
+            }
+            (None, _) => Bucket::Month,
+        }
+    }
+
+    // The field name used by PostgreSQL's date_trunc()
+    pub fn unit(&self) -> &'static str {
+        match *self {
+            Bucket::Day => "day",
+            Bucket::Week => "week",
+            Bucket::Month => "month",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match *self {
+            Bucket::Day => "Daily",
+            Bucket::Week => "Weekly",
+            Bucket::Month => "Monthly",
+        }
+    }
+}
+
+// How far through a channel's history a backfill has progressed
+#[derive(Debug)]
+pub struct BackfillProgress {
+    pub channel_id: ChannelId,
+    pub report_channel_id: ChannelId, // Where to report progress
+    pub since: Option<i64>, // Seconds since the Unix epoch; None to backfill the entire history
+    pub before_message_id: Option<MessageId>, // The oldest message processed so far
+    pub messages_processed: i64,
+}
+
+// What a channel or user is excluded from on a server
+// Emoji aren't recorded at all if excluded from tracking; those already recorded are left out of
+// statistics if excluded from leaderboards
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Exclusion {
+    pub tracking: bool,
+    pub leaderboards: bool,
+}
+
+impl Exclusion {
+    pub fn description(&self) -> &'static str {
+        match (self.tracking, self.leaderboards) {
+            (true, true) => "tracking and leaderboards",
+            (true, false) => "tracking",
+            (false, true) => "leaderboards",
+            (false, false) => "nothing",
+        }
+    }
+}
+
+// Formats a number of days since the Unix epoch as YYYY-MM-DD
+pub fn format_day(day: i64) -> String {
+    match at_utc(Timespec::new(day * SECONDS_PER_DAY, 0)).strftime("%Y-%m-%d") {
+        Ok(date) => date.to_string(),
+        Err(_) => day.to_string(),
+    }
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub enum Emoji {
     Custom(CustomEmoji),
     Unicode(String), // Some emoji span multiple chars
@@ -24,13 +366,49 @@ impl Emoji {
             Emoji::Unicode(ref emoji) => emoji,
         }
     }
+
+    // The emoji this is a skin tone or gender variant of, e.g. 👍 for 👍🏽
+    // Returns None if this isn't a variant
+    pub fn base(&self) -> Option<Emoji> {
+        match *self {
+            Emoji::Custom(_) => None,
+            Emoji::Unicode(ref emoji) => {
+                let base = base_sequence(emoji);
+
+                if base.is_empty() || base == *emoji {
+                    None
+                } else {
+                    Some(Emoji::Unicode(base))
+                }
+            }
+        }
+    }
+}
+
+// Removes skin tone modifiers, emoji presentation selectors and gender signs from an emoji sequence
+fn base_sequence(sequence: &str) -> String {
+    let mut base = sequence
+        .chars()
+        .filter(|&c| c != EMOJI_PRESENTATION_SELECTOR && (c < '\u{1F3FB}' || c > '\u{1F3FF}'))
+        .collect::<String>();
+
+    for suffix in &GENDER_SUFFIXES {
+        if base.ends_with(suffix) {
+            let len = base.len() - suffix.len();
+            base.truncate(len);
+            break;
+        }
+    }
+
+    base
 }
 
-#[derive(Debug, Eq)]
+#[derive(Debug, Clone, Eq)]
 pub struct CustomEmoji {
-    pub server_id: ServerId,
+    pub server_id: Option<ServerId>, // None for external emoji, whose server is unknown
     pub id: EmojiId,
     pub name: String,
+    pub animated: bool,
     pub pattern: String,
 }
 
@@ -49,16 +427,21 @@ impl PartialEq for CustomEmoji {
 }
 
 impl CustomEmoji {
-    pub fn new<S>(server_id: ServerId, id: EmojiId, name: S) -> CustomEmoji
+    pub fn new<S>(server_id: Option<ServerId>, id: EmojiId, name: S, animated: bool) -> CustomEmoji
         where S: Into<String>
     {
         let name = name.into();
-        let pattern = format!("<:{}:{}>", name, id);
+        let pattern = if animated {
+            format!("<a:{}:{}>", name, id)
+        } else {
+            format!("<:{}:{}>", name, id)
+        };
 
         CustomEmoji {
             server_id,
             id,
             name,
+            animated,
             pattern,
         }
     }
@@ -70,4 +453,136 @@ impl CustomEmoji {
     pub fn pattern(&self) -> &str {
         &self.pattern
     }
+
+    // External emoji are from servers the bot isn't on, e.g. emoji used by Discord Nitro users
+    pub fn is_external(&self) -> bool {
+        self.server_id.is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ActivityStats, Comparison, ComparisonSide, Emoji, Exclusion, Page, Period,
+                UserRank};
+
+    // 2017-07-30 12:00:00 UTC
+    const NOW: i64 = 1501416000;
+    const START_OF_TODAY: i64 = 1501372800;
+
+    #[test]
+    fn user_rank_top_percent() {
+        let rank = |rank, num_users| {
+            UserRank {
+                rank,
+                num_users,
+                use_count: 1,
+            }
+        };
+
+        assert_eq!(rank(12, 340).top_percent(), 4);
+        assert_eq!(rank(1, 1).top_percent(), 100);
+        assert_eq!(rank(1, 1000).top_percent(), 1);
+        assert_eq!(rank(50, 100).top_percent(), 50);
+        assert_eq!(rank(51, 100).top_percent(), 51);
+        assert_eq!(rank(1, 0).top_percent(), 100);
+    }
+
+    #[test]
+    fn activity_stats() {
+        let stats = ActivityStats {
+            num_messages: 200,
+            num_messages_with_emoji: 50,
+            num_emoji: 120,
+        };
+
+        assert_eq!(stats.emoji_per_message(), 0.6);
+        assert_eq!(stats.emoji_message_percent(), 25.0);
+        assert_eq!(ActivityStats::default().emoji_per_message(), 0.0);
+        assert_eq!(ActivityStats::default().emoji_message_percent(), 0.0);
+    }
+
+    #[test]
+    fn comparison_overlap_percent() {
+        let side = |num_distinct| {
+            ComparisonSide::<String> {
+                total: num_distinct * 2,
+                num_distinct,
+                top: Vec::new(),
+            }
+        };
+
+        let comparison = Comparison {
+            first: side(6),
+            second: side(4),
+            num_shared: 2,
+            shared: Vec::new(),
+        };
+
+        assert_eq!(comparison.overlap_percent(), 25.0);
+        assert_eq!(Comparison::<String>::default().overlap_percent(), 0.0);
+    }
+
+    #[test]
+    fn page_bounds() {
+        assert_eq!(Page::default().limit(), 5);
+        assert_eq!(Page::default().offset(), 0);
+        assert_eq!(Page { number: 3, size: 10 }.offset(), 20);
+        assert_eq!(Page { number: 0, size: 10 }.offset(), 0);
+    }
+
+    #[test]
+    fn period_bounds() {
+        assert_eq!(Period::AllTime.bounds_at(NOW), (None, None));
+        assert_eq!(Period::Today.bounds_at(NOW), (Some(START_OF_TODAY), None));
+        assert_eq!(Period::Yesterday.bounds_at(NOW),
+                   (Some(START_OF_TODAY - 86400), Some(START_OF_TODAY)));
+        assert_eq!(Period::Days(7).bounds_at(NOW), (Some(NOW - 7 * 86400), None));
+        assert_eq!(Period::Dates(Some(17377), Some(17378)).bounds_at(NOW),
+                   (Some(17377 * 86400), Some(17379 * 86400)));
+        assert_eq!(Period::Dates(None, Some(17378)).bounds_at(NOW),
+                   (None, Some(17379 * 86400)));
+    }
+
+    #[test]
+    fn period_description() {
+        assert_eq!(Period::AllTime.description(), "");
+        assert_eq!(Period::Days(1).description(), " in the last day");
+        assert_eq!(Period::Days(30).description(), " in the last 30 days");
+        assert_eq!(Period::Dates(Some(17377), Some(17377)).description(),
+                   " on 2017-07-30");
+        assert_eq!(Period::Dates(Some(17377), Some(17378)).description(),
+                   " from 2017-07-30 to 2017-07-31");
+        assert_eq!(Period::Dates(Some(17377), None).description(), " since 2017-07-30");
+    }
+
+    #[test]
+    fn exclusion_description() {
+        let exclusion = |tracking, leaderboards| {
+            Exclusion {
+                tracking,
+                leaderboards,
+            }
+        };
+
+        assert_eq!(exclusion(true, true).description(), "tracking and leaderboards");
+        assert_eq!(exclusion(true, false).description(), "tracking");
+        assert_eq!(exclusion(false, true).description(), "leaderboards");
+        assert_eq!(Exclusion::default(), exclusion(false, false));
+    }
+
+    #[test]
+    fn emoji_base() {
+        let base = |emoji: &str| Emoji::Unicode(emoji.to_string()).base();
+        let unicode = |emoji: &str| Some(Emoji::Unicode(emoji.to_string()));
+
+        assert_eq!(base("👍"), None);
+        assert_eq!(base("👍🏽"), unicode("👍"));
+        assert_eq!(base("👍🏿"), unicode("👍"));
+        assert_eq!(base("🏃‍♀️"), unicode("🏃"));
+        assert_eq!(base("🏃🏻‍♂️"), unicode("🏃"));
+        assert_eq!(base("👩🏽‍💻"), unicode("👩‍💻"));
+        assert_eq!(base("👨‍👩‍👧"), None);
+        assert_eq!(base("🏻"), None);
+        assert_eq!(base("♀"), None);
+    }
 }
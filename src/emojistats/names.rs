@@ -0,0 +1,188 @@
+extern crate discord;
+
+use super::model::Emoji;
+
+use self::discord::model::ServerId;
+
+const MAX_SUGGESTIONS: usize = 5;
+
+#[derive(Debug, PartialEq)]
+pub enum NameMatch {
+    Found(Emoji),
+    Suggestions(Vec<(Emoji, String)>), // Emoji with similar names, closest first
+    NotFound,
+}
+
+// Finds an emoji by its name or shortcode, e.g. "face with tears of joy", ":thumbs_up:" or
+// "thumbsup"; case, colons, spaces, hyphens and underscores are ignored
+// If no name matches exactly, names containing all of the words in the query are suggested, e.g.
+// "joy" suggests both "face with tears of joy" and "cat with tears of joy"
+// Custom emoji on the given server are preferred to those on other servers
+pub fn find_emoji_by_name<'a, I>(names: I, query: &str, server_id: Option<ServerId>) -> NameMatch
+    where I: IntoIterator<Item = (&'a Emoji, &'a str)>
+{
+    let query_key = name_key(query);
+    let query_words = name_words(query);
+
+    if query_key.is_empty() {
+        return NameMatch::NotFound;
+    }
+
+    let mut exact = Vec::new();
+    let mut similar = Vec::new();
+
+    for (emoji, name) in names {
+        if name_key(name) == query_key {
+            exact.push((emoji.clone(), name.to_string()));
+        } else if is_on_server(emoji, server_id) &&
+                  (name_key(name).contains(&query_key) ||
+                   contains_words(&name_words(name), &query_words)) {
+            similar.push((emoji.clone(), name.to_string()));
+        }
+    }
+
+    if exact.len() > 1 {
+        let on_server = exact
+            .iter()
+            .filter(|&&(ref emoji, _)| is_on_server(emoji, server_id))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        if !on_server.is_empty() {
+            exact = on_server;
+        }
+    }
+
+    let mut candidates = if exact.is_empty() { similar } else { exact };
+
+    // Shorter names are closer to the query
+    candidates.sort_by(|&(_, ref a), &(_, ref b)| a.len().cmp(&b.len()).then(a.cmp(b)));
+    candidates.dedup_by(|&mut (ref a, _), &mut (ref b, _)| a == b);
+
+    match candidates.len() {
+        0 => NameMatch::NotFound,
+        1 => NameMatch::Found(candidates.remove(0).0),
+        _ => {
+            candidates.truncate(MAX_SUGGESTIONS);
+            NameMatch::Suggestions(candidates)
+        }
+    }
+}
+
+// Unicode emoji are on every server; custom emoji are only on their own
+fn is_on_server(emoji: &Emoji, server_id: Option<ServerId>) -> bool {
+    match (emoji, server_id) {
+        (&Emoji::Custom(ref emoji), Some(server_id)) => emoji.server_id == Some(server_id),
+        _ => true,
+    }
+}
+
+fn name_key(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(|c| c.to_lowercase())
+        .collect()
+}
+
+fn name_words(name: &str) -> Vec<String> {
+    name.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect()
+}
+
+// Whether each of the query words begins one of the name's words
+fn contains_words(name_words: &[String], query_words: &[String]) -> bool {
+    !query_words.is_empty() &&
+    query_words
+        .iter()
+        .all(|query_word| name_words.iter().any(|word| word.starts_with(query_word.as_str())))
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate discord;
+
+    use super::{find_emoji_by_name, NameMatch};
+    use super::super::model::{CustomEmoji, Emoji};
+
+    use self::discord::model::{EmojiId, ServerId};
+
+    macro_rules! unicode {
+        ($emoji:expr) => { Emoji::Unicode($emoji.to_string()) };
+    }
+
+    macro_rules! custom {
+        ($server_id:expr, $id:expr, $name:expr) => {
+            Emoji::Custom(CustomEmoji::new(Some(ServerId($server_id)),
+                                           EmojiId($id),
+                                           $name.to_string(),
+                                           false))
+        };
+    }
+
+    fn names() -> Vec<(Emoji, String)> {
+        vec![(unicode!("😂"), "face with tears of joy".to_string()),
+             (unicode!("😹"), "cat with tears of joy".to_string()),
+             (unicode!("👍"), "thumbs up".to_string()),
+             (unicode!("👎"), "thumbs down".to_string()),
+             (unicode!("🔥"), "fire".to_string()),
+             (custom!(1, 10, "pepe"), "pepe".to_string()),
+             (custom!(2, 20, "pepe"), "pepe".to_string()),
+             (custom!(2, 21, "pepehands"), "pepehands".to_string())]
+    }
+
+    fn find(query: &str, server_id: Option<u64>) -> NameMatch {
+        let names = names();
+
+        find_emoji_by_name(names.iter().map(|&(ref emoji, ref name)| (emoji, name.as_str())),
+                           query,
+                           server_id.map(ServerId))
+    }
+
+    #[test]
+    fn exact_name() {
+        assert_eq!(find("face with tears of joy", None), NameMatch::Found(unicode!("😂")));
+        assert_eq!(find("Fire", None), NameMatch::Found(unicode!("🔥")));
+    }
+
+    #[test]
+    fn shortcode() {
+        assert_eq!(find(":thumbs_up:", None), NameMatch::Found(unicode!("👍")));
+        assert_eq!(find("thumbsup", None), NameMatch::Found(unicode!("👍")));
+        assert_eq!(find(":fire:", None), NameMatch::Found(unicode!("🔥")));
+    }
+
+    #[test]
+    fn suggestions() {
+        assert_eq!(find("joy", None),
+                   NameMatch::Suggestions(vec![(unicode!("😹"),
+                                                "cat with tears of joy".to_string()),
+                                               (unicode!("😂"),
+                                                "face with tears of joy".to_string())]));
+        assert_eq!(find("thumbs", None),
+                   NameMatch::Suggestions(vec![(unicode!("👍"), "thumbs up".to_string()),
+                                               (unicode!("👎"), "thumbs down".to_string())]));
+        assert_eq!(find("face joy", None), NameMatch::Found(unicode!("😂")));
+    }
+
+    #[test]
+    fn custom_emoji() {
+        assert_eq!(find("pepe", Some(1)), NameMatch::Found(custom!(1, 10, "pepe")));
+        assert_eq!(find(":pepe:", Some(2)), NameMatch::Found(custom!(2, 20, "pepe")));
+        assert_eq!(find("pepeh", Some(2)), NameMatch::Found(custom!(2, 21, "pepehands")));
+        assert_eq!(find("pepeh", Some(1)), NameMatch::NotFound);
+
+        match find("pepe", None) {
+            NameMatch::Suggestions(suggestions) => assert_eq!(suggestions.len(), 2),
+            other => panic!("expected suggestions, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn not_found() {
+        assert_eq!(find("", None), NameMatch::NotFound);
+        assert_eq!(find("::", None), NameMatch::NotFound);
+        assert_eq!(find("unicorn", None), NameMatch::NotFound);
+    }
+}
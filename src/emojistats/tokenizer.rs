@@ -0,0 +1,352 @@
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+const ZERO_WIDTH_JOINER: char = '\u{200D}';
+const COMBINING_ENCLOSING_KEYCAP: char = '\u{20E3}';
+const TEXT_PRESENTATION_SELECTOR: char = '\u{FE0E}';
+const EMOJI_PRESENTATION_SELECTOR: char = '\u{FE0F}';
+const WAVING_BLACK_FLAG: char = '\u{1F3F4}';
+const CANCEL_TAG: char = '\u{E007F}';
+
+// Custom emoji names are 2 to 32 characters long
+const MIN_CUSTOM_EMOJI_NAME_LEN: usize = 2;
+const MAX_CUSTOM_EMOJI_NAME_LEN: usize = 32;
+
+// A single emoji found in a message
+#[derive(Debug, PartialEq)]
+pub enum Token<'a> {
+    Custom { name: &'a str, id: u64, animated: bool }, // <:name:id> or <a:name:id>
+    Unicode(&'a str), // A complete emoji sequence, e.g. a ZWJ sequence or a flag
+}
+
+// Splits text into the emoji it contains in a single pass, ignoring everything else
+// Multi-codepoint sequences (ZWJ sequences, keycaps, flags, modifiers and variation selectors)
+// are returned whole, so e.g. 👨‍👩‍👧 is one token rather than 👨, 👩 and 👧
+pub fn tokenize(text: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((start, c)) = chars.next() {
+        if c == '<' {
+            if let Some((token, len)) = parse_custom_emoji(&text[start..]) {
+                tokens.push(token);
+
+                // Skip the rest of the custom emoji
+                while let Some(&(pos, _)) = chars.peek() {
+                    if pos >= start + len {
+                        break;
+                    }
+                    chars.next();
+                }
+            }
+        } else if is_keycap_base(c) {
+            // A keycap is a digit, # or * followed by an optional emoji presentation selector and
+            // a combining enclosing keycap
+            let mut lookahead = chars.clone();
+
+            if let Some(&(_, EMOJI_PRESENTATION_SELECTOR)) = lookahead.peek() {
+                lookahead.next();
+            }
+
+            if let Some(&(pos, COMBINING_ENCLOSING_KEYCAP)) = lookahead.peek() {
+                lookahead.next();
+                chars = lookahead;
+
+                let end = pos + COMBINING_ENCLOSING_KEYCAP.len_utf8();
+                tokens.push(Token::Unicode(&text[start..end]));
+            }
+        } else if is_regional_indicator(c) {
+            // A flag is a pair of regional indicators; a lone regional indicator is ignored
+            if let Some(&(pos, next)) = chars.peek() {
+                if is_regional_indicator(next) {
+                    chars.next();
+                    tokens.push(Token::Unicode(&text[start..(pos + next.len_utf8())]));
+                }
+            }
+        } else if is_pictographic(c) || is_modifier(c) {
+            let end = consume_sequence(&mut chars, start + c.len_utf8(), c);
+            tokens.push(Token::Unicode(&text[start..end]));
+        }
+    }
+
+    tokens
+}
+
+// Consumes the remainder of an emoji sequence beginning with first
+// Returns the byte offset of the end of the sequence
+fn consume_sequence(chars: &mut Peekable<CharIndices>, mut end: usize, first: char) -> usize {
+    end = consume_element_suffix(chars, end);
+
+    // Tag sequences, e.g. the flag of Scotland, follow a waving black flag
+    if first == WAVING_BLACK_FLAG {
+        let mut lookahead = chars.clone();
+        let mut tag_end = None;
+
+        while let Some(&(pos, c)) = lookahead.peek() {
+            if !is_tag(c) {
+                break;
+            }
+
+            lookahead.next();
+
+            if c == CANCEL_TAG {
+                tag_end = Some(pos + c.len_utf8());
+                break;
+            }
+        }
+
+        if let Some(tag_end) = tag_end {
+            *chars = lookahead;
+            return tag_end;
+        }
+    }
+
+    // A zero-width joiner only continues the sequence if another emoji follows it
+    loop {
+        let mut lookahead = chars.clone();
+
+        match lookahead.next() {
+            Some((_, ZERO_WIDTH_JOINER)) => {}
+            _ => break,
+        }
+
+        match lookahead.next() {
+            Some((pos, c)) if is_pictographic(c) => {
+                *chars = lookahead;
+                end = consume_element_suffix(chars, pos + c.len_utf8());
+            }
+            _ => break,
+        }
+    }
+
+    end
+}
+
+// Consumes any variation selectors and skin tone modifiers following an emoji
+// Returns the byte offset of the end of the element
+fn consume_element_suffix(chars: &mut Peekable<CharIndices>, mut end: usize) -> usize {
+    while let Some(&(pos, c)) = chars.peek() {
+        if c == EMOJI_PRESENTATION_SELECTOR || c == TEXT_PRESENTATION_SELECTOR || is_modifier(c) {
+            chars.next();
+            end = pos + c.len_utf8();
+        } else {
+            break;
+        }
+    }
+
+    end
+}
+
+// Parses a custom emoji at the beginning of text, e.g. "<:name:123>", or an animated custom emoji,
+// e.g. "<a:name:123>"
+// Returns the token and its length in bytes
+fn parse_custom_emoji(text: &str) -> Option<(Token, usize)> {
+    let (animated, prefix_len) = if text.starts_with("<:") {
+        (false, 2)
+    } else if text.starts_with("<a:") {
+        (true, 3)
+    } else {
+        return None;
+    };
+
+    let rest = &text[prefix_len..];
+    let name_len = rest.find(':')?;
+    let name = &rest[..name_len];
+
+    if name.len() < MIN_CUSTOM_EMOJI_NAME_LEN || name.len() > MAX_CUSTOM_EMOJI_NAME_LEN ||
+       !name.chars().all(is_custom_emoji_name_char) {
+        return None;
+    }
+
+    let rest = &rest[(name_len + 1)..];
+    let id_len = rest.find('>')?;
+    let id = &rest[..id_len];
+
+    if id.is_empty() || !id.chars().all(|c| c.is_digit(10)) {
+        return None;
+    }
+
+    let id = id.parse::<u64>().ok()?;
+
+    Some((Token::Custom { name, id, animated }, prefix_len + name_len + 1 + id_len + 1))
+}
+
+fn is_custom_emoji_name_char(c: char) -> bool {
+    match c {
+        'a'...'z' | 'A'...'Z' | '0'...'9' | '_' => true,
+        _ => false,
+    }
+}
+
+fn is_keycap_base(c: char) -> bool {
+    match c {
+        '0'...'9' | '#' | '*' => true,
+        _ => false,
+    }
+}
+
+fn is_regional_indicator(c: char) -> bool {
+    c >= '\u{1F1E6}' && c <= '\u{1F1FF}'
+}
+
+// Fitzpatrick skin tone modifiers
+fn is_modifier(c: char) -> bool {
+    c >= '\u{1F3FB}' && c <= '\u{1F3FF}'
+}
+
+fn is_tag(c: char) -> bool {
+    c >= '\u{E0020}' && c <= '\u{E007F}'
+}
+
+// Whether a character may begin an emoji (approximately Unicode's Extended_Pictographic property)
+fn is_pictographic(c: char) -> bool {
+    match c as u32 {
+        0x00A9 | 0x00AE | 0x203C | 0x2049 | 0x2122 | 0x2139 | 0x2328 | 0x23CF | 0x24C2 |
+        0x25B6 | 0x25C0 | 0x2934 | 0x2935 | 0x2B50 | 0x2B55 | 0x3030 | 0x303D | 0x3297 |
+        0x3299 => true,
+        0x2194...0x2199 | 0x21A9...0x21AA | 0x231A...0x231B | 0x23E9...0x23F3 |
+        0x23F8...0x23FA | 0x25AA...0x25AB | 0x25FB...0x25FE | 0x2600...0x27BF |
+        0x2B05...0x2B07 | 0x2B1B...0x2B1C => true,
+        0x1F000...0x1F1E5 | 0x1F200...0x1F3FA | 0x1F400...0x1FAFF => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{tokenize, Token};
+
+    macro_rules! test {
+        ($test_string:expr => [$($token:expr),*]) => {
+            assert_eq!(tokenize($test_string), vec![$($token),*] as Vec<Token>);
+        };
+    }
+
+    macro_rules! unicode {
+        ($emoji:expr) => { Token::Unicode($emoji) };
+    }
+
+    macro_rules! custom {
+        ($name:expr, $id:expr) => { Token::Custom { name: $name, id: $id, animated: false } };
+    }
+
+    macro_rules! animated {
+        ($name:expr, $id:expr) => { Token::Custom { name: $name, id: $id, animated: true } };
+    }
+
+    #[test]
+    fn no_emoji() {
+        test!("" => []);
+        test!("abc" => []);
+        test!("123 #channel *bold*" => []);
+        test!("日本語のテキスト" => []);
+        test!("a < b > c" => []);
+    }
+
+    #[test]
+    fn single_codepoint() {
+        test!("😀" => [unicode!("😀")]);
+        test!("a😀b" => [unicode!("😀")]);
+        test!("😀😀" => [unicode!("😀"), unicode!("😀")]);
+        test!("😀 🎉" => [unicode!("😀"), unicode!("🎉")]);
+        test!("©®" => [unicode!("©"), unicode!("®")]);
+        test!("☺" => [unicode!("☺")]);
+    }
+
+    #[test]
+    fn variation_selectors() {
+        test!("❤️" => [unicode!("❤️")]);
+        test!("❤︎" => [unicode!("❤︎")]);
+        test!("❤️❤" => [unicode!("❤️"), unicode!("❤")]);
+        test!("\u{FE0F}" => []);
+    }
+
+    #[test]
+    fn modifiers() {
+        test!("👍🏽" => [unicode!("👍🏽")]);
+        test!("👍🏽👍" => [unicode!("👍🏽"), unicode!("👍")]);
+        test!("🏻" => [unicode!("🏻")]);
+        test!("a🏿" => [unicode!("🏿")]);
+    }
+
+    #[test]
+    fn zwj_sequences() {
+        test!("👨‍👩‍👧" => [unicode!("👨‍👩‍👧")]);
+        test!("👨‍👩‍👧👨" => [unicode!("👨‍👩‍👧"), unicode!("👨")]);
+        test!("🏳️‍🌈" => [unicode!("🏳️‍🌈")]);
+        test!("👩🏽‍💻" => [unicode!("👩🏽‍💻")]);
+        test!("🏃‍♀️" => [unicode!("🏃‍♀️")]);
+        test!("👩‍❤️‍💋‍👨" => [unicode!("👩‍❤️‍💋‍👨")]);
+    }
+
+    #[test]
+    fn dangling_zwj() {
+        test!("👨\u{200D}" => [unicode!("👨")]);
+        test!("👨\u{200D}a👩" => [unicode!("👨"), unicode!("👩")]);
+        test!("\u{200D}👩" => [unicode!("👩")]);
+        test!("👨\u{200D}\u{200D}👩" => [unicode!("👨"), unicode!("👩")]);
+    }
+
+    #[test]
+    fn keycaps() {
+        test!("#⃣" => [unicode!("#⃣")]);
+        test!("1️⃣" => [unicode!("1️⃣")]);
+        test!("*⃣9⃣" => [unicode!("*⃣"), unicode!("9⃣")]);
+        test!("10⃣" => [unicode!("0⃣")]);
+        test!("1\u{FE0F}" => []);
+        test!("\u{20E3}" => []);
+    }
+
+    #[test]
+    fn flags() {
+        test!("🇨🇦" => [unicode!("🇨🇦")]);
+        test!("🇨🇦🇺🇸" => [unicode!("🇨🇦"), unicode!("🇺🇸")]);
+        test!("🇨🇦🇺" => [unicode!("🇨🇦")]);
+        test!("🇨 a" => []);
+        test!("🏴󠁧󠁢󠁳󠁣󠁴󠁿" => [unicode!("🏴󠁧󠁢󠁳󠁣󠁴󠁿")]);
+        test!("🏴" => [unicode!("🏴")]);
+        test!("🏴\u{E0067}\u{E0062}" => [unicode!("🏴")]);
+    }
+
+    #[test]
+    fn custom_emoji() {
+        test!("<:thonk:123456789>" => [custom!("thonk", 123456789)]);
+        test!("a<:thonk:1>b<:ok_hand_2:2>" => [custom!("thonk", 1), custom!("ok_hand_2", 2)]);
+        test!("<:thonk:1><:thonk:1>" => [custom!("thonk", 1), custom!("thonk", 1)]);
+        test!("<<:thonk:1>>" => [custom!("thonk", 1)]);
+    }
+
+    #[test]
+    fn animated_custom_emoji() {
+        test!("<a:partyparrot:123456789>" => [animated!("partyparrot", 123456789)]);
+        test!("<:thonk:1><a:thonk:1>" => [custom!("thonk", 1), animated!("thonk", 1)]);
+        test!("<a:thonk:>" => []);
+        test!("<a::123>" => []);
+        test!("<b:thonk:123>" => []);
+        test!("<aa:thonk:123>" => []);
+    }
+
+    #[test]
+    fn not_custom_emoji() {
+        test!("<:thonk:>" => []);
+        test!("<:thonk:abc>" => []);
+        test!("<::123>" => []);
+        test!("<:a:123>" => []);
+        test!("<:thonk 2:123>" => []);
+        test!("<:thonk:123" => []);
+        test!(":thonk:123>" => []);
+        test!("<#123456789>" => []);
+        test!("<@123456789>" => []);
+        test!("<:thonk:99999999999999999999999>" => []);
+    }
+
+    #[test]
+    fn mixed() {
+        test!("Hi 👋🏼 <:wave:42> 🇯🇵!👨‍👩‍👧‍👦 3⃣" =>
+              [unicode!("👋🏼"),
+               custom!("wave", 42),
+               unicode!("🇯🇵"),
+               unicode!("👨‍👩‍👧‍👦"),
+               unicode!("3⃣")]);
+    }
+}
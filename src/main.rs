@@ -1,4 +1,5 @@
 extern crate config;
+extern crate discord;
 #[macro_use]
 extern crate log;
 extern crate log4rs;
@@ -6,28 +7,39 @@ extern crate nix;
 extern crate postgres;
 
 mod arg;
+mod backfill;
 mod bot_utility;
 mod emojistats;
+mod options;
+mod permissions;
+mod settings;
 mod bot;
 
 use std::env::args;
 use std::ffi::CString;
+use std::fs::File;
+use std::io::{self, BufRead, Read};
 use std::process;
 use nix::unistd::execv;
 use log4rs::config::Logger;
-use emojistats::Database;
+use emojistats::{parse_emoji_data, Database, UnicodeEmoji};
 use bot::BotDisposition;
+use discord::model::UserId;
+use permissions::hash_password;
 
 const PROGRAM_NAME: &str = env!("CARGO_PKG_NAME");
 const PROGRAM_VERSION: &str = env!("CARGO_PKG_VERSION");
 const LOG_FILENAME: &str = "emojistats.log";
 const LOG_FORMAT: &str = "{d(%Y-%m-%d %H:%M:%S %Z)(local)}: [{M}] {h([{l}])} {m}{n}";
+// Unicode's emoji-test.txt for Emoji 17.0, used unless another file is named in the config
+const BUNDLED_EMOJI_DATA: &str = include_str!("../emoji-test.txt");
 
 enum ExitStatus {
     UnableToObtainConfig = 10,
     UnableToObtainExecutablePath = 11,
     UnableToRestart = 12,
     UnableToConvertCString = 13,
+    UnableToHashPassword = 14,
     UnableToCreateDatabaseConnection = 21,
 }
 
@@ -112,22 +124,127 @@ fn restart() {
     }
 }
 
+// Loads the Unicode emoji listed in the bundled emoji-test.txt, or in the Unicode emoji data file
+// named by emojistats.emoji_file, along with any extra emoji listed in emojistats.emoji
 fn load_unicode_emoji(config: &config::Config, bot: &mut bot::Bot) {
+    let emoji_data = match config.get_str("emojistats.emoji_file") {
+        Ok(emoji_filename) => {
+            match read_file(&emoji_filename) {
+                Ok(emoji_data) => {
+                    info!("Loading Unicode emoji from \"{}\"", emoji_filename);
+                    emoji_data
+                }
+                Err(reason) => {
+                    warn!("Unable to read emoji file \"{}\"; using bundled emoji instead: {}",
+                          emoji_filename,
+                          reason);
+                    BUNDLED_EMOJI_DATA.to_string()
+                }
+            }
+        }
+        Err(_) => BUNDLED_EMOJI_DATA.to_string(),
+    };
+
     let mut num_emoji_loaded = 0;
 
+    for emoji in parse_emoji_data(&emoji_data) {
+        if bot.add_unicode_emoji(emoji) {
+            num_emoji_loaded += 1;
+        }
+    }
+
+    // Emoji already in the emoji data are skipped, so that an old list of every emoji adds
+    // nothing
     if let Ok(emoji_list) = config.get_array("emojistats.emoji") {
+        warn!("emojistats.emoji is deprecated; list extra emoji in an emoji data file named by \
+               emojistats.emoji_file instead");
+
         for emoji_value in emoji_list {
             if let Ok(emoji) = emoji_value.into_str() {
-                bot.add_unicode_emoji(emoji);
-                num_emoji_loaded += 1;
+                let added = bot.add_unicode_emoji(UnicodeEmoji {
+                                                      emoji,
+                                                      name: None,
+                                                      group: None,
+                                                      subgroup: None,
+                                                  });
+
+                if added {
+                    num_emoji_loaded += 1;
+                }
+            }
+        }
+    }
+
+    info!("Loaded {} Unicode emoji", num_emoji_loaded);
+}
+
+// Reads a password from standard input and prints its hash, for bot_admin_password_hash
+fn print_password_hash() {
+    let stdin = io::stdin();
+    let mut password = String::new();
+
+    if let Err(reason) = stdin.lock().read_line(&mut password) {
+        eprintln!("Unable to read password: {}", reason);
+        process::exit(ExitStatus::UnableToHashPassword as i32);
+    }
+
+    match hash_password(password.trim()) {
+        Ok(password_hash) => println!("{}", password_hash),
+        Err(reason) => {
+            eprintln!("Unable to hash password: {}", reason);
+            process::exit(ExitStatus::UnableToHashPassword as i32);
+        }
+    }
+}
+
+// Adds the bot administrators listed in config.bot_admins and enables password authentication if
+// config.bot_admin_password_hash (or the older config.bot_admin_password) is set
+fn load_bot_admins(config: &config::Config, bot: &mut bot::Bot) {
+    if let Ok(admin_ids) = config.get_array("config.bot_admins") {
+        for admin_id in admin_ids {
+            // User IDs may be given as numbers or strings
+            match admin_id.into_int() {
+                Ok(admin_id) if admin_id > 0 => bot.add_bot_admin(UserId(admin_id as u64)),
+                _ => warn!("Ignoring invalid user ID in config.bot_admins"),
+            }
+        }
+    }
+
+    if let Ok(password_hash) = config.get_str("config.bot_admin_password_hash") {
+        if !password_hash.is_empty() && !bot.set_bot_admin_password_hash(password_hash) {
+            warn!("Ignoring invalid config.bot_admin_password_hash; generate one with \
+                   --hash-password");
+        }
+    } else if let Ok(password) = config.get_str("config.bot_admin_password") {
+        // Only the hash of a plaintext password is kept
+        if !password.is_empty() {
+            warn!("config.bot_admin_password is deprecated; use bot_admin_password_hash instead");
+
+            match hash_password(&password) {
+                Ok(password_hash) => {
+                    bot.set_bot_admin_password_hash(password_hash);
+                }
+                Err(reason) => warn!("Unable to hash bot administration password: {}", reason),
             }
         }
     }
+}
+
+fn read_file(filename: &str) -> io::Result<String> {
+    let mut file = File::open(filename)?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
 
-    info!("Loaded {} Unicode emoji from config", num_emoji_loaded);
+    Ok(contents)
 }
 
 fn main() {
+    // Used to generate config.bot_admin_password_hash without starting the bot
+    if args().nth(1).as_ref().map(String::as_str) == Some("--hash-password") {
+        print_password_hash();
+        return;
+    }
+
     init_logging();
     info!("Starting {} (version {}).", PROGRAM_NAME, PROGRAM_VERSION);
 
@@ -182,16 +299,15 @@ fn main() {
 
     // Get bot settings and connect to Discord
     let bot_token = config.get_str("config.bot_token").unwrap_or("".to_string());
-    let bot_admin_password = config
-        .get_str("config.bot_admin_password")
-        .unwrap_or("".to_string());
-    let mut bot = match bot::Bot::new(&bot_token, &bot_admin_password, db) {
+    let mut bot = match bot::Bot::new(&bot_token, db) {
         Ok(bot) => bot,
         Err(bot_error) => process::exit(bot_error as i32),
     };
     info!("Connected to Discord successfully");
 
     // Perform other setup tasks
+    load_bot_admins(&config, &mut bot);
+
     if let Ok(about_text) = config.get_str("config.about_text") {
         bot.set_about_text(about_text);
     }
@@ -203,6 +319,14 @@ fn main() {
     if let Ok(feedback_filename) = config.get_str("config.feedback_filename") {
         bot.set_feedback_file(feedback_filename);
     }
+
+    if let Ok(page_size) = config.get_int("config.page_size") {
+        bot.set_page_size(page_size as u32);
+    }
+
+    if let Ok(prefix) = config.get_str("config.prefix") {
+        bot.set_prefix(prefix);
+    }
     load_unicode_emoji(&config, &mut bot);
 
     // Begin event loop
@@ -0,0 +1,281 @@
+extern crate time;
+
+use emojistats::{Bucket, Period, UsageFilter, UsageSource};
+
+const SECONDS_PER_DAY: i64 = 86400;
+const MAX_PERIOD_DAYS: u32 = 36500;
+
+// Options which may follow any statistics command, e.g. "@EmojiStats server reactions week"
+#[derive(Debug, PartialEq)]
+pub struct StatsOptions {
+    pub filter: UsageFilter,
+    pub period_specified: bool, // Whether the period was given or is the default
+    pub show_variants: bool, // Whether to break down usage by skin tone and gender variant
+    pub page: u32, // Which page of a leaderboard to show, starting from 1
+    pub show_rank: bool, // Whether to show where the user ranks for an emoji
+}
+
+impl Default for StatsOptions {
+    fn default() -> StatsOptions {
+        StatsOptions {
+            filter: UsageFilter::default(),
+            period_specified: false,
+            show_variants: false,
+            page: 1,
+            show_rank: false,
+        }
+    }
+}
+
+// Separates statistics options from the rest of a command's arguments
+// Returns (the options, the remaining arguments in the order they appeared)
+// Options are case-insensitive; if an option is repeated, the last one wins
+pub fn parse_stats_options(args: &str) -> (StatsOptions, Vec<&str>) {
+    let mut options = StatsOptions::default();
+    let mut other_args = Vec::new();
+    let mut args = args.split_whitespace().peekable();
+
+    while let Some(arg) = args.next() {
+        let lowercase_arg = arg.to_lowercase();
+
+        match lowercase_arg.as_ref() {
+            // A page number, e.g. "page 2"
+            "page" if args.peek().and_then(|arg| parse_page(arg)).is_some() => {
+                options.page = args.next().and_then(parse_page).unwrap();
+            }
+            "text" | "messages" => options.filter.source = UsageSource::Text,
+            "reactions" | "reacts" => options.filter.source = UsageSource::Reactions,
+            "all" | "combined" => options.filter.source = UsageSource::All,
+            "variants" | "tones" => options.show_variants = true,
+            "external" | "nitro" => options.filter.external = true,
+            "rank" | "mine" => options.show_rank = true,
+            _ => {
+                match parse_period(&lowercase_arg) {
+                    Some(period) => {
+                        options.filter.period = period;
+                        options.period_specified = true;
+                    }
+                    None => other_args.push(arg),
+                }
+            }
+        }
+    }
+
+    (options, other_args)
+}
+
+fn parse_page(arg: &str) -> Option<u32> {
+    match arg.parse::<u32>() {
+        Ok(page) if page > 0 => Some(page),
+        _ => None,
+    }
+}
+
+// Parses a period such as "today", "week", "30d", "2017-07-01" or "2017-07-01..2017-07-31"
+// Either end of a date range may be left out, e.g. "2017-07-01.."
+pub fn parse_period(arg: &str) -> Option<Period> {
+    match arg {
+        "ever" | "alltime" => return Some(Period::AllTime),
+        "today" => return Some(Period::Today),
+        "yesterday" => return Some(Period::Yesterday),
+        "week" => return Some(Period::Days(7)),
+        "month" => return Some(Period::Days(30)),
+        "year" => return Some(Period::Days(365)),
+        _ => {}
+    }
+
+    // A number of days or weeks, e.g. "30d" or "2w"
+    if let Some(unit) = arg.chars().last() {
+        let number = &arg[..(arg.len() - unit.len_utf8())];
+
+        if let Ok(number) = number.parse::<u32>() {
+            let days = match unit {
+                'd' => Some(number),
+                'w' => number.checked_mul(7),
+                _ => None,
+            };
+
+            return match days {
+                Some(days) if days > 0 && days <= MAX_PERIOD_DAYS => Some(Period::Days(days)),
+                _ => None,
+            };
+        }
+    }
+
+    // A range of dates
+    if let Some(pos) = arg.find("..") {
+        let (from, to) = (&arg[..pos], &arg[(pos + 2)..]);
+
+        let from = if from.is_empty() {
+            None
+        } else {
+            Some(parse_day(from)?)
+        };
+        let to = if to.is_empty() {
+            None
+        } else {
+            Some(parse_day(to)?)
+        };
+
+        return match (from, to) {
+            (None, None) => None,
+            (Some(from), Some(to)) if from > to => None,
+            (from, to) => Some(Period::Dates(from, to)),
+        };
+    }
+
+    // A single date
+    parse_day(arg).map(|day| Period::Dates(Some(day), Some(day)))
+}
+
+// Parses the bucket size for a trend
+pub fn parse_bucket(arg: &str) -> Option<Bucket> {
+    match arg.to_lowercase().as_ref() {
+        "daily" => Some(Bucket::Day),
+        "weekly" => Some(Bucket::Week),
+        "monthly" => Some(Bucket::Month),
+        _ => None,
+    }
+}
+
+// Parses a YYYY-MM-DD date as a number of days since the Unix epoch
+fn parse_day(date: &str) -> Option<i64> {
+    if date.len() != 10 {
+        return None;
+    }
+
+    match time::strptime(date, "%Y-%m-%d") {
+        Ok(tm) => Some(tm.to_timespec().sec / SECONDS_PER_DAY),
+        Err(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_bucket, parse_period, parse_stats_options};
+    use emojistats::{Bucket, Period, UsageSource};
+
+    macro_rules! test {
+        ($test_string:expr => ($source:ident, $period:expr, $other_args:expr)) => {
+            let (options, other_args) = parse_stats_options($test_string);
+            assert_eq!(options.filter.source, UsageSource::$source);
+            assert_eq!(options.filter.period, $period);
+            assert_eq!(other_args, $other_args as Vec<&str>);
+        };
+    }
+
+    #[test]
+    fn no_options() {
+        test!("" => (All, Period::AllTime, vec![]));
+        test!("  \t " => (All, Period::AllTime, vec![]));
+        test!("abc" => (All, Period::AllTime, vec!["abc"]));
+    }
+
+    #[test]
+    fn source() {
+        test!("text" => (Text, Period::AllTime, vec![]));
+        test!("messages" => (Text, Period::AllTime, vec![]));
+        test!("reactions" => (Reactions, Period::AllTime, vec![]));
+        test!("REACTS" => (Reactions, Period::AllTime, vec![]));
+        test!("combined" => (All, Period::AllTime, vec![]));
+        test!("text all" => (All, Period::AllTime, vec![]));
+        test!("all reactions" => (Reactions, Period::AllTime, vec![]));
+    }
+
+    #[test]
+    fn period() {
+        test!("week" => (All, Period::Days(7), vec![]));
+        test!("text Today" => (Text, Period::Today, vec![]));
+        test!("30d reactions" => (Reactions, Period::Days(30), vec![]));
+        test!("week ever" => (All, Period::AllTime, vec![]));
+    }
+
+    #[test]
+    fn period_specified() {
+        assert_eq!(parse_stats_options("text").0.period_specified, false);
+        assert_eq!(parse_stats_options("ever").0.period_specified, true);
+        assert_eq!(parse_stats_options("reactions 2w").0.period_specified, true);
+    }
+
+    #[test]
+    fn show_variants() {
+        assert_eq!(parse_stats_options("").0.show_variants, false);
+        assert_eq!(parse_stats_options("variants").0.show_variants, true);
+        assert_eq!(parse_stats_options("text Tones week").0.show_variants, true);
+        test!("variants abc" => (All, Period::AllTime, vec!["abc"]));
+    }
+
+    #[test]
+    fn page() {
+        assert_eq!(parse_stats_options("").0.page, 1);
+        assert_eq!(parse_stats_options("page 2").0.page, 2);
+        assert_eq!(parse_stats_options("week PAGE 10 text").0.page, 10);
+        test!("page 3 reactions" => (Reactions, Period::AllTime, vec![]));
+        test!("page" => (All, Period::AllTime, vec!["page"]));
+        test!("page 0" => (All, Period::AllTime, vec!["page", "0"]));
+        test!("page abc" => (All, Period::AllTime, vec!["page", "abc"]));
+        test!("page week" => (All, Period::Days(7), vec!["page"]));
+    }
+
+    #[test]
+    fn show_rank() {
+        assert_eq!(parse_stats_options("").0.show_rank, false);
+        assert_eq!(parse_stats_options("rank").0.show_rank, true);
+        assert_eq!(parse_stats_options("Mine week").0.show_rank, true);
+        test!("rank abc" => (All, Period::AllTime, vec!["abc"]));
+    }
+
+    #[test]
+    fn external() {
+        assert_eq!(parse_stats_options("").0.filter.external, false);
+        assert_eq!(parse_stats_options("external").0.filter.external, true);
+        assert_eq!(parse_stats_options("reactions Nitro").0.filter.external, true);
+        test!("external abc" => (All, Period::AllTime, vec!["abc"]));
+    }
+
+    #[test]
+    fn other_args() {
+        test!("abc reactions def" => (Reactions, Period::AllTime, vec!["abc", "def"]));
+        test!("  <#123>  text month " => (Text, Period::Days(30), vec!["<#123>"]));
+    }
+
+    #[test]
+    fn periods() {
+        assert_eq!(parse_period("today"), Some(Period::Today));
+        assert_eq!(parse_period("yesterday"), Some(Period::Yesterday));
+        assert_eq!(parse_period("month"), Some(Period::Days(30)));
+        assert_eq!(parse_period("1d"), Some(Period::Days(1)));
+        assert_eq!(parse_period("2w"), Some(Period::Days(14)));
+        assert_eq!(parse_period("2017-07-30"),
+                   Some(Period::Dates(Some(17377), Some(17377))));
+        assert_eq!(parse_period("2017-07-30..2017-07-31"),
+                   Some(Period::Dates(Some(17377), Some(17378))));
+        assert_eq!(parse_period("2017-07-30.."), Some(Period::Dates(Some(17377), None)));
+        assert_eq!(parse_period("..2017-07-31"), Some(Period::Dates(None, Some(17378))));
+    }
+
+    #[test]
+    fn not_periods() {
+        assert_eq!(parse_period(""), None);
+        assert_eq!(parse_period("d"), None);
+        assert_eq!(parse_period("0d"), None);
+        assert_eq!(parse_period("-1d"), None);
+        assert_eq!(parse_period("99999d"), None);
+        assert_eq!(parse_period("3y"), None);
+        assert_eq!(parse_period("3😀"), None);
+        assert_eq!(parse_period(".."), None);
+        assert_eq!(parse_period("2017-07-31..2017-07-30"), None);
+        assert_eq!(parse_period("2017-07-30..abc"), None);
+        assert_eq!(parse_period("2017-13-01"), None);
+        assert_eq!(parse_period("2017-7-1"), None);
+    }
+
+    #[test]
+    fn buckets() {
+        assert_eq!(parse_bucket("daily"), Some(Bucket::Day));
+        assert_eq!(parse_bucket("Weekly"), Some(Bucket::Week));
+        assert_eq!(parse_bucket("monthly"), Some(Bucket::Month));
+        assert_eq!(parse_bucket("week"), None);
+        assert_eq!(parse_bucket(""), None);
+    }
+}
@@ -0,0 +1,190 @@
+extern crate argon2;
+extern crate discord;
+extern crate rand;
+
+use std::collections::HashMap;
+use std::io;
+
+use self::argon2::{Config, Variant};
+use self::discord::model::UserId;
+use self::rand::{OsRng, Rng};
+
+// Password hashes are Argon2id hashes in the standard encoded format, "$argon2id$v=19$...", which
+// records the parameters and the salt
+const SALT_LENGTH: usize = 16;
+
+// What a user may do, from least to most
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PermissionLevel {
+    Member,
+    ServerManager, // Owns the server or has the Manage Server permission on it
+    BotAdmin,
+}
+
+// Why a user is a bot administrator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdminSource {
+    Owner, // Owns the bot's Discord application
+    Config, // Listed in the config file
+    Password, // Authenticated with the bot administration password
+}
+
+impl AdminSource {
+    pub fn description(&self) -> &'static str {
+        match *self {
+            AdminSource::Owner => "application owner",
+            AdminSource::Config => "listed in the config file",
+            AdminSource::Password => "authenticated with the password",
+        }
+    }
+}
+
+// The bot administrators, and the hash of the bot administration password if password
+// authentication is enabled
+#[derive(Debug, Default)]
+pub struct BotAdmins {
+    admins: HashMap<UserId, AdminSource>,
+    password_hash: Option<String>,
+}
+
+impl BotAdmins {
+    pub fn is_admin(&self, user_id: &UserId) -> bool {
+        self.admins.contains_key(user_id)
+    }
+
+    pub fn source(&self, user_id: &UserId) -> Option<AdminSource> {
+        self.admins.get(user_id).cloned()
+    }
+
+    pub fn user_ids(&self) -> Vec<UserId> {
+        self.admins.keys().cloned().collect()
+    }
+
+    // An owner or configured administrator stays one even if they also authenticate
+    pub fn add(&mut self, user_id: UserId, source: AdminSource) {
+        let existing = self.admins.entry(user_id).or_insert(source);
+
+        if *existing == AdminSource::Password {
+            *existing = source;
+        }
+    }
+
+    // Only administrators who authenticated with the password can be removed; the others are
+    // added again whenever the bot starts
+    pub fn remove(&mut self, user_id: &UserId) -> bool {
+        if self.admins.get(user_id) == Some(&AdminSource::Password) {
+            self.admins.remove(user_id);
+            true
+        } else {
+            false
+        }
+    }
+
+    // Returns false if the hash isn't one produced by hash_password
+    pub fn set_password_hash<S>(&mut self, password_hash: S) -> bool
+        where S: Into<String>
+    {
+        let password_hash = password_hash.into().trim().to_string();
+
+        if argon2::verify_encoded(&password_hash, b"").is_err() {
+            return false;
+        }
+
+        self.password_hash = Some(password_hash);
+        true
+    }
+
+    pub fn is_password_set(&self) -> bool {
+        self.password_hash.is_some()
+    }
+
+    pub fn check_password(&self, password: &str) -> bool {
+        match self.password_hash {
+            Some(ref password_hash) => verify_password(password, password_hash),
+            None => false,
+        }
+    }
+}
+
+// Hashes a password with a random salt, for storing in the config file
+pub fn hash_password(password: &str) -> io::Result<String> {
+    let mut salt = [0u8; SALT_LENGTH];
+    OsRng::new()?.fill_bytes(&mut salt);
+
+    hash_password_with_salt(password, &salt)
+}
+
+fn hash_password_with_salt(password: &str, salt: &[u8]) -> io::Result<String> {
+    let config = Config {
+        variant: Variant::Argon2id,
+        ..Config::default()
+    };
+
+    argon2::hash_encoded(password.as_bytes(), salt, &config)
+        .map_err(|reason| io::Error::new(io::ErrorKind::InvalidInput, reason))
+}
+
+pub fn verify_password(password: &str, password_hash: &str) -> bool {
+    argon2::verify_encoded(password_hash.trim(), password.as_bytes()).unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate discord;
+
+    use super::{hash_password_with_salt, verify_password, AdminSource, BotAdmins,
+                PermissionLevel};
+
+    use self::discord::model::UserId;
+
+    fn hash(password: &str) -> String {
+        hash_password_with_salt(password, b"saltsalt").unwrap()
+    }
+
+    #[test]
+    fn password_hash() {
+        let password_hash = hash("hunter2");
+
+        assert!(password_hash.starts_with("$argon2id$v=19$m=4096,t=3,p=1$c2FsdHNhbHQ$"));
+        assert_eq!(verify_password("hunter2", &password_hash), true);
+        assert_eq!(verify_password("hunter3", &password_hash), false);
+        assert_eq!(verify_password("hunter2", "hunter2"), false);
+        assert_eq!(verify_password("hunter2", "$argon2id$v=19$m=4096,t=3,p=1$c2FsdHNhbHQ$"),
+                   false);
+        assert!(hash_password_with_salt("hunter2", b"salt").is_err());
+    }
+
+    #[test]
+    fn bot_admins() {
+        let mut bot_admins = BotAdmins::default();
+
+        bot_admins.add(UserId(1), AdminSource::Owner);
+        bot_admins.add(UserId(2), AdminSource::Password);
+        bot_admins.add(UserId(1), AdminSource::Password);
+
+        assert_eq!(bot_admins.is_admin(&UserId(1)), true);
+        assert_eq!(bot_admins.is_admin(&UserId(3)), false);
+        assert_eq!(bot_admins.source(&UserId(1)), Some(AdminSource::Owner));
+        assert_eq!(bot_admins.remove(&UserId(1)), false);
+        assert_eq!(bot_admins.remove(&UserId(2)), true);
+        assert_eq!(bot_admins.is_admin(&UserId(2)), false);
+    }
+
+    #[test]
+    fn bot_admin_password() {
+        let mut bot_admins = BotAdmins::default();
+
+        assert_eq!(bot_admins.check_password(""), false);
+        assert_eq!(bot_admins.set_password_hash("hunter2"), false);
+        assert_eq!(bot_admins.is_password_set(), false);
+        assert_eq!(bot_admins.set_password_hash(hash("hunter2")), true);
+        assert_eq!(bot_admins.check_password("hunter2"), true);
+        assert_eq!(bot_admins.check_password("hunter3"), false);
+    }
+
+    #[test]
+    fn permission_levels() {
+        assert!(PermissionLevel::Member < PermissionLevel::ServerManager);
+        assert!(PermissionLevel::ServerManager < PermissionLevel::BotAdmin);
+    }
+}
@@ -0,0 +1,316 @@
+use std::collections::HashMap;
+use emojistats::Period;
+use options::parse_period;
+
+// Prefixes are kept short so that they're quick to type
+const MAX_PREFIX_LENGTH: usize = 10;
+
+// Commands that can be disabled on a server, and their aliases
+// The settings command can't be disabled, so that it can't lock a server out of its settings
+const COMMANDS: [(&str, &[&str]); 14] = [("global", &["g"]),
+                                         ("server", &["s"]),
+                                         ("channel", &["c"]),
+                                         ("me", &["m"]),
+                                         ("user", &[]), // @user
+                                         ("role", &[]), // @role
+                                         ("emoji", &[]), // (emoji)
+                                         ("trend", &["t"]),
+                                         ("ratio", &["r"]),
+                                         ("compare", &["vs"]),
+                                         ("pairs", &[]),
+                                         ("combos", &[]),
+                                         ("leastused", &[]),
+                                         ("unused", &[])];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Setting {
+    PageSize,
+    Period,
+    UserLeaderboards,
+    DisabledCommands,
+    Prefix,
+}
+
+impl Setting {
+    pub fn all() -> &'static [Setting] {
+        const ALL: [Setting; 5] = [Setting::PageSize,
+                                   Setting::Period,
+                                   Setting::UserLeaderboards,
+                                   Setting::DisabledCommands,
+                                   Setting::Prefix];
+        &ALL
+    }
+
+    pub fn from_name(name: &str) -> Option<Setting> {
+        let name = name.to_lowercase();
+
+        Setting::all()
+            .iter()
+            .find(|setting| setting.name() == name)
+            .cloned()
+    }
+
+    // The name used in the settings command and in the database
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Setting::PageSize => "pagesize",
+            Setting::Period => "period",
+            Setting::UserLeaderboards => "userleaderboards",
+            Setting::DisabledCommands => "disabledcommands",
+            Setting::Prefix => "prefix",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match *self {
+            Setting::PageSize => "The number of entries on each page of a leaderboard",
+            Setting::Period => {
+                "The period statistics cover when none is given, e.g. `month` or `30d`"
+            }
+            Setting::UserLeaderboards => "Whether to show the top users (`on` or `off`)",
+            Setting::DisabledCommands => "Commands that can't be used on this server",
+            Setting::Prefix => {
+                "Text that can begin a command instead of mentioning the bot, e.g. `!es`, or \
+                 `none`"
+            }
+        }
+    }
+}
+
+// A server's settings; any that haven't been set take the bot's defaults
+// Values are kept as they're stored in the database, and are parsed when they're used
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ServerSettings {
+    values: HashMap<Setting, String>,
+}
+
+impl ServerSettings {
+    // Returns the setting's value as it's stored, or None if it hasn't been set
+    pub fn get(&self, setting: Setting) -> Option<&str> {
+        self.values.get(&setting).map(|value| value.as_str())
+    }
+
+    // Checks and sets a setting from a value given in the settings command, returning the value to
+    // store, or a description of the problem if the value isn't valid
+    // max_page_size is the largest page size the bot allows
+    pub fn set(&mut self,
+               setting: Setting,
+               value: &str,
+               max_page_size: u32)
+               -> Result<String, String> {
+        let value = normalise_value(setting, value, max_page_size)?;
+
+        self.values.insert(setting, value.clone());
+
+        Ok(value)
+    }
+
+    // Sets a setting from a value stored in the database, which is trusted
+    pub fn load(&mut self, setting: Setting, value: String) {
+        self.values.insert(setting, value);
+    }
+
+    pub fn reset(&mut self, setting: Setting) {
+        self.values.remove(&setting);
+    }
+
+    pub fn page_size(&self) -> Option<u32> {
+        self.get(Setting::PageSize)
+            .and_then(|value| value.parse::<u32>().ok())
+    }
+
+    pub fn period(&self) -> Option<Period> {
+        self.get(Setting::Period).and_then(parse_period)
+    }
+
+    // Returns an empty prefix if the server has turned the prefix off, or None if it hasn't set
+    // one, in which case the bot's prefix is used
+    pub fn prefix(&self) -> Option<&str> {
+        self.get(Setting::Prefix)
+    }
+
+    pub fn show_user_leaderboards(&self) -> bool {
+        self.get(Setting::UserLeaderboards) != Some("off")
+    }
+
+    // The command may be any alias of a command, e.g. "g" for "global"
+    pub fn is_command_disabled(&self, command: &str) -> bool {
+        let command = match find_command(command) {
+            Some(command) => command,
+            None => return false,
+        };
+
+        match self.get(Setting::DisabledCommands) {
+            Some(value) => value.split_whitespace().any(|disabled| disabled == command),
+            None => false,
+        }
+    }
+}
+
+fn normalise_value(setting: Setting, value: &str, max_page_size: u32) -> Result<String, String> {
+    let value = value.trim().to_lowercase();
+
+    match setting {
+        Setting::PageSize => {
+            match value.parse::<u32>() {
+                Ok(page_size) if page_size > 0 && page_size <= max_page_size => {
+                    Ok(page_size.to_string())
+                }
+                _ => Err(format!("Please give a number from 1 to {}.", max_page_size)),
+            }
+        }
+        Setting::Period => {
+            match parse_period(&value) {
+                Some(_) => Ok(value),
+                None => Err("Please give a period, e.g. `week`, `30d` or `ever`.".to_string()),
+            }
+        }
+        Setting::UserLeaderboards => {
+            match value.as_ref() {
+                "on" | "yes" | "true" => Ok("on".to_string()),
+                "off" | "no" | "false" => Ok("off".to_string()),
+                _ => Err("Please give `on` or `off`.".to_string()),
+            }
+        }
+        Setting::DisabledCommands => {
+            let mut commands = Vec::new();
+
+            for command in value.split_whitespace() {
+                match find_command(command) {
+                    Some(command) => {
+                        if !commands.contains(&command) {
+                            commands.push(command);
+                        }
+                    }
+                    None => return Err(format!("`{}` can't be disabled.", command)),
+                }
+            }
+
+            Ok(commands.join(" "))
+        }
+        Setting::Prefix => {
+            match value.as_ref() {
+                "none" | "off" => Ok(String::new()),
+                // Mentions begin with '<', so a prefix that did too could be mistaken for one
+                _ if value.is_empty() || value.starts_with('<') ||
+                     value.contains(char::is_whitespace) => {
+                    Err("Please give a prefix without spaces, e.g. `!es`, or `none`.".to_string())
+                }
+                _ if value.chars().count() > MAX_PREFIX_LENGTH => {
+                    Err(format!("Please give a prefix of up to {} characters.",
+                                MAX_PREFIX_LENGTH))
+                }
+                _ => Ok(value),
+            }
+        }
+    }
+}
+
+// Returns the name of the command that the text is the name or an alias of
+fn find_command(command: &str) -> Option<&'static str> {
+    let command = command.to_lowercase();
+
+    COMMANDS
+        .iter()
+        .find(|&&(name, aliases)| name == command || aliases.contains(&command.as_str()))
+        .map(|&(name, _)| name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ServerSettings, Setting};
+    use emojistats::Period;
+
+    #[test]
+    fn setting_names() {
+        for setting in Setting::all() {
+            assert_eq!(Setting::from_name(setting.name()), Some(*setting));
+        }
+
+        assert_eq!(Setting::from_name("PageSize"), Some(Setting::PageSize));
+        assert_eq!(Setting::from_name("colour"), None);
+    }
+
+    #[test]
+    fn defaults() {
+        let settings = ServerSettings::default();
+
+        assert_eq!(settings.page_size(), None);
+        assert_eq!(settings.period(), None);
+        assert_eq!(settings.show_user_leaderboards(), true);
+        assert_eq!(settings.is_command_disabled("global"), false);
+        assert_eq!(settings.prefix(), None);
+    }
+
+    #[test]
+    fn page_size() {
+        let mut settings = ServerSettings::default();
+
+        assert_eq!(settings.set(Setting::PageSize, " 10 ", 15), Ok("10".to_string()));
+        assert_eq!(settings.page_size(), Some(10));
+        assert!(settings.set(Setting::PageSize, "0", 15).is_err());
+        assert!(settings.set(Setting::PageSize, "16", 15).is_err());
+        assert!(settings.set(Setting::PageSize, "ten", 15).is_err());
+        assert_eq!(settings.page_size(), Some(10));
+
+        settings.reset(Setting::PageSize);
+        assert_eq!(settings.page_size(), None);
+    }
+
+    #[test]
+    fn period() {
+        let mut settings = ServerSettings::default();
+
+        assert_eq!(settings.set(Setting::Period, "Month", 15), Ok("month".to_string()));
+        assert_eq!(settings.period(), Some(Period::Days(30)));
+        assert!(settings.set(Setting::Period, "fortnight", 15).is_err());
+    }
+
+    #[test]
+    fn user_leaderboards() {
+        let mut settings = ServerSettings::default();
+
+        assert_eq!(settings.set(Setting::UserLeaderboards, "OFF", 15),
+                   Ok("off".to_string()));
+        assert_eq!(settings.show_user_leaderboards(), false);
+        assert_eq!(settings.set(Setting::UserLeaderboards, "yes", 15),
+                   Ok("on".to_string()));
+        assert_eq!(settings.show_user_leaderboards(), true);
+        assert!(settings
+                    .set(Setting::UserLeaderboards, "maybe", 15)
+                    .is_err());
+    }
+
+    #[test]
+    fn disabled_commands() {
+        let mut settings = ServerSettings::default();
+
+        assert_eq!(settings.set(Setting::DisabledCommands, "g trend VS global", 15),
+                   Ok("global trend compare".to_string()));
+        assert_eq!(settings.is_command_disabled("global"), true);
+        assert_eq!(settings.is_command_disabled("G"), true);
+        assert_eq!(settings.is_command_disabled("t"), true);
+        assert_eq!(settings.is_command_disabled("compare"), true);
+        assert_eq!(settings.is_command_disabled("server"), false);
+        assert_eq!(settings.is_command_disabled("settings"), false);
+        assert!(settings
+                    .set(Setting::DisabledCommands, "settings", 15)
+                    .is_err());
+    }
+
+    #[test]
+    fn prefix() {
+        let mut settings = ServerSettings::default();
+
+        assert_eq!(settings.set(Setting::Prefix, " !ES ", 15), Ok("!es".to_string()));
+        assert_eq!(settings.prefix(), Some("!es"));
+        assert!(settings.set(Setting::Prefix, "! es", 15).is_err());
+        assert!(settings.set(Setting::Prefix, "<@1>", 15).is_err());
+        assert!(settings
+                    .set(Setting::Prefix, "!emojistats", 15)
+                    .is_err());
+        assert_eq!(settings.prefix(), Some("!es"));
+        assert_eq!(settings.set(Setting::Prefix, "none", 15), Ok("".to_string()));
+        assert_eq!(settings.prefix(), Some(""));
+    }
+}